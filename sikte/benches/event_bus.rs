@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Throughput of the event bus when events are sent one by one or in batches, compared with
+//! a `tokio::sync::broadcast` channel that every subscriber clones each event out of.
+//!
+//! Run with `cargo bench --bench event_bus`.
+use std::time::{Duration, Instant};
+
+use sikte::{
+    common::generated_types::SyscallData,
+    events::{Backpressure, BusConfig, DEFAULT_BUS_CAPACITY, Event, EventBatch, EventBus},
+    subscribers::EventSubscriber,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events published in every run
+const EVENTS: u64 = 1_000_000;
+/// Events per batch, about what a busy ring buffer poll returns
+const BATCH_SIZE: u64 = 256;
+/// Subscribers reading every event, like a shell printer, a SQLite writer and metrics
+const SUBSCRIBERS: usize = 3;
+
+/// Subscriber that only looks at the events
+struct NullSubscriber {
+    sum: u64,
+}
+
+impl EventSubscriber for NullSubscriber {
+    fn get_name(&self) -> &str {
+        "Null"
+    }
+
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        self.sum = self.sum.wrapping_add(syscall_data.timestamp);
+    }
+}
+
+fn event(timestamp: u64) -> Event {
+    Event::Syscall(SyscallData {
+        timestamp,
+        ..Default::default()
+    })
+}
+
+/// Publish every event to a bus that never drops them, and measure how long it takes for all
+/// subscribers to read them
+async fn run(batch_size: u64) -> Duration {
+    let mut bus = EventBus::with_config(BusConfig {
+        backpressure: Backpressure::Block,
+        ..BusConfig::default()
+    });
+    for _ in 0..SUBSCRIBERS {
+        bus.spawn_subscription(NullSubscriber { sum: 0 });
+    }
+    let tx = bus.tx();
+
+    let start = Instant::now();
+    for first in (0..EVENTS).step_by(batch_size as usize) {
+        if batch_size == 1 {
+            tx.send(event(first)).await;
+        } else {
+            let batch: EventBatch = (first..(first + batch_size).min(EVENTS))
+                .map(event)
+                .collect();
+            tx.send_batch(batch).await;
+        }
+    }
+    bus.shutdown(Duration::from_secs(60)).await;
+    start.elapsed()
+}
+
+/// Publish every event one by one to a broadcast channel, like the event bus did before batches,
+/// and measure how long it takes for all subscribers to read them. A broadcast channel drops
+/// events rather than wait, so the publisher yields while it's full. Returns the number of events
+/// the subscribers missed anyway
+async fn run_broadcast() -> (Duration, u64) {
+    let (tx, _) = broadcast::channel(DEFAULT_BUS_CAPACITY);
+    let subscribers: Vec<_> = (0..SUBSCRIBERS)
+        .map(|_| {
+            let mut rx = tx.subscribe();
+            tokio::spawn(async move {
+                let mut subscriber = NullSubscriber { sum: 0 };
+                let mut lost = 0;
+                loop {
+                    // Every receiver gets its own clone of the event
+                    match rx.recv().await {
+                        Ok(Event::Syscall(syscall_data)) => subscriber.read_syscall(&syscall_data),
+                        Ok(Event::Lost(_)) => {}
+                        Err(RecvError::Lagged(n)) => lost += n,
+                        Err(RecvError::Closed) => return lost,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    for timestamp in 0..EVENTS {
+        while tx.len() >= DEFAULT_BUS_CAPACITY {
+            tokio::task::yield_now().await;
+        }
+        let _ = tx.send(event(timestamp));
+    }
+    drop(tx);
+    let mut lost = 0;
+    for subscriber in subscribers {
+        lost += subscriber.await.expect("subscriber panicked");
+    }
+    (start.elapsed(), lost)
+}
+
+fn report(name: &str, elapsed: Duration, lost: u64) {
+    // Events each subscriber read per second
+    let read = EVENTS - lost / SUBSCRIBERS as u64;
+    println!(
+        "{name:<14} {:>12.0} events/s ({EVENTS} events, {SUBSCRIBERS} subscribers, {lost} lost, \
+         {elapsed:?})",
+        read as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the tokio runtime");
+
+    // warm up the allocator and the worker threads
+    runtime.block_on(run_broadcast());
+    let (elapsed, lost) = runtime.block_on(run_broadcast());
+    report("broadcast", elapsed, lost);
+
+    for (name, batch_size) in [("single events", 1), ("batches", BATCH_SIZE)] {
+        runtime.block_on(run(batch_size));
+        let elapsed = runtime.block_on(run(batch_size));
+        report(name, elapsed, 0);
+    }
+}
@@ -7,8 +7,14 @@
 
 char LICENSE[] SEC("license") = "GPL";
 
-// Dummy instance to force skeleton to generate Rust type definitions
+// Dummy instances to force skeleton to generate Rust type definitions
 struct syscall_data _syscall_data = {0};
+struct trace_config _trace_config = {0};
+struct comm _comm = {0};
+
+// Set by user space before loading: write events to PERF_SYSCALL_EVENTS instead of
+// SYSCALL_EVENTS, on kernels without BPF ring buffers (before 5.8)
+const volatile bool use_perf_buffer = false;
 
 // Ring buffer for syscall events (kernel -> userspace)
 struct {
@@ -16,6 +22,14 @@ struct {
     __uint(max_entries, MAX_SYSCALL_EVENTS);
 } SYSCALL_EVENTS SEC(".maps");
 
+// Per-CPU perf buffers for syscall events, used when the ring buffer isn't supported.
+// libbpf sizes it to the number of CPUs
+struct {
+    __uint(type, BPF_MAP_TYPE_PERF_EVENT_ARRAY);
+    __uint(key_size, sizeof(__u32));
+    __uint(value_size, sizeof(__u32));
+} PERF_SYSCALL_EVENTS SEC(".maps");
+
 // PID allow list (hash map used as a set)
 struct {
     __uint(type, BPF_MAP_TYPE_HASH);
@@ -24,92 +38,330 @@ struct {
     __type(value, __u8);  // Value unused, only key matters
 } PID_ALLOW_LIST SEC(".maps");
 
+// TID allow list, consulted alongside PID_ALLOW_LIST (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_ALLOWED_TIDS);
+    __type(key, pid_t);
+    __type(value, __u8);  // Value unused, only key matters
+} TID_ALLOW_LIST SEC(".maps");
+
+// UID allow list (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_ALLOWED_USERS);
+    __type(key, __u32);
+    __type(value, __u8);  // Value unused, only key matters
+} UID_ALLOW_LIST SEC(".maps");
+
+// GID allow list (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_ALLOWED_USERS);
+    __type(key, __u32);
+    __type(value, __u8);  // Value unused, only key matters
+} GID_ALLOW_LIST SEC(".maps");
+
+// Cgroup v2 traced with its descendants, set by user space from a file descriptor of its
+// directory. Single entry at index 0
+struct {
+    __uint(type, BPF_MAP_TYPE_CGROUP_ARRAY);
+    __uint(max_entries, 1);
+    __uint(key_size, sizeof(__u32));
+    __uint(value_size, sizeof(__u32));
+} TRACED_CGROUP SEC(".maps");
+
+// Which processes are traced. Single entry at index 0
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct trace_config);
+} TRACE_CONFIG SEC(".maps");
+
+// PID deny list when tracing every process (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_DENIED_PIDS);
+    __type(key, pid_t);
+    __type(value, __u8);  // Value unused, only key matters
+} PID_DENY_LIST SEC(".maps");
+
+// Command name deny list when tracing every process (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_DENIED_COMMS);
+    __type(key, struct comm);
+    __type(value, __u8);  // Value unused, only key matters
+} COMM_DENY_LIST SEC(".maps");
+
+// Processes are added to PID_ALLOW_LIST when they execute one of these paths, as passed to
+// execve (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_EXEC_PATTERNS);
+    __type(key, struct exec_path);
+    __type(value, __u8);  // Value unused, only key matters
+} EXEC_PATH_LIST SEC(".maps");
+
+// Processes are added to PID_ALLOW_LIST when they execute a file with one of these command
+// names (hash map used as a set)
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, NUM_EXEC_PATTERNS);
+    __type(key, struct comm);
+    __type(value, __u8);  // Value unused, only key matters
+} EXEC_COMM_LIST SEC(".maps");
+
+// Per-CPU counters of events dropped because the ring buffer was full, indexed by
+// enum dropped_event_kind
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, NUM_DROPPED_EVENT_KINDS);
+    __type(key, __u32);
+    __type(value, __u64);
+} DROPPED_EVENTS SEC(".maps");
+
 // Check if a TGID is in the allow list
 static __always_inline bool is_tgid_in_allowlist(pid_t tgid) {
     return bpf_map_lookup_elem(&PID_ALLOW_LIST, &tgid) != NULL;
 }
 
-// Raw tracepoint handler for sys_enter
-// https://elixir.bootlin.com/linux/v6.16/source/include/trace/events/syscalls.h#L20
-SEC("raw_tp/sys_enter")
-int sikte_raw_trace_point_at_enter(struct bpf_raw_tracepoint_args* ctx) {
+// Check if the current process is in a deny list, by TGID or by the command name of its main
+// thread, which its other threads may have renamed themselves from
+static __always_inline bool is_current_denied(pid_t tgid) {
+    if (bpf_map_lookup_elem(&PID_DENY_LIST, &tgid) != NULL) {
+        return true;
+    }
+
+    // Zeroed, so that the name is padded with NULs like the keys
+    struct comm comm = {0};
+    struct task_struct* task = (struct task_struct*)bpf_get_current_task();
+    BPF_CORE_READ_STR_INTO(&comm.name, task, group_leader, comm);
+    return bpf_map_lookup_elem(&COMM_DENY_LIST, &comm) != NULL;
+}
+
+// Check if the current thread has to be traced
+static __always_inline bool is_current_traced(__u64 pid_tgid) {
+    pid_t tgid = pid_tgid >> 32;
+    pid_t pid = (pid_t)pid_tgid;
+
+    __u32 index = 0;
+    struct trace_config* config = bpf_map_lookup_elem(&TRACE_CONFIG, &index);
+    if (config && config->trace_all) {
+        return !is_current_denied(tgid);
+    }
+    if (config && config->trace_cgroups) {
+        // Processes move between cgroups, and cgroups are created below the traced one, e.g. by
+        // a container runtime, so they are checked on every event
+        return bpf_current_task_under_cgroup(&TRACED_CGROUP, 0) == 1;
+    }
+    if (config && config->trace_users) {
+        // Real IDs, as seen from the initial user namespace
+        __u64 gid_uid = bpf_get_current_uid_gid();
+        __u32 uid = (__u32)gid_uid;
+        __u32 gid = gid_uid >> 32;
+        return bpf_map_lookup_elem(&UID_ALLOW_LIST, &uid) != NULL ||
+               bpf_map_lookup_elem(&GID_ALLOW_LIST, &gid) != NULL;
+    }
+    return is_tgid_in_allowlist(tgid) || bpf_map_lookup_elem(&TID_ALLOW_LIST, &pid) != NULL;
+}
+
+// Fill in the IDs of the current thread and process in their PID namespace, which is the last
+// level of their struct pid, and the start time of the process
+static __always_inline void read_ns_ids(struct syscall_data* data) {
+    struct task_struct* task = (struct task_struct*)bpf_get_current_task();
+    struct pid* thread_pid = BPF_CORE_READ(task, thread_pid);
+    unsigned int level = BPF_CORE_READ(thread_pid, level);
+    data->ns_pid = BPF_CORE_READ(thread_pid, numbers[level].nr);
+    data->pid_ns = BPF_CORE_READ(thread_pid, numbers[level].ns, ns.inum);
+
+    // Threads of a process are always in the same PID namespace
+    struct pid* leader_pid = BPF_CORE_READ(task, group_leader, thread_pid);
+    data->ns_tgid = BPF_CORE_READ(leader_pid, numbers[level].nr);
+    data->process_start = BPF_CORE_READ(task, group_leader, start_time);
+}
+
+// Fill in the current command names of the thread and of its process
+static __always_inline void read_comms(struct syscall_data* data) {
+    bpf_get_current_comm(&data->comm.name, sizeof(data->comm.name));
+    struct task_struct* task = (struct task_struct*)bpf_get_current_task();
+    BPF_CORE_READ_STR_INTO(&data->process_comm.name, task, group_leader, comm);
+}
+
+// Count an event that could not be written to the ring buffer
+static __always_inline void count_dropped_event(__u32 kind) {
+    __u64* count = bpf_map_lookup_elem(&DROPPED_EVENTS, &kind);
+    if (count) {
+        // Per-CPU value, so no atomic operation is needed
+        (*count)++;
+    }
+}
+
+// Send an event to user space, through whichever buffer the kernel supports
+static __always_inline void submit_event(void* ctx, struct syscall_data* data, __u32 dropped_kind) {
+    long err;
+    if (use_perf_buffer) {
+        err = bpf_perf_event_output(ctx, &PERF_SYSCALL_EVENTS, BPF_F_CURRENT_CPU, data,
+                                    sizeof(*data));
+    } else {
+        err = bpf_ringbuf_output(&SYSCALL_EVENTS, data, sizeof(*data), 0);
+    }
+    if (err) {
+        // Buffer full - drop event, but let user space know
+        count_dropped_event(dropped_kind);
+    }
+}
+
+// Common handler of both sys_enter programs
+static __always_inline int handle_sys_enter(void* ctx, __s64 syscall_id) {
     // Get current process/thread IDs
     __u64 pid_tgid = bpf_get_current_pid_tgid();
     pid_t tgid = pid_tgid >> 32;  // TGID = userspace PID
 
-    // Filter: only trace allowed PIDs
-    if (!is_tgid_in_allowlist(tgid)) {
+    // Filter: only trace the selected threads, see is_current_traced
+    if (!is_current_traced(pid_tgid)) {
         return 0;
     }
 
     // PID in kernel = TID in userspace
-    pid_t pid = (pid_t)((pid_tgid << 32) >> 32);
-    __u64 timestamp = bpf_ktime_get_ns();
+    struct syscall_data data = {
+        .timestamp = bpf_ktime_get_ns(),
+        .tgid = tgid,
+        .pid = (pid_t)((pid_tgid << 32) >> 32),
+        .state.tag = SYSCALL_STATE_AT_ENTER,
+        .state.data.at_enter.syscall_id = syscall_id,
+    };
+    read_ns_ids(&data);
+    read_comms(&data);
+    submit_event(ctx, &data, DROPPED_AT_ENTER);
+    return 0;
+}
 
-    // Extract syscall ID from tracepoint context
-    // ctx->args[0] is struct pt_regs*
-    // ctx->args[1] is the syscall ID (long)
-    __s64 syscall_id = (long)ctx->args[1];
-
-    // Reserve space in ring buffer
-    struct syscall_data* data =
-        bpf_ringbuf_reserve(&SYSCALL_EVENTS, sizeof(struct syscall_data), 0);
-    if (!data) {
-        // Ring buffer full - drop event
-        // Could use bpf_printk for debugging: bpf_printk("Dropped sys_enter:
-        // tgid=%d\n", tgid);
+// Common handler of both sys_exit programs
+static __always_inline int handle_sys_exit(void* ctx, __s64 syscall_ret) {
+    // Get current process/thread IDs
+    __u64 pid_tgid = bpf_get_current_pid_tgid();
+    pid_t tgid = pid_tgid >> 32;
+
+    // Filter: only trace the selected threads, see is_current_traced
+    if (!is_current_traced(pid_tgid)) {
         return 0;
     }
 
-    // Populate syscall data
-    data->timestamp = timestamp;
-    data->tgid = tgid;
-    data->pid = pid;
-    data->state.tag = SYSCALL_STATE_AT_ENTER;
-    data->state.data.at_enter.syscall_id = syscall_id;
-
-    // Submit to ring buffer
-    bpf_ringbuf_submit(data, 0);
+    struct syscall_data data = {
+        .timestamp = bpf_ktime_get_ns(),
+        .tgid = tgid,
+        .pid = (pid_t)((pid_tgid << 32) >> 32),
+        .state.tag = SYSCALL_STATE_AT_EXIT,
+        .state.data.at_exit.syscall_ret = syscall_ret,
+    };
+    read_ns_ids(&data);
+    read_comms(&data);
+    submit_event(ctx, &data, DROPPED_AT_EXIT);
     return 0;
 }
 
+// Raw tracepoint handler for sys_enter
+// https://elixir.bootlin.com/linux/v6.16/source/include/trace/events/syscalls.h#L20
+SEC("raw_tp/sys_enter")
+int sikte_raw_trace_point_at_enter(struct bpf_raw_tracepoint_args* ctx) {
+    // ctx->args[0] is struct pt_regs*
+    // ctx->args[1] is the syscall ID (long)
+    return handle_sys_enter(ctx, (long)ctx->args[1]);
+}
+
 // Raw tracepoint handler for sys_exit
 // https://elixir.bootlin.com/linux/v6.16/source/include/trace/events/syscalls.h#L46
 SEC("raw_tp/sys_exit")
 int sikte_raw_trace_point_at_exit(struct bpf_raw_tracepoint_args* ctx) {
-    // Get current process/thread IDs
-    __u64 pid_tgid = bpf_get_current_pid_tgid();
-    pid_t tgid = pid_tgid >> 32;
+    // ctx->args[0] is struct pt_regs*
+    // ctx->args[1] is the return value (long)
+    return handle_sys_exit(ctx, (long)ctx->args[1]);
+}
 
-    // Filter: only trace allowed PIDs
-    if (!is_tgid_in_allowlist(tgid)) {
-        return 0;
-    }
+// Classic tracepoint handler for sys_enter, used when raw tracepoints aren't supported
+// See /sys/kernel/tracing/events/raw_syscalls/sys_enter/format
+SEC("tracepoint/raw_syscalls/sys_enter")
+int sikte_trace_point_at_enter(struct trace_event_raw_sys_enter* ctx) {
+    return handle_sys_enter(ctx, ctx->id);
+}
 
-    pid_t pid = (pid_t)((pid_tgid << 32) >> 32);
-    __u64 timestamp = bpf_ktime_get_ns();
+// Classic tracepoint handler for sys_exit, used when raw tracepoints aren't supported
+// See /sys/kernel/tracing/events/raw_syscalls/sys_exit/format
+SEC("tracepoint/raw_syscalls/sys_exit")
+int sikte_trace_point_at_exit(struct trace_event_raw_sys_exit* ctx) {
+    return handle_sys_exit(ctx, ctx->ret);
+}
 
-    // Extract syscall return value from tracepoint context
-    // ctx->args[0] is struct pt_regs*
-    // ctx->args[1] is the return value (long)
-    __s64 syscall_ret = (long)ctx->args[1];
+// Start tracing the current process if it executed a file in EXEC_PATH_LIST or EXEC_COMM_LIST,
+// so that even short-lived ones are traced from their first syscall after exec
+static __always_inline int handle_exec(struct exec_path* path) {
+    pid_t tgid = bpf_get_current_pid_tgid() >> 32;
 
-    // Reserve space in ring buffer
-    struct syscall_data* data =
-        bpf_ringbuf_reserve(&SYSCALL_EVENTS, sizeof(struct syscall_data), 0);
-    if (!data) {
-        // Ring buffer full - drop event
-        return 0;
+    // The command name was already set to the file name of the new executable. Zeroed, so that
+    // it is padded with NULs like the keys
+    struct comm comm = {0};
+    bpf_get_current_comm(&comm.name, sizeof(comm.name));
+
+    if (bpf_map_lookup_elem(&EXEC_PATH_LIST, path) != NULL ||
+        bpf_map_lookup_elem(&EXEC_COMM_LIST, &comm) != NULL) {
+        __u8 unused = 0;
+        bpf_map_update_elem(&PID_ALLOW_LIST, &tgid, &unused, BPF_ANY);
     }
+    return 0;
+}
 
-    // Populate syscall data
-    data->timestamp = timestamp;
-    data->tgid = tgid;
-    data->pid = pid;
-    data->state.tag = SYSCALL_STATE_AT_EXIT;
-    data->state.data.at_exit.syscall_ret = syscall_ret;
+// Stop tracing the current thread when it exits, and its process when its last thread exits, so
+// that the allow lists don't fill up with short-lived ones and a reused ID isn't traced by mistake
+static __always_inline int handle_process_exit(void) {
+    __u64 pid_tgid = bpf_get_current_pid_tgid();
+    pid_t tgid = pid_tgid >> 32;
 
-    // Submit to ring buffer
-    bpf_ringbuf_submit(data, 0);
+    // The main thread may exit first, or be replaced when another thread calls execve, while the
+    // process keeps running. The exiting thread was already removed from the live ones
+    struct task_struct* task = (struct task_struct*)bpf_get_current_task();
+    if (BPF_CORE_READ(task, signal, live.counter) == 0) {
+        bpf_map_delete_elem(&PID_ALLOW_LIST, &tgid);
+    }
+    // Threads exit one by one
+    pid_t pid = (pid_t)pid_tgid;
+    bpf_map_delete_elem(&TID_ALLOW_LIST, &pid);
     return 0;
 }
+
+// Raw tracepoint handler for sched_process_exec
+// https://elixir.bootlin.com/linux/v6.16/source/include/trace/events/sched.h#L430
+SEC("raw_tp/sched_process_exec")
+int sikte_raw_trace_point_at_exec(struct bpf_raw_tracepoint_args* ctx) {
+    // ctx->args[2] is struct linux_binprm*, whose filename is the path passed to execve
+    struct linux_binprm* bprm = (struct linux_binprm*)ctx->args[2];
+    // Zeroed, so that the path is padded with NULs like the keys
+    struct exec_path path = {0};
+    BPF_CORE_READ_STR_INTO(&path.name, bprm, filename);
+    return handle_exec(&path);
+}
+
+// Raw tracepoint handler for sched_process_exit
+SEC("raw_tp/sched_process_exit")
+int sikte_raw_trace_point_at_process_exit(struct bpf_raw_tracepoint_args* ctx) {
+    return handle_process_exit();
+}
+
+// Classic tracepoint handler for sched_process_exec, used when raw tracepoints aren't supported
+// See /sys/kernel/tracing/events/sched/sched_process_exec/format
+SEC("tracepoint/sched/sched_process_exec")
+int sikte_trace_point_at_exec(struct trace_event_raw_sched_process_exec* ctx) {
+    struct exec_path path = {0};
+    // The filename is stored after the fixed fields, at the offset in the low 16 bits
+    unsigned int offset = ctx->__data_loc_filename & 0xFFFF;
+    bpf_probe_read_kernel_str(&path.name, sizeof(path.name), (void*)ctx + offset);
+    return handle_exec(&path);
+}
+
+// Classic tracepoint handler for sched_process_exit, used when raw tracepoints aren't supported
+// See /sys/kernel/tracing/events/sched/sched_process_exit/format
+SEC("tracepoint/sched/sched_process_exit")
+int sikte_trace_point_at_process_exit(void* ctx) {
+    return handle_process_exit();
+}
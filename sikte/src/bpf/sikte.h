@@ -4,9 +4,50 @@
 
 #include "vmlinux.h"
 
-// Ring buffer and map size constants
+// Default ring buffer and map sizes. User space may resize the maps before loading them
 #define MAX_SYSCALL_EVENTS (1 << 20)  // 1MB ring buffer
 #define NUM_ALLOWED_PIDS (1 << 10)    // 1024 PIDs
+#define NUM_DENIED_PIDS (1 << 10)     // 1024 PIDs
+#define NUM_DENIED_COMMS (1 << 6)     // 64 command names
+#define NUM_EXEC_PATTERNS (1 << 6)    // 64 executable paths or command names
+#define NUM_ALLOWED_TIDS (1 << 10)    // 1024 threads
+#define NUM_ALLOWED_USERS (1 << 6)    // 64 UIDs and 64 GIDs
+
+// Length of task command names, including the trailing NUL (TASK_COMM_LEN in the kernel)
+#define COMM_LEN 16
+// Length of the executable paths compared on exec, including the trailing NUL. Longer ones are
+// truncated
+#define EXEC_PATH_LEN 256
+
+// Index into the DROPPED_EVENTS counters
+enum dropped_event_kind {
+    DROPPED_AT_ENTER = 0,
+    DROPPED_AT_EXIT = 1,
+    NUM_DROPPED_EVENT_KINDS = 2,
+};
+
+// Which processes are traced, set by user space in TRACE_CONFIG
+struct trace_config {
+    // Trace every process except those in PID_DENY_LIST and COMM_DENY_LIST, instead of those in
+    // PID_ALLOW_LIST
+    __u32 trace_all;
+    // Trace the processes in the cgroup of TRACED_CGROUP or its descendants, instead of those in
+    // PID_ALLOW_LIST
+    __u32 trace_cgroups;
+    // Trace the processes whose user is in UID_ALLOW_LIST or whose group is in GID_ALLOW_LIST,
+    // instead of those in PID_ALLOW_LIST
+    __u32 trace_users;
+};
+
+// Key of COMM_DENY_LIST: a command name padded with NULs
+struct comm {
+    char name[COMM_LEN];
+};
+
+// Key of EXEC_PATH_LIST: an executable path padded with NULs
+struct exec_path {
+    char name[EXEC_PATH_LEN];
+};
 
 // Syscall state discriminant
 enum syscall_state_tag {
@@ -40,6 +81,19 @@ struct syscall_data {
     __u64 timestamp;
     pid_t tgid;
     pid_t pid;
+    // IDs in the PID namespace of the thread, e.g. of its container. Equal to tgid and pid in the
+    // initial namespace
+    pid_t ns_tgid;
+    pid_t ns_pid;
+    // Inode number of that PID namespace, as in /proc/<pid>/ns/pid
+    __u32 pid_ns;
+    __u32 _padding;  // Explicit padding for 8-byte alignment
+    // Start time of the process in ns since boot, which tells apart processes reusing a PID
+    __u64 process_start;
+    // Command names of the thread and of its process (main thread). Threads of a pool are often
+    // named with prctl(PR_SET_NAME), and both change on exec
+    struct comm comm;
+    struct comm process_comm;
     struct syscall_state state;
 } __attribute__((aligned(8)));
 
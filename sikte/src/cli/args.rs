@@ -1,11 +1,29 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
-use clap::{Args, Parser, Subcommand};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use log::debug;
+use regex::Regex;
+
+use crate::{
+    common::{
+        constants::map_sizes::DEFAULT_MAX_PIDS, http::HttpUrl, process_selector::ProcessSelector,
+        users,
+    },
+    ebpf::EbpfConfig,
+    events::{Backpressure, BusConfig, DEFAULT_BUS_CAPACITY, DEFAULT_MAX_SPILL_SIZE},
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "sikte")]
 #[command(about = "A tracing tool for syscalls")]
 pub struct Cli {
+    #[command(flatten)]
+    pub ebpf: EbpfArgs,
+
+    #[command(flatten)]
+    pub bus: BusArgs,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -19,37 +37,372 @@ impl Cli {
     }
 }
 
+/// Sizes of the eBPF maps, common to every command
+#[derive(Debug, Args)]
+pub struct EbpfArgs {
+    /// Size of the ring buffer the kernel writes events to, e.g. `64M`. It must be a power of two
+    /// and a multiple of the page size
+    #[arg(
+        long,
+        global = true,
+        value_name = "BYTES",
+        value_parser = parse_ring_buffer_size,
+        default_value = "1M"
+    )]
+    pub ring_buffer_size: u32,
+
+    /// Maximum number of processes that can be traced at once
+    #[arg(
+        long,
+        global = true,
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = DEFAULT_MAX_PIDS
+    )]
+    pub max_pids: u32,
+}
+
+impl EbpfArgs {
+    /// Convert to the settings used when loading eBPF
+    pub fn to_config(&self) -> EbpfConfig {
+        EbpfConfig {
+            ring_buffer_size: self.ring_buffer_size,
+            max_pids: self.max_pids,
+            features: None,
+        }
+    }
+}
+
+/// Delivery of events to the subscribers, common to every command
+#[derive(Debug, Args)]
+pub struct BusArgs {
+    /// What to do with events a subscriber is too slow to read. Subscribers writing trace files
+    /// spill events instead of dropping them
+    #[arg(long, global = true, value_enum, default_value_t = Backpressure::Drop)]
+    pub backpressure: Backpressure,
+
+    /// Maximum size of the temporary file each spilling subscriber writes to, e.g. `512M`.
+    /// Events that don't fit are dropped
+    #[arg(
+        long,
+        global = true,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value_t = DEFAULT_MAX_SPILL_SIZE
+    )]
+    pub max_spill_size: u64,
+
+    /// Number of events each subscriber can have waiting in memory
+    #[arg(
+        long,
+        global = true,
+        value_name = "EVENTS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value_t = DEFAULT_BUS_CAPACITY as u64
+    )]
+    pub bus_capacity: u64,
+}
+
+impl BusArgs {
+    /// Convert to the configuration of the event bus
+    pub fn to_config(&self) -> BusConfig {
+        BusConfig {
+            capacity: self.bus_capacity as usize,
+            backpressure: self.backpressure,
+            max_spill_size: self.max_spill_size,
+        }
+    }
+}
+
+/// Parse a size in bytes with an optional binary suffix, e.g. `4096`, `64K` or `1MiB`
+fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size '{s}'"))?;
+
+    let suffix = suffix.to_ascii_uppercase();
+    let multiplier: u64 = match suffix.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("unknown size suffix in '{s}' (supported: K, M, G)")),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{s}' is too large"))
+}
+
+fn parse_ring_buffer_size(s: &str) -> Result<u32, String> {
+    EbpfConfig::check_ring_buffer_size(parse_size(s)?).map_err(|e| e.to_string())
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Record traces from processes
     Record(RecordArgs),
+    /// Show a live view of the busiest syscalls and threads
+    Top(TopArgs),
+    /// Check whether this system can run sikte
+    Doctor(DoctorArgs),
 }
 
 #[derive(Debug, Args)]
 pub struct RecordArgs {
     #[command(flatten)]
     pub target: TargetArgs,
+
+    /// Additional outputs for the trace, e.g. `sqlite:trace.db` or `otlp:http://localhost:4318`
+    /// (can be repeated)
+    #[arg(long, value_name = "FORMAT:PATH")]
+    pub output: Vec<OutputSpec>,
+
+    /// Only export syscalls to OTLP that took at least this long, e.g. `500us` or `2ms`
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    pub otlp_min_latency: Duration,
+
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9898`
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Instead of every syscall, print syscall rates, errors and latency percentiles every
+    /// interval, e.g. `1s`
+    #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+    pub interval: Option<Duration>,
+
+    /// Format of the per-interval reports
+    #[arg(long, value_enum, default_value_t = IntervalFormat::Text, requires = "interval")]
+    pub interval_format: IntervalFormat,
+}
+
+/// How per-interval reports are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IntervalFormat {
+    /// A table per interval
+    Text,
+    /// A JSON object per line
+    Json,
 }
 
 #[derive(Debug, Args)]
-#[group(required = true, multiple = false)]
+pub struct TopArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    /// Time between screen refreshes, e.g. `1s` or `500ms`
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    pub refresh: Duration,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Print the results as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// What to trace. It takes one argument of the "selection" group, except that --tid may be given
+/// with --pid, and --uid with --gid: their "filter" group allows several. The "target" group
+/// lists every argument itself, since groups can't contain groups, and arguments naming their
+/// group are left out of the struct's
+#[derive(Debug, Args)]
+#[group(skip)]
+#[command(group = ArgGroup::new("filter").multiple(true))]
+#[command(group = ArgGroup::new("target").required(true).multiple(true).args([
+    "pid", "tid", "uid", "gid", "command", "comm", "exe", "pgrep", "on_exec", "cgroup", "container",
+    "all"
+]))]
 pub struct TargetArgs {
-    /// Process IDs to trace (comma-separated)
-    #[arg(long, value_delimiter = ',', num_args = 1.., group = "target")]
+    /// Process IDs to trace, in the initial PID namespace unless --pid-ns is given
+    /// (comma-separated)
+    #[arg(long, value_delimiter = ',', num_args = 1.., group = "selection")]
     pub pid: Option<Vec<i32>>,
 
+    /// Thread IDs to trace, without the other threads of their processes, besides the --pid
+    /// processes (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 1..,
+        group = "filter",
+        conflicts_with_all = [
+            "uid", "gid", "command", "comm", "exe", "pgrep", "on_exec", "cgroup", "container", "all"
+        ]
+    )]
+    pub tid: Option<Vec<i32>>,
+
+    /// Trace the processes of these users, by name or real user ID, and those of the --gid
+    /// groups (comma-separated)
+    #[arg(
+        long,
+        value_name = "USER",
+        value_delimiter = ',',
+        num_args = 1..,
+        value_parser = parse_uid,
+        group = "filter",
+        conflicts_with = "selection"
+    )]
+    pub uid: Option<Vec<u32>>,
+
+    /// Trace the processes of these groups, by name or real group ID (comma-separated)
+    #[arg(
+        long,
+        value_name = "GROUP",
+        value_delimiter = ',',
+        num_args = 1..,
+        value_parser = parse_gid,
+        group = "filter",
+        conflicts_with = "selection"
+    )]
+    pub gid: Option<Vec<u32>>,
+
     /// Command to execute and trace
-    #[arg(long, num_args = 1.., group = "target")]
+    #[arg(long, num_args = 1.., group = "selection")]
     pub command: Option<Vec<String>>,
+
+    /// Trace the processes with this command name, as shown by `ps -o comm`
+    #[arg(long, value_name = "NAME", group = "selection")]
+    pub comm: Option<String>,
+
+    /// Trace the processes running this executable
+    #[arg(long, value_name = "PATH", group = "selection")]
+    pub exe: Option<PathBuf>,
+
+    /// Trace the processes whose command line matches this regular expression, like `pgrep -f`
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new, group = "selection")]
+    pub pgrep: Option<Regex>,
+
+    /// Trace the processes that execute one of these files from then on, given by path as
+    /// passed to execve, e.g. `/usr/bin/git`, or by command name, e.g. `git` (comma-separated)
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        value_delimiter = ',',
+        num_args = 1..,
+        value_parser = parse_exec_pattern,
+        group = "selection"
+    )]
+    pub on_exec: Option<Vec<String>>,
+
+    /// Trace the processes in this cgroup v2 directory or its descendants, including those
+    /// created later on, e.g. `/sys/fs/cgroup/system.slice/nginx.service`
+    #[arg(long, value_name = "PATH", group = "selection")]
+    pub cgroup: Option<PathBuf>,
+
+    /// Trace the processes in a Docker, containerd, CRI-O or podman container, given by full or
+    /// short ID
+    #[arg(long, value_name = "ID", group = "selection")]
+    pub container: Option<String>,
+
+    /// Trace every process on the host, except sikte and the processes that started it in its
+    /// session, e.g. sudo and the shell
+    #[arg(long, group = "selection")]
+    pub all: bool,
+
+    #[command(flatten)]
+    pub options: TargetOptions,
+}
+
+/// Options of some targets. They aren't part of the target group, whose selection only allows one
+/// argument, and conflict with the other targets rather than requiring theirs, since a flag's
+/// default value already satisfies `requires`
+#[derive(Debug, Args)]
+#[group(skip)]
+pub struct TargetOptions {
+    /// Keep looking for processes selected by --comm, --exe or --pgrep, and trace those that
+    /// start later too
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "pid", "tid", "uid", "gid", "command", "on_exec", "cgroup", "container", "all"
+        ]
+    )]
+    pub watch: bool,
+
+    /// PID namespace the --pid IDs are in, e.g. a container's: a path such as
+    /// `/proc/1234/ns/pid`, or the ID of a process in that namespace
+    #[arg(
+        long,
+        value_name = "NAMESPACE",
+        value_parser = parse_pid_namespace,
+        conflicts_with_all = [
+            "tid", "uid", "gid", "command", "comm", "exe", "pgrep", "on_exec", "cgroup",
+            "container", "all"
+        ]
+    )]
+    pub pid_ns: Option<PathBuf>,
+
+    /// Process IDs not to trace with --all (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 1..,
+        conflicts_with_all = [
+            "pid", "tid", "uid", "gid", "command", "comm", "exe", "pgrep", "on_exec", "cgroup",
+            "container"
+        ]
+    )]
+    pub exclude_pid: Vec<i32>,
+
+    /// Command names not to trace with --all, as shown by `ps -o comm` (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 1..,
+        conflicts_with_all = [
+            "pid", "tid", "uid", "gid", "command", "comm", "exe", "pgrep", "on_exec", "cgroup",
+            "container"
+        ]
+    )]
+    pub exclude_comm: Vec<String>,
 }
 
 impl TargetArgs {
     /// Convert to a more ergonomic enum representation
     pub fn to_target(&self) -> Target {
-        match (&self.pid, &self.command) {
-            (Some(pids), None) => Target::Pid(pids.clone()),
-            (None, Some(cmd)) => Target::Command(cmd.clone()),
-            _ => unreachable!("clap ensures exactly one target is provided"),
+        let matching = |selector| Target::Matching {
+            selector,
+            watch: self.options.watch,
+        };
+        if let Some(tids) = &self.tid {
+            Target::Tid {
+                tids: tids.clone(),
+                pids: self.pid.clone().unwrap_or_default(),
+            }
+        } else if let Some(pids) = &self.pid {
+            match &self.options.pid_ns {
+                Some(namespace) => Target::NamespacedPid {
+                    pids: pids.clone(),
+                    namespace: namespace.clone(),
+                },
+                None => Target::Pid(pids.clone()),
+            }
+        } else if self.uid.is_some() || self.gid.is_some() {
+            Target::Users {
+                uids: self.uid.clone().unwrap_or_default(),
+                gids: self.gid.clone().unwrap_or_default(),
+            }
+        } else if let Some(cmd) = &self.command {
+            Target::Command(cmd.clone())
+        } else if let Some(name) = &self.comm {
+            matching(ProcessSelector::comm(name))
+        } else if let Some(path) = &self.exe {
+            matching(ProcessSelector::exe(path))
+        } else if let Some(regex) = &self.pgrep {
+            matching(ProcessSelector::Pattern(regex.clone()))
+        } else if let Some(patterns) = &self.on_exec {
+            Target::OnExec(patterns.clone())
+        } else if let Some(path) = &self.cgroup {
+            Target::Cgroup(path.clone())
+        } else if let Some(id) = &self.container {
+            Target::Container(id.clone())
+        } else if self.all {
+            Target::All {
+                exclude_pids: self.options.exclude_pid.clone(),
+                exclude_comms: self.options.exclude_comm.clone(),
+            }
+        } else {
+            unreachable!("clap ensures exactly one target is provided")
         }
     }
 }
@@ -57,5 +410,210 @@ impl TargetArgs {
 #[derive(Debug, Clone)]
 pub enum Target {
     Pid(Vec<i32>),
+    /// Processes given by their IDs in another PID namespace, e.g. a container's
+    NamespacedPid {
+        pids: Vec<i32>,
+        /// Namespace file, such as `/proc/<pid>/ns/pid`
+        namespace: PathBuf,
+    },
+    /// Threads, without the other threads of their processes, and whole processes
+    Tid {
+        tids: Vec<i32>,
+        pids: Vec<i32>,
+    },
+    /// Processes whose real user is one of `uids` or whose real group is one of `gids`
+    Users {
+        uids: Vec<u32>,
+        gids: Vec<u32>,
+    },
     Command(Vec<String>),
+    /// Running processes selected when starting, and with `watch` those selected later on
+    Matching {
+        selector: ProcessSelector,
+        watch: bool,
+    },
+    /// Processes executing one of these paths or command names after the session started
+    OnExec(Vec<String>),
+    /// Processes in a cgroup v2 directory or its descendants
+    Cgroup(PathBuf),
+    /// Processes in the cgroup of a container, given by full or short ID
+    Container(String),
+    /// Every process except sikte, its ancestors in its session and the excluded ones
+    All {
+        exclude_pids: Vec<i32>,
+        /// Command names of the main threads of the excluded processes
+        exclude_comms: Vec<String>,
+    },
+}
+
+/// Parse a user name or ID
+fn parse_uid(s: &str) -> Result<u32, String> {
+    if let Ok(uid) = s.parse() {
+        return Ok(uid);
+    }
+    users::uid(s)
+        .map_err(|e| format!("failed to look up user '{s}': {e}"))?
+        .ok_or_else(|| format!("no user named '{s}'"))
+}
+
+/// Parse a group name or ID
+fn parse_gid(s: &str) -> Result<u32, String> {
+    if let Ok(gid) = s.parse() {
+        return Ok(gid);
+    }
+    users::gid(s)
+        .map_err(|e| format!("failed to look up group '{s}': {e}"))?
+        .ok_or_else(|| format!("no group named '{s}'"))
+}
+
+/// Parse a PID namespace file, or the ID of a process whose namespace to use
+fn parse_pid_namespace(s: &str) -> Result<PathBuf, String> {
+    match s.parse::<i32>() {
+        Ok(pid) if pid > 0 => Ok(PathBuf::from(format!("/proc/{pid}/ns/pid"))),
+        Ok(_) => Err("expected a positive process ID".to_string()),
+        Err(_) => Ok(PathBuf::from(s)),
+    }
+}
+
+/// Parse a path or command name compared to the executed files
+fn parse_exec_pattern(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        Err("expected a path or a command name".to_string())
+    } else if s.contains('/') && s.len() >= 256 {
+        Err("paths are limited to 255 bytes".to_string())
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+/// Where to write a trace to, besides the terminal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSpec {
+    /// SQLite database file
+    Sqlite(PathBuf),
+    /// OpenTelemetry collector accepting OTLP/HTTP
+    Otlp(HttpUrl),
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected FORMAT:PATH, got '{s}'"))?;
+
+        if path.is_empty() {
+            return Err(format!("missing path for output format '{format}'"));
+        }
+
+        match format {
+            "sqlite" => Ok(OutputSpec::Sqlite(PathBuf::from(path))),
+            "otlp" => path.parse().map(OutputSpec::Otlp),
+            _ => Err(format!(
+                "unknown output format '{format}' (supported: sqlite, otlp)"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("64kb"), Ok(64 << 10));
+        assert_eq!(parse_size("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    fn parse_target(args: &[&str]) -> Result<Target, clap::Error> {
+        let cli = Cli::try_parse_from(["sikte", "record"].iter().chain(args))?;
+        match cli.command {
+            Commands::Record(record) => Ok(record.target.to_target()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert!(matches!(parse_target(&["--pid", "1,2"]), Ok(Target::Pid(pids)) if pids == [1, 2]));
+        assert!(matches!(
+            parse_target(&["--all", "--exclude-pid", "1,2", "--exclude-comm", "sshd"]),
+            Ok(Target::All { exclude_pids, exclude_comms })
+                if exclude_pids == [1, 2] && exclude_comms == ["sshd"]
+        ));
+        assert!(parse_target(&[]).is_err());
+        assert!(parse_target(&["--all", "--pid", "1"]).is_err());
+        assert!(parse_target(&["--pid", "1", "--exclude-pid", "2"]).is_err());
+
+        assert!(matches!(
+            parse_target(&["--comm", "nginx", "--watch"]),
+            Ok(Target::Matching { selector: ProcessSelector::Comm(name), watch: true }) if name == "nginx"
+        ));
+        assert!(matches!(
+            parse_target(&["--pgrep", "python.*app"]),
+            Ok(Target::Matching {
+                selector: ProcessSelector::Pattern(_),
+                watch: false
+            })
+        ));
+        assert!(parse_target(&["--pgrep", "("]).is_err());
+        assert!(parse_target(&["--pid", "1", "--watch"]).is_err());
+        assert!(parse_target(&["--exe", "/bin/sh", "--exclude-comm", "sh"]).is_err());
+
+        assert!(matches!(
+            parse_target(&["--on-exec", "/usr/bin/git,make"]),
+            Ok(Target::OnExec(patterns)) if patterns == ["/usr/bin/git", "make"]
+        ));
+        assert!(parse_target(&["--on-exec", &format!("/{}", "a".repeat(255))]).is_err());
+
+        assert!(matches!(
+            parse_target(&["--container", "4f1c1b2c3d4e"]),
+            Ok(Target::Container(id)) if id == "4f1c1b2c3d4e"
+        ));
+        assert!(parse_target(&["--cgroup", "/sys/fs/cgroup/a", "--container", "4f1c"]).is_err());
+
+        assert!(matches!(
+            parse_target(&["--tid", "7,8"]),
+            Ok(Target::Tid { tids, pids }) if tids == [7, 8] && pids.is_empty()
+        ));
+        assert!(matches!(
+            parse_target(&["--tid", "7", "--pid", "1"]),
+            Ok(Target::Tid { tids, pids }) if tids == [7] && pids == [1]
+        ));
+        assert!(parse_target(&["--tid", "7", "--comm", "nginx"]).is_err());
+        assert!(parse_target(&["--tid", "7", "--uid", "0"]).is_err());
+        assert!(matches!(
+            parse_target(&["--uid", "root,1000"]),
+            Ok(Target::Users { uids, gids }) if uids == [0, 1000] && gids.is_empty()
+        ));
+        assert!(matches!(
+            parse_target(&["--uid", "0", "--gid", "0"]),
+            Ok(Target::Users { uids, gids }) if uids == [0] && gids == [0]
+        ));
+        assert!(parse_target(&["--uid", "0", "--pid", "1"]).is_err());
+        assert!(parse_target(&["--gid", "0", "--all"]).is_err());
+        assert!(parse_target(&["--gid", "sikte-no-such-group"]).is_err());
+
+        assert!(matches!(
+            parse_target(&["--pid", "1", "--pid-ns", "4242"]),
+            Ok(Target::NamespacedPid { pids, namespace })
+                if pids == [1] && namespace == Path::new("/proc/4242/ns/pid")
+        ));
+        assert!(matches!(
+            parse_target(&["--pid", "1", "--pid-ns", "/run/netns/pid"]),
+            Ok(Target::NamespacedPid { namespace, .. }) if namespace == Path::new("/run/netns/pid")
+        ));
+        assert!(parse_target(&["--tid", "1", "--pid-ns", "4242"]).is_err());
+        assert!(parse_target(&["--pid", "1", "--pid-ns", "0"]).is_err());
+    }
 }
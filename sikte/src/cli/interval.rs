@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime},
+};
+
+use serde_json::{Value, json};
+use tokio::task::JoinHandle;
+
+use crate::{
+    cli::args::IntervalFormat,
+    common::units::format_ns,
+    subscribers::{SharedStats, StatsWindow, SyscallTotals},
+};
+
+/// Quantiles reported for every syscall
+const QUANTILES: [(&str, f64); 3] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)];
+
+/// Background task printing interval reports, see [`spawn_interval_reports`]
+pub struct IntervalReports {
+    task: JoinHandle<()>,
+    stats: SharedStats,
+    format: IntervalFormat,
+}
+
+impl IntervalReports {
+    /// Stop the reports and print the last, partial interval. Call it once the session stopped,
+    /// so that the syscalls completed since the last report are counted
+    pub async fn finish(self) {
+        self.task.abort();
+        // A report being printed is completed first
+        let _ = self.task.await;
+        print_report(&self.stats, self.format);
+    }
+}
+
+/// Print the syscalls completed during every `interval` from a background task, like `vmstat`
+pub fn spawn_interval_reports(
+    stats: SharedStats,
+    interval: Duration,
+    format: IntervalFormat,
+) -> IntervalReports {
+    let task = tokio::spawn({
+        let stats = stats.clone();
+        async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick completes immediately
+            ticker.tick().await;
+            stats.lock().expect("stats lock poisoned").take_window();
+
+            loop {
+                ticker.tick().await;
+                print_report(&stats, format);
+            }
+        }
+    });
+    IntervalReports {
+        task,
+        stats,
+        format,
+    }
+}
+
+/// Print the syscalls completed since the last report
+fn print_report(stats: &SharedStats, format: IntervalFormat) {
+    let window = stats.lock().expect("stats lock poisoned").take_window();
+    let now = SystemTime::now();
+    match format {
+        IntervalFormat::Text => print!("{}", render_text(&window, now)),
+        IntervalFormat::Json => println!("{}", render_json(&window, now)),
+    }
+}
+
+/// Syscalls of a window sorted by descending number of calls
+fn busiest(window: &StatsWindow) -> Vec<(&'static str, SyscallTotals)> {
+    let mut syscalls: Vec<_> = window.by_syscall(None).into_iter().collect();
+    syscalls.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.calls()));
+    syscalls
+}
+
+/// Render a window as a table with a line per syscall
+fn render_text(window: &StatsWindow, now: SystemTime) -> String {
+    let total = window.total(None);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} {:.0} syscalls/s, {:.0} errors/s",
+        humantime::format_rfc3339_seconds(now),
+        window.rate(total.calls()),
+        window.rate(total.errors)
+    );
+    if window.lost > 0 {
+        let _ = writeln!(out, "WARNING: {} events were dropped", window.lost);
+    }
+
+    let _ = write!(
+        out,
+        "{:<24} {:>10} {:>10}",
+        "SYSCALL", "CALLS/S", "ERRORS/S"
+    );
+    for (name, _) in QUANTILES {
+        let _ = write!(out, " {:>10}", name.to_uppercase());
+    }
+    let _ = writeln!(out);
+
+    for (name, totals) in busiest(window) {
+        let _ = write!(
+            out,
+            "{name:<24} {:>10.0} {:>10.0}",
+            window.rate(totals.calls()),
+            window.rate(totals.errors)
+        );
+        for (_, q) in QUANTILES {
+            let _ = write!(out, " {:>10}", format_ns(totals.latency.quantile_ns(q)));
+        }
+        let _ = writeln!(out);
+    }
+    let _ = writeln!(out);
+
+    out
+}
+
+/// Render a window as a single JSON object
+fn render_json(window: &StatsWindow, now: SystemTime) -> Value {
+    let total = window.total(None);
+    let syscalls: Vec<Value> = busiest(window)
+        .into_iter()
+        .map(|(name, totals)| {
+            let mut syscall = json!({
+                "name": name,
+                "calls": totals.calls(),
+                "errors": totals.errors,
+                "calls_per_second": window.rate(totals.calls()),
+                "errors_per_second": window.rate(totals.errors),
+                "avg_ns": totals.latency.mean_ns(),
+            });
+            for (quantile, q) in QUANTILES {
+                syscall[format!("{quantile}_ns")] = totals.latency.quantile_ns(q).into();
+            }
+            syscall
+        })
+        .collect();
+
+    json!({
+        "timestamp": humantime::format_rfc3339_millis(now).to_string(),
+        "interval_seconds": window.duration.as_secs_f64(),
+        "calls_per_second": window.rate(total.calls()),
+        "errors_per_second": window.rate(total.errors),
+        "dropped_events": window.lost,
+        "syscalls": syscalls,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        publishers::syscalls::SyscallID,
+        subscribers::{
+            EventSubscriber, StatsSubscriber,
+            test_utils::{sys_enter, sys_exit},
+        },
+    };
+
+    fn window() -> StatsWindow {
+        let mut subscriber = StatsSubscriber::new();
+        let read = SyscallID::read as i64;
+        let openat = SyscallID::openat as i64;
+        for i in 0..4 {
+            subscriber.read_syscall(&sys_enter(i * 10_000, 1, 1, read));
+            subscriber.read_syscall(&sys_exit(i * 10_000 + 2_000, 1, 1, 0));
+        }
+        subscriber.read_syscall(&sys_enter(0, 1, 2, openat));
+        subscriber.read_syscall(&sys_exit(500, 1, 2, -2));
+
+        let mut window = subscriber.stats().lock().unwrap().take_window();
+        window.duration = Duration::from_secs(1);
+        window
+    }
+
+    #[test]
+    fn test_render_json() {
+        let report = render_json(&window(), SystemTime::UNIX_EPOCH);
+
+        assert_eq!(report["timestamp"], "1970-01-01T00:00:00.000Z");
+        assert_eq!(report["calls_per_second"], 5.0);
+        assert_eq!(report["errors_per_second"], 1.0);
+
+        let syscalls = report["syscalls"].as_array().unwrap();
+        assert_eq!(syscalls[0]["name"], "read");
+        assert_eq!(syscalls[0]["calls"], 4);
+        assert_eq!(syscalls[0]["calls_per_second"], 4.0);
+        assert_eq!(syscalls[0]["avg_ns"], 2_000);
+        assert_eq!(syscalls[1]["name"], "openat");
+        assert_eq!(syscalls[1]["errors"], 1);
+        assert!(syscalls[1]["p99_ns"].as_u64().unwrap() <= 1_000);
+    }
+
+    #[test]
+    fn test_render_text() {
+        let report = render_text(&window(), SystemTime::UNIX_EPOCH);
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "1970-01-01T00:00:00Z 5 syscalls/s, 1 errors/s");
+        assert!(lines[1].starts_with("SYSCALL"));
+        assert!(lines[2].starts_with("read "), "{report}");
+        assert!(lines[3].starts_with("openat "), "{report}");
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Linux capabilities of the current process, as found in `/proc/self/status`, and how sikte
+//! gives up the ones it doesn't need.
+//!
+//! Capabilities belong to threads, and new threads inherit them from the thread creating them. They
+//! must be changed before any other thread is started, e.g. before the tokio runtime.
+use std::{fmt, fs, io};
+
+use log::{debug, info};
+
+/// `CAP_DAC_READ_SEARCH`, which allows reading the files of other users, e.g. in `/proc/<pid>`
+pub const CAP_DAC_READ_SEARCH: u32 = 2;
+/// `CAP_SYS_PTRACE`, which allows inspecting the processes of other users, e.g. their executable
+pub const CAP_SYS_PTRACE: u32 = 19;
+/// `CAP_SYS_ADMIN`, which allows loading eBPF on kernels older than 5.8
+pub const CAP_SYS_ADMIN: u32 = 21;
+/// `CAP_SYS_RESOURCE`, which allows raising the locked memory limit, needed before Linux 5.11
+pub const CAP_SYS_RESOURCE: u32 = 24;
+/// `CAP_PERFMON`, which allows attaching tracing programs since Linux 5.8
+pub const CAP_PERFMON: u32 = 38;
+/// `CAP_BPF`, which allows loading eBPF programs and creating maps since Linux 5.8
+pub const CAP_BPF: u32 = 39;
+
+/// Capabilities needed to trace syscalls, with their names
+pub const TRACING_CAPABILITIES: [(u32, &str); 2] =
+    [(CAP_BPF, "CAP_BPF"), (CAP_PERFMON, "CAP_PERFMON")];
+
+/// Capabilities needed to look up the processes of every user in `/proc`
+pub const PROCFS_CAPABILITIES: CapabilitySet =
+    CapabilitySet(1 << CAP_DAC_READ_SEARCH | 1 << CAP_SYS_PTRACE);
+
+/// Capabilities sikte deals with, with their names
+const NAMES: [(u32, &str); 6] = [
+    (CAP_DAC_READ_SEARCH, "CAP_DAC_READ_SEARCH"),
+    (CAP_SYS_PTRACE, "CAP_SYS_PTRACE"),
+    (CAP_SYS_ADMIN, "CAP_SYS_ADMIN"),
+    (CAP_SYS_RESOURCE, "CAP_SYS_RESOURCE"),
+    (CAP_PERFMON, "CAP_PERFMON"),
+    (CAP_BPF, "CAP_BPF"),
+];
+
+/// `_LINUX_CAPABILITY_VERSION_3`, for 64-bit capability sets
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// `struct __user_cap_header_struct` of `capset(2)`
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// `struct __user_cap_data_struct` of `capset(2)`, holding 32 capabilities of each set
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Set of capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilitySet(pub u64);
+
+impl CapabilitySet {
+    pub const EMPTY: CapabilitySet = CapabilitySet(0);
+
+    /// Effective capabilities of the current thread
+    pub fn effective() -> io::Result<CapabilitySet> {
+        Self::read("CapEff")
+    }
+
+    /// Permitted capabilities of the current thread, which it may make effective
+    pub fn permitted() -> io::Result<CapabilitySet> {
+        Self::read("CapPrm")
+    }
+
+    fn read(field: &str) -> io::Result<CapabilitySet> {
+        // /proc/thread-self is the calling thread, while /proc/self is the main thread
+        let status = fs::read_to_string("/proc/thread-self/status")?;
+        Self::parse_status(&status, field).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no {field} in /proc/thread-self/status"),
+            )
+        })
+    }
+
+    /// Parse a capability field, e.g. `CapEff`, of a `/proc/<pid>/status` file
+    fn parse_status(status: &str, field: &str) -> Option<CapabilitySet> {
+        status.lines().find_map(|line| {
+            let value = line.strip_prefix(field)?.strip_prefix(':')?;
+            u64::from_str_radix(value.trim(), 16)
+                .ok()
+                .map(CapabilitySet)
+        })
+    }
+
+    /// Whether the set contains `cap`
+    pub fn contains(self, cap: u32) -> bool {
+        self.0 & (1 << cap) != 0
+    }
+
+    /// Add `cap` to the set
+    pub fn with(self, cap: u32) -> CapabilitySet {
+        CapabilitySet(self.0 | (1 << cap))
+    }
+
+    /// Capabilities in either set
+    pub fn union(self, other: CapabilitySet) -> CapabilitySet {
+        CapabilitySet(self.0 | other.0)
+    }
+
+    /// Capabilities in both sets
+    pub fn intersection(self, other: CapabilitySet) -> CapabilitySet {
+        CapabilitySet(self.0 & other.0)
+    }
+
+    /// Names of the capabilities needed to trace syscalls that are missing from the set.
+    /// `CAP_SYS_ADMIN` grants all of them
+    pub fn missing_for_tracing(self) -> Vec<&'static str> {
+        if self.contains(CAP_SYS_ADMIN) {
+            return vec![];
+        }
+        TRACING_CAPABILITIES
+            .iter()
+            .filter(|&&(cap, _)| !self.contains(cap))
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
+
+/// Names of the capabilities sikte deals with, and the others in hexadecimal as understood by
+/// `capsh --decode`
+impl fmt::Display for CapabilitySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut others = self.0;
+        let mut names = vec![];
+        for (cap, name) in NAMES {
+            if self.contains(cap) {
+                names.push(name.to_string());
+                others &= !(1 << cap);
+            }
+        }
+        if others != 0 {
+            names.push(format!("{others:#x}"));
+        }
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+/// Set the capabilities of the calling thread. Clearing the inheritable set also clears the
+/// ambient set, so that commands started later don't get any capability
+fn set_capabilities(
+    effective: CapabilitySet,
+    permitted: CapabilitySet,
+    inheritable: CapabilitySet,
+) -> io::Result<()> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [0, 32].map(|shift| CapUserData {
+        effective: (effective.0 >> shift) as u32,
+        permitted: (permitted.0 >> shift) as u32,
+        inheritable: (inheritable.0 >> shift) as u32,
+    });
+    // SAFETY: both pointers are valid, and version 3 reads two data structs
+    let ret = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Capabilities sikte uses while loading and attaching the eBPF programs, and looking up the
+/// processes to trace
+fn wanted_for_loading() -> CapabilitySet {
+    PROCFS_CAPABILITIES
+        .with(CAP_BPF)
+        .with(CAP_PERFMON)
+        .with(CAP_SYS_RESOURCE)
+        .with(CAP_SYS_ADMIN)
+}
+
+/// Make the permitted capabilities sikte needs effective. They are only permitted when file
+/// capabilities were set without the effective flag, e.g. `setcap cap_bpf,cap_perfmon+p`
+pub fn raise_tracing_capabilities() -> io::Result<()> {
+    let effective = CapabilitySet::effective()?;
+    let permitted = CapabilitySet::permitted()?;
+    let raised = effective.union(permitted.intersection(wanted_for_loading()));
+    if raised == effective {
+        return Ok(());
+    }
+
+    debug!("Raising effective capabilities from {effective} to {raised}");
+    let status = fs::read_to_string("/proc/thread-self/status")?;
+    let inheritable =
+        CapabilitySet::parse_status(&status, "CapInh").unwrap_or(CapabilitySet::EMPTY);
+    set_capabilities(raised, permitted, inheritable)
+}
+
+/// Drop every capability except the one the eBPF maps need once the programs are attached, and
+/// the effective ones of `keep`, and return those kept. Before Linux 6.5 every `bpf()` command
+/// fails without `CAP_BPF`, or `CAP_SYS_ADMIN` before 5.8, when unprivileged eBPF is disabled.
+/// Sessions that keep looking for processes to trace also need [`PROCFS_CAPABILITIES`].
+///
+/// Only the calling thread drops them, so it must be called before other threads are started.
+/// Commands started as root still get root's capabilities, as the bounding set is left alone
+pub fn drop_capabilities(keep: CapabilitySet) -> io::Result<CapabilitySet> {
+    let effective = CapabilitySet::effective()?;
+    let for_maps = if effective.contains(CAP_BPF) {
+        CapabilitySet::EMPTY.with(CAP_BPF)
+    } else {
+        effective.intersection(CapabilitySet::EMPTY.with(CAP_SYS_ADMIN))
+    };
+    let kept = for_maps.union(effective.intersection(keep));
+    set_capabilities(kept, kept, CapabilitySet::EMPTY)?;
+    info!("Dropped capabilities, keeping {kept}");
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let status = "Name:\tsikte\nCapPrm:\t0000000000000000\nCapEff:\t000000c000000000\n";
+        let caps = CapabilitySet::parse_status(status, "CapEff").unwrap();
+        assert!(caps.contains(CAP_BPF));
+        assert!(caps.contains(CAP_PERFMON));
+        assert!(!caps.contains(CAP_SYS_ADMIN));
+        assert!(caps.missing_for_tracing().is_empty());
+
+        let caps = CapabilitySet::parse_status(status, "CapPrm").unwrap();
+        assert_eq!(caps.missing_for_tracing(), ["CAP_BPF", "CAP_PERFMON"]);
+        assert!(
+            CapabilitySet(1 << CAP_SYS_ADMIN)
+                .missing_for_tracing()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let caps = CapabilitySet::EMPTY.with(CAP_BPF).with(CAP_PERFMON);
+        assert!(caps.contains(CAP_BPF));
+        assert!(!caps.contains(CAP_SYS_RESOURCE));
+        assert_eq!(
+            caps.intersection(CapabilitySet::EMPTY.with(CAP_BPF).with(CAP_SYS_ADMIN)),
+            CapabilitySet::EMPTY.with(CAP_BPF)
+        );
+        assert_eq!(
+            caps.intersection(wanted_for_loading()),
+            caps,
+            "sikte must be able to raise the tracing capabilities"
+        );
+
+        assert_eq!(caps.to_string(), "CAP_PERFMON, CAP_BPF");
+        assert_eq!(
+            PROCFS_CAPABILITIES.to_string(),
+            "CAP_DAC_READ_SEARCH, CAP_SYS_PTRACE"
+        );
+        assert_eq!(CapabilitySet(0b11).to_string(), "0x3");
+        assert_eq!(CapabilitySet::EMPTY.to_string(), "none");
+    }
+}
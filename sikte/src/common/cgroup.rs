@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Helpers for finding cgroup v2 directories, which the eBPF programs are given as open
+//! directories
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where the cgroup v2 hierarchy is usually mounted
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Prefixes of the cgroup directories of containers, followed by their ID: Docker, containerd,
+/// CRI-O and podman with the systemd cgroup driver. With the cgroupfs driver, the directory name
+/// is the bare ID
+const CONTAINER_PREFIXES: [&str; 4] = ["docker-", "cri-containerd-", "crio-", "libpod-"];
+
+/// Length of a full container ID, in hexadecimal digits
+const CONTAINER_ID_LEN: usize = 64;
+
+/// Check that a path is a cgroup v2 directory
+pub fn validate(path: &Path) -> io::Result<()> {
+    if path.join("cgroup.procs").exists() && !path.join("tasks").exists() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a cgroup v2 directory", path.display()),
+        ))
+    }
+}
+
+/// A cgroup and its descendants, since processes may be in a child cgroup of the one given
+pub fn with_descendants(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut cgroups = vec![path.to_path_buf()];
+    let mut i = 0;
+    while i < cgroups.len() {
+        for entry in fs::read_dir(&cgroups[i])? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                cgroups.push(entry.path());
+            }
+        }
+        i += 1;
+    }
+    Ok(cgroups)
+}
+
+/// Find the cgroup of a container by its full or short ID under `root`
+pub fn find_container(root: &Path, id: &str) -> io::Result<PathBuf> {
+    if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid container ID '{id}'"),
+        ));
+    }
+
+    let matches: Vec<PathBuf> = with_descendants(root)?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| container_id(name.to_str()?))
+                .is_some_and(|container| container.starts_with(id))
+        })
+        .collect();
+    match matches.as_slice() {
+        [path] => Ok(path.clone()),
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no cgroup of container {id} under {}", root.display()),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("container ID {id} is ambiguous"),
+        )),
+    }
+}
+
+/// ID of the container a cgroup directory belongs to, if it looks like one
+fn container_id(name: &str) -> Option<&str> {
+    let name = name.strip_suffix(".scope").unwrap_or(name);
+    let id = CONTAINER_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    (id.len() == CONTAINER_ID_LEN && id.bytes().all(|byte| byte.is_ascii_hexdigit())).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4f1c1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8";
+
+    #[test]
+    fn test_container_id() {
+        assert_eq!(container_id(&format!("docker-{ID}.scope")), Some(ID));
+        assert_eq!(
+            container_id(&format!("cri-containerd-{ID}.scope")),
+            Some(ID)
+        );
+        assert_eq!(container_id(&format!("libpod-{ID}.scope")), Some(ID));
+        assert_eq!(container_id(ID), Some(ID));
+        assert_eq!(container_id(&format!("libpod-conmon-{ID}.scope")), None);
+        assert_eq!(container_id("system.slice"), None);
+        assert_eq!(container_id("docker-1234.scope"), None);
+    }
+
+    #[test]
+    fn test_find_container() {
+        let root = std::env::temp_dir().join(format!("sikte-cgroup-test-{}", std::process::id()));
+        let scope = root.join("system.slice").join(format!("docker-{ID}.scope"));
+        fs::create_dir_all(&scope).unwrap();
+        fs::create_dir_all(root.join("docker").join(ID.replace('4', "5"))).unwrap();
+
+        assert_eq!(find_container(&root, &ID[..12]).unwrap(), scope);
+        assert_eq!(find_container(&root, ID).unwrap(), scope);
+        let not_found = find_container(&root, "abc").unwrap_err();
+        assert_eq!(not_found.kind(), io::ErrorKind::NotFound);
+        assert!(find_container(&root, "").is_err());
+        assert!(find_container(&root, "../etc").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::mem::MaybeUninit;
+
+/// Read a clock in ns since its epoch
+fn clock_ns(clock: libc::clockid_t) -> i128 {
+    let mut ts = MaybeUninit::<libc::timespec>::uninit();
+    // SAFETY: both clocks always exist on Linux and `ts` is a valid pointer
+    let ts = unsafe {
+        libc::clock_gettime(clock, ts.as_mut_ptr());
+        ts.assume_init()
+    };
+    ts.tv_sec as i128 * 1_000_000_000 + ts.tv_nsec as i128
+}
+
+/// Current time of the clock used by `bpf_ktime_get_ns`, in ns since boot
+pub fn monotonic_ns() -> u64 {
+    clock_ns(libc::CLOCK_MONOTONIC) as u64
+}
+
+/// Converts eBPF timestamps into wall-clock time.
+///
+/// `bpf_ktime_get_ns` uses `CLOCK_MONOTONIC`, which starts counting at boot time.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    /// `CLOCK_REALTIME - CLOCK_MONOTONIC` in ns
+    offset_ns: i128,
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WallClock {
+    pub fn new() -> WallClock {
+        WallClock {
+            offset_ns: clock_ns(libc::CLOCK_REALTIME) - clock_ns(libc::CLOCK_MONOTONIC),
+        }
+    }
+
+    /// Convert a `bpf_ktime_get_ns` timestamp to ns since the UNIX epoch
+    pub fn to_unix_ns(&self, ktime_ns: u64) -> u64 {
+        (ktime_ns as i128 + self.offset_ns).max(0) as u64
+    }
+}
@@ -1,3 +1,9 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 pub const SYS_ENTER: &str = "sys_enter";
 pub const SYS_EXIT: &str = "sys_exit";
+pub const SCHED_PROCESS_EXEC: &str = "sched_process_exec";
+pub const SCHED_PROCESS_EXIT: &str = "sched_process_exit";
+pub const RAW_SYSCALLS_SYS_ENTER: &str = "raw_syscalls/sys_enter";
+pub const RAW_SYSCALLS_SYS_EXIT: &str = "raw_syscalls/sys_exit";
+pub const SCHED_SCHED_PROCESS_EXEC: &str = "sched/sched_process_exec";
+pub const SCHED_SCHED_PROCESS_EXIT: &str = "sched/sched_process_exit";
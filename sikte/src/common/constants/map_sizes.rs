@@ -0,0 +1,7 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Default map sizes. They must match the ones in `sikte.h`
+
+/// Default size of the `SYSCALL_EVENTS` ring buffer in bytes
+pub const DEFAULT_RING_BUFFER_SIZE: u32 = 1 << 20;
+/// Default maximum number of entries of `PID_ALLOW_LIST`
+pub const DEFAULT_MAX_PIDS: u32 = 1 << 10;
@@ -1,3 +1,4 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 pub mod attach_points;
+pub mod map_sizes;
 pub mod program_names;
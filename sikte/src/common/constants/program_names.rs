@@ -1,3 +1,9 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 pub const SIKTE_RAW_TRACE_POINT_AT_ENTER: &str = "sikte_raw_trace_point_at_enter";
 pub const SIKTE_RAW_TRACE_POINT_AT_EXIT: &str = "sikte_raw_trace_point_at_exit";
+pub const SIKTE_RAW_TRACE_POINT_AT_EXEC: &str = "sikte_raw_trace_point_at_exec";
+pub const SIKTE_RAW_TRACE_POINT_AT_PROCESS_EXIT: &str = "sikte_raw_trace_point_at_process_exit";
+pub const SIKTE_TRACE_POINT_AT_ENTER: &str = "sikte_trace_point_at_enter";
+pub const SIKTE_TRACE_POINT_AT_EXIT: &str = "sikte_trace_point_at_exit";
+pub const SIKTE_TRACE_POINT_AT_EXEC: &str = "sikte_trace_point_at_exec";
+pub const SIKTE_TRACE_POINT_AT_PROCESS_EXIT: &str = "sikte_trace_point_at_process_exit";
@@ -4,6 +4,8 @@
 /// This module provides a bridge between the auto-generated types from libbpf-rs
 /// and the rest of the codebase. It re-exports generated types with conventional
 /// naming and adds helper methods for working with tagged unions.
+use std::borrow::Cow;
+
 use plain::Plain;
 
 // Include generated skeleton to access types module
@@ -15,7 +17,11 @@ mod sikte_skel {
 }
 
 // Re-export generated types with capitalized names to match Rust conventions
-pub use sikte_skel::types::{syscall_data as SyscallData, syscall_state as SyscallState};
+pub use sikte_skel::types::{
+    at_enter_t as AtEnter, at_exit_t as AtExit, comm as Comm, syscall_data as SyscallData,
+    syscall_state as SyscallState, syscall_state_data as SyscallStateData,
+    trace_config as TraceConfig,
+};
 
 pub type PidT = i32;
 
@@ -26,9 +32,17 @@ pub mod syscall_state_tag {
     pub const AT_EXIT: u32 = 1;
 }
 
+/// Index constants of the `DROPPED_EVENTS` map (matching C enum values)
+pub mod dropped_event_kind {
+    pub const AT_ENTER: u32 = 0;
+    pub const AT_EXIT: u32 = 1;
+}
+
 // Implement Plain trait for zero-copy deserialization from ring buffers
 // SAFETY: These types are #[repr(C)] and generated from C structs with BTF
 unsafe impl Plain for SyscallData {}
+unsafe impl Plain for TraceConfig {}
+unsafe impl Plain for Comm {}
 unsafe impl Plain for SyscallState {}
 
 /// Extension trait for SyscallState to provide helper methods
@@ -58,6 +72,33 @@ impl SyscallStateExt for SyscallState {
     }
 }
 
+/// Extension trait for Comm to read the command name it holds
+pub trait CommExt {
+    /// Command name up to the first NUL byte, with invalid UTF-8 replaced
+    fn to_string_lossy(&self) -> Cow<'_, str>;
+}
+
+impl CommExt for Comm {
+    fn to_string_lossy(&self) -> Cow<'_, str> {
+        // SAFETY: Comm is a plain array of bytes
+        let bytes = unsafe { plain::as_bytes(self) };
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..len])
+    }
+}
+
+impl From<&str> for Comm {
+    /// Command name truncated like the kernel does, leaving room for the NUL byte
+    fn from(name: &str) -> Comm {
+        let mut comm = Comm::default();
+        let len = comm.name.len() - 1;
+        for (dst, &src) in comm.name.iter_mut().zip(name.as_bytes().iter().take(len)) {
+            *dst = src as _;
+        }
+        comm
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,10 +106,20 @@ mod tests {
     #[test]
     fn test_syscall_data_layout() {
         // Verify size matches expectations (should be same as manual types)
-        assert_eq!(std::mem::size_of::<SyscallData>(), 32);
+        assert_eq!(std::mem::size_of::<SyscallData>(), 88);
         assert_eq!(std::mem::align_of::<SyscallData>(), 8);
     }
 
+    #[test]
+    fn test_comm() {
+        assert_eq!(Comm::from("worker-3").to_string_lossy(), "worker-3");
+        assert_eq!(
+            Comm::from("systemd-journald").to_string_lossy(),
+            "systemd-journal"
+        );
+        assert_eq!(Comm::default().to_string_lossy(), "");
+    }
+
     #[test]
     fn test_syscall_state_tagged_union() {
         use sikte_skel::types::{at_enter_t, at_exit_t, syscall_state_data};
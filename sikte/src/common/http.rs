@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Minimal HTTP/1.1 helpers, enough for exporting traces and serving metrics without pulling in a
+//! full HTTP stack
+use std::{fmt, io, str::FromStr, sync::Arc, time::Duration};
+
+use log::{debug, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Maximum time allowed for a whole request/response exchange
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum size of the request line and headers accepted by [`serve`]
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Plain `http://host[:port][/path]` URL. IPv6 hosts are written in brackets, e.g.
+/// `http://[::1]:4318`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    /// Host name or IP address, without brackets
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("only http:// URLs are supported, got '{s}'"))?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| format!("missing ']' after IPv6 host in '{s}'"))?;
+                match rest {
+                    "" => (host, None),
+                    _ => (
+                        host,
+                        Some(rest.strip_prefix(':').ok_or_else(|| {
+                            format!("unexpected '{rest}' after IPv6 host in '{s}'")
+                        })?),
+                    ),
+                }
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("invalid port '{port}' in '{s}'"))?,
+            None => 80,
+        };
+
+        if host.is_empty() {
+            return Err(format!("missing host in '{s}'"));
+        }
+
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl HttpUrl {
+    /// `host:port`, with IPv6 hosts in brackets
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+/// Send a POST request and return the response status code
+pub async fn post(url: &HttpUrl, content_type: &str, body: &[u8]) -> io::Result<u16> {
+    tokio::time::timeout(REQUEST_TIMEOUT, post_inner(url, content_type, body))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "HTTP request timed out"))?
+}
+
+async fn post_inner(url: &HttpUrl, content_type: &str, body: &[u8]) -> io::Result<u16> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+
+    let path = if url.path.is_empty() { "/" } else { &url.path };
+    let head = format!(
+        "POST {path} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        url.authority(),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    parse_status(&response)
+}
+
+/// Extract the status code from the first line of an HTTP response
+fn parse_status(response: &[u8]) -> io::Result<u16> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+
+    let line_end = response
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(invalid)?;
+    let status_line = std::str::from_utf8(&response[..line_end]).map_err(|_| invalid())?;
+
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(invalid)
+}
+
+/// HTTP response produced by a [`serve`] handler
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Response {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found() -> Response {
+        Response {
+            status: 404,
+            content_type: "text/plain",
+            body: "Not Found\n".to_string(),
+        }
+    }
+}
+
+/// Answer GET requests on `listener` forever, building responses from the request path
+pub async fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&str) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("Failed to accept HTTP connection: {err}");
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(err) = answer(stream, handler.as_ref()).await {
+                debug!("HTTP connection from {peer} failed: {err}");
+            }
+        });
+    }
+}
+
+async fn answer<F>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&str) -> Response,
+{
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut chunk))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "HTTP request timed out"))??;
+        if n == 0 || request.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete HTTP request",
+            ));
+        }
+        request.extend_from_slice(&chunk[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => handler(path),
+        _ => Response {
+            status: 405,
+            content_type: "text/plain",
+            body: "Method Not Allowed\n".to_string(),
+        },
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(host: &str, port: u16, path: &str) -> HttpUrl {
+        HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            "http://localhost:4318/v1/traces".parse(),
+            Ok(url("localhost", 4318, "/v1/traces"))
+        );
+        assert_eq!("http://collector".parse(), Ok(url("collector", 80, "")));
+        assert!("https://collector".parse::<HttpUrl>().is_err());
+        assert!("http://:4318".parse::<HttpUrl>().is_err());
+        assert!("http://collector:otlp".parse::<HttpUrl>().is_err());
+    }
+
+    #[test]
+    fn test_parse_ipv6_url() {
+        let parsed: HttpUrl = "http://[::1]:4318/v1/traces".parse().unwrap();
+        assert_eq!(parsed, url("::1", 4318, "/v1/traces"));
+        assert_eq!(parsed.to_string(), "http://[::1]:4318/v1/traces");
+        assert_eq!("http://[fe80::1]".parse(), Ok(url("fe80::1", 80, "")));
+        assert!("http://[::1".parse::<HttpUrl>().is_err());
+        assert!("http://[::1]4318".parse::<HttpUrl>().is_err());
+    }
+}
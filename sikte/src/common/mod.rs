@@ -1,4 +1,12 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
+pub mod capabilities;
+pub mod cgroup;
+pub mod clock;
 pub mod constants;
 pub mod generated_types;
 pub mod generic_types;
+pub mod http;
+pub mod process_selector;
+pub mod procfs;
+pub mod units;
+pub mod users;
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Selection of processes by name, executable or command line, like `pgrep`
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::PathBuf,
+};
+
+use libc::pid_t;
+use regex::Regex;
+
+use crate::common::{
+    generated_types::{Comm, CommExt},
+    procfs,
+};
+
+/// Which processes to trace
+#[derive(Debug, Clone)]
+pub enum ProcessSelector {
+    /// Command name, as in `/proc/<pid>/comm`. Longer names are truncated like the kernel does
+    Comm(String),
+    /// Path of the executable, with symbolic links resolved
+    Exe(PathBuf),
+    /// Regular expression matched against the whole command line, like `pgrep -f`
+    Pattern(Regex),
+}
+
+impl ProcessSelector {
+    /// Select processes by command name
+    pub fn comm(name: impl Into<String>) -> ProcessSelector {
+        ProcessSelector::Comm(name.into())
+    }
+
+    /// Select processes by executable. Symbolic links in the path, e.g. `/usr/bin/python3`, are
+    /// resolved now, since `/proc/<pid>/exe` points to the actual file
+    pub fn exe(path: impl Into<PathBuf>) -> ProcessSelector {
+        let path = path.into();
+        ProcessSelector::Exe(fs::canonicalize(&path).unwrap_or(path))
+    }
+
+    /// Select processes whose command line matches a regular expression
+    pub fn pattern(pattern: &str) -> Result<ProcessSelector, regex::Error> {
+        Ok(ProcessSelector::Pattern(Regex::new(pattern)?))
+    }
+
+    /// Whether a process is selected. False if it exited, an error if it can't be told, e.g.
+    /// when reading the executable of another user's process without privileges
+    pub fn matches(&self, pid: pid_t) -> io::Result<bool> {
+        Ok(match self {
+            ProcessSelector::Comm(name) => procfs::comm(pid)
+                .is_some_and(|comm| comm == Comm::from(name.as_str()).to_string_lossy()),
+            ProcessSelector::Exe(path) => procfs::exe(pid)?.is_some_and(|exe| exe == *path),
+            ProcessSelector::Pattern(regex) => procfs::cmdline(pid)
+                .is_some_and(|cmdline| !cmdline.is_empty() && regex.is_match(&cmdline)),
+        })
+    }
+
+    /// Running processes selected, except sikte itself
+    pub fn find(&self) -> io::Result<Selected> {
+        let own_pid = std::process::id() as pid_t;
+        let mut selected = Selected::default();
+        for pid in procfs::pids()?.into_iter().filter(|&pid| pid != own_pid) {
+            match self.matches(pid) {
+                Ok(true) => selected.pids.push(pid),
+                Ok(false) => {}
+                Err(e) => selected.unreadable.push((pid, e)),
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Running processes found by [`ProcessSelector::find`]
+#[derive(Debug, Default)]
+pub struct Selected {
+    pub pids: Vec<pid_t>,
+    /// Processes that may be selected but couldn't be checked, with the reason
+    pub unreadable: Vec<(pid_t, io::Error)>,
+}
+
+impl Display for ProcessSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessSelector::Comm(name) => write!(f, "command name {name}"),
+            ProcessSelector::Exe(path) => write!(f, "executable {}", path.display()),
+            ProcessSelector::Pattern(regex) => write!(f, "command line matching {regex}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::capabilities::{CapabilitySet, drop_capabilities};
+
+    #[test]
+    fn test_match_truncated_comm() {
+        // Test binaries have names like `sikte-0123456789abcdef`, longer than command names
+        let own_pid = std::process::id() as pid_t;
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_name().unwrap().to_str().unwrap();
+        assert!(name.len() > 15);
+        assert!(ProcessSelector::comm(name).matches(own_pid).unwrap());
+    }
+
+    #[test]
+    fn test_match_own_process() {
+        // The test binary matches itself, but like sikte, `find` leaves it out
+        let own_pid = std::process::id() as pid_t;
+        let comm = procfs::comm(own_pid).unwrap();
+        assert!(ProcessSelector::comm(&comm).matches(own_pid).unwrap());
+        assert!(!ProcessSelector::comm("x").matches(own_pid).unwrap());
+
+        let exe = std::env::current_exe().unwrap();
+        assert!(ProcessSelector::exe(&exe).matches(own_pid).unwrap());
+        assert!(
+            !ProcessSelector::exe("/nonexistent")
+                .matches(own_pid)
+                .unwrap()
+        );
+
+        let cmdline = procfs::cmdline(own_pid).unwrap();
+        let pattern = ProcessSelector::pattern(&regex::escape(&cmdline)).unwrap();
+        assert!(pattern.matches(own_pid).unwrap());
+        assert!(!pattern.find().unwrap().pids.contains(&own_pid));
+    }
+
+    #[test]
+    fn test_unreadable_executable() {
+        // Capabilities belong to threads, so the other tests keep theirs. Without them, the
+        // executable of init can't be read, whoever runs the tests
+        std::thread::spawn(|| {
+            drop_capabilities(CapabilitySet::EMPTY).unwrap();
+            let selector = ProcessSelector::exe("/sbin/init");
+            let e = selector.matches(1).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+            assert!(
+                selector
+                    .find()
+                    .unwrap()
+                    .unreadable
+                    .iter()
+                    .any(|&(pid, _)| pid == 1)
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Helpers for reading process information from `/proc`
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use libc::pid_t;
+
+/// Inode number of the initial PID namespace, which the kernel fixes (`PROC_PID_INIT_INO`)
+pub const PROC_PID_INIT_INO: u64 = 0xEFFF_FFFC;
+
+/// Command name of a process or thread, as found in `/proc/<pid>/comm`
+pub fn comm(pid: pid_t) -> Option<String> {
+    fs::read_to_string(format!("/proc/{pid}/comm"))
+        .ok()
+        .map(|comm| comm.trim_end().to_string())
+}
+
+/// Path of the executable of a process, if it still runs and isn't a kernel thread. Reading it
+/// needs the same privileges as tracing the process, and fails with `PermissionDenied` without
+pub fn exe(pid: pid_t) -> io::Result<Option<PathBuf>> {
+    match fs::read_link(format!("/proc/{pid}/exe")) {
+        Ok(path) => Ok(Some(path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Command line of a process, with its arguments separated by spaces. Empty for kernel threads
+pub fn cmdline(pid: pid_t) -> Option<String> {
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    Some(
+        cmdline
+            .split(|&byte| byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Whether a process or thread exists
+pub fn exists(pid: pid_t) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// PIDs of the running processes. Only main threads are listed in `/proc`
+pub fn pids() -> io::Result<Vec<pid_t>> {
+    let mut pids = vec![];
+    for entry in fs::read_dir("/proc")? {
+        if let Some(pid) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// Inode number of the PID namespace of a process, as in `/proc/<pid>/ns/pid`
+pub fn pid_namespace(pid: pid_t) -> Option<u64> {
+    fs::metadata(format!("/proc/{pid}/ns/pid"))
+        .ok()
+        .map(|metadata| metadata.ino())
+}
+
+/// ID of a process in its own PID namespace: the last one of the `NSpid` line of its status
+pub fn ns_pid(pid: pid_t) -> Option<pid_t> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    parse_ns_pid(&status)
+}
+
+fn parse_ns_pid(status: &str) -> Option<pid_t> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+/// Parent of a process, as in the `PPid` line of its status. None if it exited, or for init
+pub fn parent(pid: pid_t) -> Option<pid_t> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    parse_parent(&status)
+}
+
+fn parse_parent(status: &str) -> Option<pid_t> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("PPid:"))?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&ppid| ppid > 0)
+}
+
+/// A process and its ancestors up to the leader of its session, e.g. the shell it was started
+/// from, or up to init, which is left out
+pub fn session_ancestors(pid: pid_t) -> Vec<pid_t> {
+    // SAFETY: getsid has no preconditions, and fails with -1 if the process exited
+    let leader = unsafe { libc::getsid(pid) };
+    ancestors(pid, leader, parent)
+}
+
+fn ancestors(pid: pid_t, leader: pid_t, parent: impl Fn(pid_t) -> Option<pid_t>) -> Vec<pid_t> {
+    let mut pids = vec![pid];
+    let mut current = pid;
+    while current != leader {
+        match parent(current) {
+            Some(ppid) if ppid > 1 && !pids.contains(&ppid) => {
+                pids.push(ppid);
+                current = ppid;
+            }
+            _ => break,
+        }
+    }
+    pids
+}
+
+/// IDs of processes in the initial PID namespace, from their IDs in the PID namespace with the
+/// given inode number. Processes of nested namespaces aren't found
+pub fn host_pids(pid_ns: u64, ns_pids: &[pid_t]) -> io::Result<Vec<Option<pid_t>>> {
+    let mut host_pids = vec![None; ns_pids.len()];
+    for pid in pids()? {
+        if pid_namespace(pid) != Some(pid_ns) {
+            continue;
+        }
+        if let Some(ns_pid) = ns_pid(pid)
+            && let Some(i) = ns_pids.iter().position(|&wanted| wanted == ns_pid)
+        {
+            host_pids[i] = Some(pid);
+        }
+    }
+    Ok(host_pids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ns_pid() {
+        assert_eq!(
+            parse_ns_pid("Name:\tsh\nNSpid:\t4242\t7\nNSpgid:\t1\n"),
+            Some(7)
+        );
+        assert_eq!(parse_ns_pid("Name:\tsh\nNSpid:\t4242\n"), Some(4242));
+        assert_eq!(parse_ns_pid("Name:\tsh\n"), None);
+    }
+
+    #[test]
+    fn test_parse_parent() {
+        assert_eq!(
+            parse_parent("Name:\tsh\nPid:\t4242\nPPid:\t4200\n"),
+            Some(4200)
+        );
+        assert_eq!(parse_parent("Name:\tsystemd\nPid:\t1\nPPid:\t0\n"), None);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let parents = [(100, 90), (90, 80), (80, 1)];
+        let parent = |pid| {
+            parents
+                .iter()
+                .find(|&&(child, _)| child == pid)
+                .map(|&(_, parent)| parent)
+        };
+        assert_eq!(ancestors(100, 90, parent), [100, 90]);
+        assert_eq!(ancestors(100, 100, parent), [100]);
+        // Init is never part of them, e.g. when the session leader is in another namespace
+        assert_eq!(ancestors(100, 0, parent), [100, 90, 80]);
+    }
+
+    #[test]
+    fn test_session_ancestors_include_parent() {
+        let own_pid = std::process::id() as pid_t;
+        let ancestors = session_ancestors(own_pid);
+        assert_eq!(ancestors[0], own_pid);
+        // SAFETY: neither has preconditions
+        let (leader, ppid) = unsafe { (libc::getsid(0), libc::getppid()) };
+        if leader != own_pid && ppid > 1 {
+            assert!(ancestors.contains(&ppid));
+        }
+    }
+
+    #[test]
+    fn test_host_pids_of_own_namespace() {
+        let own_pid = std::process::id() as pid_t;
+        let pid_ns = pid_namespace(own_pid).unwrap();
+        let own_ns_pid = ns_pid(own_pid).unwrap();
+        assert_eq!(
+            host_pids(pid_ns, &[own_ns_pid, -1]).unwrap(),
+            [Some(own_pid), None]
+        );
+    }
+}
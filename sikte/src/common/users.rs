@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Lookup of users and groups by name, through the system's name service like `id`
+use std::{ffi::CString, io, mem::MaybeUninit, ptr};
+
+/// Initial size of the buffer for the strings of an entry, doubled until they fit
+const INITIAL_BUFFER_SIZE: usize = 1024;
+
+/// `getpwnam_r` or `getgrnam_r`, which look up an entry of type `T` by name
+type GetByName<T> = unsafe extern "C" fn(
+    *const libc::c_char,
+    *mut T,
+    *mut libc::c_char,
+    libc::size_t,
+    *mut *mut T,
+) -> libc::c_int;
+
+/// ID of a user, if it exists
+pub fn uid(name: &str) -> io::Result<Option<u32>> {
+    lookup(name, libc::getpwnam_r, |entry| entry.pw_uid)
+}
+
+/// ID of a group, if it exists
+pub fn gid(name: &str) -> io::Result<Option<u32>> {
+    lookup(name, libc::getgrnam_r, |entry| entry.gr_gid)
+}
+
+/// ID of the entry found by `get_by_name`, if it exists
+fn lookup<T>(name: &str, get_by_name: GetByName<T>, id: fn(&T) -> u32) -> io::Result<Option<u32>> {
+    let name = c_name(name)?;
+    let mut buffer = vec![0; INITIAL_BUFFER_SIZE];
+    loop {
+        let mut entry = MaybeUninit::<T>::uninit();
+        let mut result = ptr::null_mut();
+        let ret = unsafe {
+            get_by_name(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            // SAFETY: `result` points to `entry` when it was found
+            0 => return Ok((!result.is_null()).then(|| id(unsafe { &*result }))),
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            _ => return Err(io::Error::from_raw_os_error(ret)),
+        }
+    }
+}
+
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(uid("root").unwrap(), Some(0));
+        assert_eq!(gid("root").unwrap(), Some(0));
+        assert_eq!(uid("sikte-no-such-user").unwrap(), None);
+        assert_eq!(gid("sikte-no-such-group").unwrap(), None);
+        assert!(uid("nul\0byte").is_err());
+    }
+}
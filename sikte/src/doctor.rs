@@ -0,0 +1,489 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Checks of whether the system can run sikte, for `sikte doctor`
+use std::{fmt::Write, fs};
+
+use libbpf_rs::{MapType, ProgramType};
+use serde_json::{Value, json};
+
+use crate::{
+    common::capabilities::CapabilitySet,
+    ebpf::diagnostics::{VMLINUX_BTF, btf_available, lockdown_mode, unprivileged_bpf_disabled},
+};
+
+/// Oldest kernel with BPF ring buffers, CAP_BPF and CAP_PERFMON
+const RECOMMENDED_KERNEL_VERSION: (u32, u32) = (5, 8);
+/// Oldest kernel with read-only global data, which the eBPF programs need
+const MIN_KERNEL_VERSION: (u32, u32) = (5, 2);
+const OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+const BPF_JIT_ENABLE: &str = "/proc/sys/net/core/bpf_jit_enable";
+const MOUNTS: &str = "/proc/self/mounts";
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Fine
+    Ok,
+    /// sikte may work, but not as well
+    Warn,
+    /// sikte can't trace
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// Result of checking a single requirement
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// Short identifier, e.g. `btf`
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found, as a JSON value so that scripts can inspect it
+    pub value: Value,
+    /// Human readable description of what was found
+    pub detail: String,
+    /// How to fix a failure or warning
+    pub hint: Option<&'static str>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, value: Value, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            value,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: &'static str) -> Check {
+        if self.status != Status::Ok {
+            self.hint = Some(hint);
+        }
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut json = json!({
+            "name": self.name,
+            "status": self.status.as_str(),
+            "value": self.value,
+            "detail": self.detail,
+        });
+        if let Some(hint) = self.hint {
+            json["hint"] = json!(hint);
+        }
+        json
+    }
+}
+
+/// Results of every check
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Whether no check failed
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Check the running system
+    pub fn run() -> Report {
+        let checks = vec![
+            check_kernel(),
+            check_btf(),
+            probe_check(
+                "ring_buffer",
+                MapType::RingBuf.is_supported(),
+                "BPF ring buffer",
+                "sikte falls back to per-CPU perf buffers, which cost more",
+            ),
+            probe_check(
+                "raw_tracepoint",
+                ProgramType::RawTracepoint.is_supported(),
+                "raw tracepoint programs",
+                "sikte falls back to classic tracepoints, which need tracefs",
+            ),
+            check_capabilities(),
+            check_unprivileged_bpf(),
+            check_lockdown(),
+            check_memlock(),
+            check_mount("tracefs", "tracefs"),
+            check_mount("debugfs", "debugfs"),
+            check_jit(),
+        ];
+        Report::new(checks)
+    }
+
+    fn new(checks: Vec<Check>) -> Report {
+        let ok = checks.iter().all(|check| check.status != Status::Fail);
+        Report { ok, checks }
+    }
+
+    /// Render as a line per check, with hints below the checks that need attention
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Ok => "  ok",
+                Status::Warn => "warn",
+                Status::Fail => "FAIL",
+            };
+            let _ = writeln!(out, "[{status}] {:<16} {}", check.name, check.detail);
+            if let Some(hint) = check.hint {
+                let _ = writeln!(out, "       hint: {hint}");
+            }
+        }
+        let _ = writeln!(
+            out,
+            "{}",
+            if self.ok {
+                "sikte can trace on this system"
+            } else {
+                "sikte can't trace on this system"
+            }
+        );
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ok": self.ok,
+            "checks": self.checks.iter().map(Check::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Parse the major and minor numbers of a kernel release, e.g. `6.1.0-18-amd64`
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse().ok());
+    Some((numbers.next()??, numbers.next()??))
+}
+
+fn check_kernel() -> Check {
+    let Ok(release) = fs::read_to_string(OSRELEASE) else {
+        return Check::new("kernel", Status::Warn, Value::Null, "unknown version");
+    };
+    let release = release.trim();
+    let (status, hint) = match parse_kernel_version(release) {
+        Some(version) if version >= RECOMMENDED_KERNEL_VERSION => (Status::Ok, ""),
+        Some(version) if version >= MIN_KERNEL_VERSION => (
+            Status::Warn,
+            "sikte uses fallbacks before Linux 5.8, and needs root",
+        ),
+        Some(_) => (Status::Fail, "sikte needs Linux 5.2 or later"),
+        None => (Status::Warn, "sikte needs Linux 5.2 or later"),
+    };
+    Check::new("kernel", status, json!(release), format!("Linux {release}")).hint(hint)
+}
+
+fn check_btf() -> Check {
+    let available = btf_available();
+    let (status, detail) = if available {
+        (Status::Ok, format!("{VMLINUX_BTF} is available"))
+    } else {
+        (Status::Fail, format!("{VMLINUX_BTF} is missing"))
+    };
+    Check::new("btf", status, json!(available), detail)
+        .hint("use a kernel built with CONFIG_DEBUG_INFO_BTF=y")
+}
+
+/// Check support of a kernel feature probed through libbpf. Probing needs privileges. sikte
+/// falls back to an older mechanism when a feature is missing
+fn probe_check(
+    name: &'static str,
+    supported: libbpf_rs::Result<bool>,
+    feature: &str,
+    fallback: &'static str,
+) -> Check {
+    match supported {
+        Ok(true) => Check::new(
+            name,
+            Status::Ok,
+            json!(true),
+            format!("{feature} supported"),
+        ),
+        Ok(false) => Check::new(
+            name,
+            Status::Warn,
+            json!(false),
+            format!("{feature} not supported"),
+        )
+        .hint(fallback),
+        Err(e) => Check::new(
+            name,
+            Status::Warn,
+            Value::Null,
+            format!("couldn't probe {feature}: {e}"),
+        )
+        .hint("run sikte doctor with the privileges sikte runs with"),
+    }
+}
+
+fn check_capabilities() -> Check {
+    let (effective, permitted) = match (CapabilitySet::effective(), CapabilitySet::permitted()) {
+        (Ok(effective), Ok(permitted)) => (effective, permitted),
+        (Err(e), _) | (_, Err(e)) => {
+            return Check::new(
+                "capabilities",
+                Status::Warn,
+                Value::Null,
+                format!("couldn't read capabilities: {e}"),
+            );
+        }
+    };
+
+    // sikte makes the permitted capabilities it needs effective
+    let missing = effective.union(permitted).missing_for_tracing();
+    let (status, detail) = if missing.is_empty() {
+        (
+            Status::Ok,
+            format!("permitted capabilities include {permitted}"),
+        )
+    } else {
+        (Status::Fail, format!("missing {}", missing.join(", ")))
+    };
+    Check::new(
+        "capabilities",
+        status,
+        json!({
+            "effective": format!("{:016x}", effective.0),
+            "permitted": format!("{:016x}", permitted.0),
+            "missing": missing,
+        }),
+        detail,
+    )
+    .hint("run sikte as root, or `sudo setcap cap_bpf,cap_perfmon+ep $(which sikte)`")
+}
+
+fn check_unprivileged_bpf() -> Check {
+    let Some(value) = unprivileged_bpf_disabled() else {
+        return Check::new(
+            "unprivileged_bpf",
+            Status::Warn,
+            Value::Null,
+            "kernel.unprivileged_bpf_disabled is unknown",
+        );
+    };
+    let privileged = match (CapabilitySet::effective(), CapabilitySet::permitted()) {
+        (Ok(effective), Ok(permitted)) => {
+            effective.union(permitted).missing_for_tracing().is_empty()
+        }
+        _ => false,
+    };
+    let (status, detail, hint) = unprivileged_bpf_status(value, privileged);
+    Check::new("unprivileged_bpf", status, json!(value), detail).hint(hint)
+}
+
+/// Status of a `kernel.unprivileged_bpf_disabled` value, given whether sikte has the
+/// capabilities to load eBPF programs anyway
+fn unprivileged_bpf_status(value: u32, privileged: bool) -> (Status, String, &'static str) {
+    match value {
+        0 => (
+            Status::Warn,
+            "unprivileged users can load eBPF programs (kernel.unprivileged_bpf_disabled = 0)"
+                .to_string(),
+            "sikte doesn't need it: `sysctl kernel.unprivileged_bpf_disabled=2` reduces the \
+             attack surface",
+        ),
+        1 | 2 if privileged => (
+            Status::Ok,
+            format!(
+                "kernel.unprivileged_bpf_disabled = {value}, sikte has the capabilities to load eBPF"
+            ),
+            "",
+        ),
+        1 | 2 => (
+            Status::Fail,
+            format!(
+                "kernel.unprivileged_bpf_disabled = {value} and sikte lacks the capabilities to \
+                 load eBPF"
+            ),
+            "run sikte as root, or `sudo setcap cap_bpf,cap_perfmon+ep $(which sikte)`",
+        ),
+        _ => (
+            Status::Warn,
+            format!("unknown kernel.unprivileged_bpf_disabled = {value}"),
+            "the kernel documents 0, 1 and 2 only",
+        ),
+    }
+}
+
+fn check_lockdown() -> Check {
+    match lockdown_mode() {
+        Some(mode) => {
+            let status = if mode == "confidentiality" {
+                Status::Fail
+            } else {
+                Status::Ok
+            };
+            Check::new(
+                "lockdown",
+                status,
+                json!(mode),
+                format!("lockdown is {mode}"),
+            )
+            .hint("boot with lockdown=integrity, or disable Secure Boot")
+        }
+        None => Check::new(
+            "lockdown",
+            Status::Ok,
+            Value::Null,
+            "lockdown is not supported",
+        ),
+    }
+}
+
+fn format_rlimit(limit: libc::rlim_t) -> String {
+    if limit == libc::RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        format!("{limit} bytes")
+    }
+}
+
+fn check_memlock() -> Check {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Check::new(
+            "memlock",
+            Status::Warn,
+            Value::Null,
+            format!("couldn't read the locked memory limit: {e}"),
+        );
+    }
+
+    let status = if rlim.rlim_cur == libc::RLIM_INFINITY {
+        Status::Ok
+    } else {
+        Status::Warn
+    };
+    let limit = |limit| {
+        if limit == libc::RLIM_INFINITY {
+            Value::Null
+        } else {
+            json!(limit)
+        }
+    };
+    Check::new(
+        "memlock",
+        status,
+        json!({ "soft": limit(rlim.rlim_cur), "hard": limit(rlim.rlim_max) }),
+        format!(
+            "locked memory limit is {} (hard limit {})",
+            format_rlimit(rlim.rlim_cur),
+            format_rlimit(rlim.rlim_max)
+        ),
+    )
+    .hint("kernels older than 5.11 need `ulimit -l unlimited`, which sikte sets as root")
+}
+
+/// Mount points of a filesystem type in `/proc/self/mounts`
+fn mount_points<'a>(mounts: &'a str, fs_type: &str) -> Vec<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?;
+            (fields.next()? == fs_type).then_some(mount_point)
+        })
+        .collect()
+}
+
+fn check_mount(name: &'static str, fs_type: &str) -> Check {
+    let mounts = fs::read_to_string(MOUNTS).unwrap_or_default();
+    let points = mount_points(&mounts, fs_type);
+    match points.first() {
+        Some(point) => Check::new(
+            name,
+            Status::Ok,
+            json!(points),
+            format!("mounted at {point}"),
+        ),
+        None => Check::new(name, Status::Warn, json!(points), "not mounted")
+            .hint("only needed by the classic tracepoints fallback; mount it under /sys/kernel"),
+    }
+}
+
+fn check_jit() -> Check {
+    let value: Option<u32> = fs::read_to_string(BPF_JIT_ENABLE)
+        .ok()
+        .and_then(|value| value.trim().parse().ok());
+    let (status, detail) = match value {
+        Some(0) => (Status::Warn, "BPF JIT is disabled".to_string()),
+        Some(value) => (Status::Ok, format!("BPF JIT is enabled ({value})")),
+        None => (Status::Warn, "BPF JIT state is unknown".to_string()),
+    };
+    Check::new("bpf_jit", status, json!(value), detail)
+        .hint("`sysctl net.core.bpf_jit_enable=1` makes tracing cheaper")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("6.1.0-18-amd64"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("5.15.153.1-microsoft"), Some((5, 15)));
+        assert_eq!(parse_kernel_version("garbage"), None);
+    }
+
+    #[test]
+    fn test_mount_points() {
+        let mounts = "sysfs /sys sysfs rw 0 0\n\
+                      tracefs /sys/kernel/tracing tracefs rw 0 0\n\
+                      debugfs /sys/kernel/debug debugfs rw 0 0\n";
+        assert_eq!(mount_points(mounts, "tracefs"), ["/sys/kernel/tracing"]);
+        assert!(mount_points(mounts, "bpf").is_empty());
+    }
+
+    #[test]
+    fn test_unprivileged_bpf_status() {
+        assert_eq!(unprivileged_bpf_status(0, true).0, Status::Warn);
+        assert_eq!(unprivileged_bpf_status(2, true).0, Status::Ok);
+        assert_eq!(unprivileged_bpf_status(1, false).0, Status::Fail);
+        assert_eq!(unprivileged_bpf_status(2, false).0, Status::Fail);
+        assert_eq!(unprivileged_bpf_status(7, true).0, Status::Warn);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report::new(vec![
+            Check::new("btf", Status::Ok, json!(true), "available").hint("unused"),
+            Check::new("bpf_jit", Status::Warn, json!(0), "disabled").hint("enable it"),
+        ]);
+        assert!(report.ok);
+
+        let json = report.to_json();
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["checks"][0]["status"], "ok");
+        assert!(json["checks"][0].get("hint").is_none());
+        assert_eq!(json["checks"][1]["hint"], "enable it");
+
+        let text = report.to_text();
+        assert!(text.contains("[warn] bpf_jit"), "{text}");
+        assert!(text.contains("hint: enable it"), "{text}");
+
+        let report = Report::new(vec![Check::new(
+            "btf",
+            Status::Fail,
+            json!(false),
+            "missing",
+        )]);
+        assert!(!report.ok);
+    }
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use super::{error::EbpfError, features::KernelFeatures};
+use crate::common::constants::map_sizes::{DEFAULT_MAX_PIDS, DEFAULT_RING_BUFFER_SIZE};
+
+/// Settings applied to the eBPF maps and programs before they are loaded in the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EbpfConfig {
+    /// Size of the `SYSCALL_EVENTS` ring buffer in bytes. When falling back to perf buffers, it
+    /// is split between the CPUs
+    pub ring_buffer_size: u32,
+    /// Maximum number of processes in `PID_ALLOW_LIST`
+    pub max_pids: u32,
+    /// Kernel features to use, or `None` to probe the kernel for them
+    pub features: Option<KernelFeatures>,
+}
+
+impl Default for EbpfConfig {
+    fn default() -> Self {
+        EbpfConfig {
+            ring_buffer_size: DEFAULT_RING_BUFFER_SIZE,
+            max_pids: DEFAULT_MAX_PIDS,
+            features: None,
+        }
+    }
+}
+
+impl EbpfConfig {
+    /// Check that the kernel will accept these settings
+    pub fn validate(&self) -> Result<(), EbpfError> {
+        Self::check_ring_buffer_size(self.ring_buffer_size.into())?;
+        if self.max_pids == 0 {
+            return Err(EbpfError::InvalidMaxPids(self.max_pids));
+        }
+        Ok(())
+    }
+
+    /// The kernel requires ring buffer sizes to be a power of two and a multiple of the page size
+    pub fn check_ring_buffer_size(size: u64) -> Result<u32, EbpfError> {
+        let page_size = page_size();
+        match u32::try_from(size) {
+            Ok(size) if size.is_power_of_two() && size % page_size == 0 => Ok(size),
+            _ => Err(EbpfError::InvalidRingBufferSize { size, page_size }),
+        }
+    }
+
+    /// Number of pages of each per-CPU perf buffer, which must be a power of two, so that all of
+    /// them together take about as much memory as the ring buffer
+    pub fn perf_buffer_pages(&self, cpus: usize) -> usize {
+        let pages = self.ring_buffer_size as usize / page_size() as usize / cpus.max(1);
+        match pages.checked_ilog2() {
+            Some(log) => 1 << log,
+            None => 1,
+        }
+    }
+}
+
+/// Size of a memory page in bytes
+fn page_size() -> u32 {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    u32::try_from(size).unwrap_or(4096)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_size_validation() {
+        let page_size = page_size() as u64;
+        assert!(EbpfConfig::check_ring_buffer_size(page_size).is_ok());
+        assert!(EbpfConfig::check_ring_buffer_size(64 << 20).is_ok());
+        assert!(EbpfConfig::check_ring_buffer_size(0).is_err());
+        assert!(EbpfConfig::check_ring_buffer_size(page_size / 2).is_err());
+        assert!(EbpfConfig::check_ring_buffer_size(3 * page_size).is_err());
+        assert!(EbpfConfig::check_ring_buffer_size(1 << 32).is_err());
+
+        assert!(EbpfConfig::default().validate().is_ok());
+        let no_pids = EbpfConfig {
+            max_pids: 0,
+            ..EbpfConfig::default()
+        };
+        assert!(no_pids.validate().is_err());
+    }
+
+    #[test]
+    fn test_perf_buffer_pages() {
+        let page_size = page_size();
+        let config = EbpfConfig {
+            ring_buffer_size: 64 * page_size,
+            ..EbpfConfig::default()
+        };
+        assert_eq!(config.perf_buffer_pages(1), 64);
+        assert_eq!(config.perf_buffer_pages(4), 16);
+        // rounded down to a power of two
+        assert_eq!(config.perf_buffer_pages(3), 16);
+        // never empty
+        assert_eq!(config.perf_buffer_pages(128), 1);
+        assert_eq!(config.perf_buffer_pages(0), 64);
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Probes of the system conditions that keep eBPF programs from loading, to explain failures
+use std::{fs, path::Path, sync::Mutex};
+
+use libbpf_rs::PrintLevel;
+use log::{debug, warn};
+
+use super::error::EbpfError;
+use crate::common::capabilities::CapabilitySet;
+
+/// BTF of the running kernel, needed for CO-RE relocations
+pub const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+/// Sysctl that restricts eBPF to privileged users when non-zero
+const UNPRIVILEGED_BPF_DISABLED: &str = "/proc/sys/kernel/unprivileged_bpf_disabled";
+/// Current kernel lockdown mode, between brackets
+const LOCKDOWN: &str = "/sys/kernel/security/lockdown";
+/// Lockdown mode that forbids eBPF programs from reading kernel memory
+const LOCKDOWN_CONFIDENTIALITY: &str = "confidentiality";
+
+const PROG_LOAD_LOG_BEGIN: &str = "-- BEGIN PROG LOAD LOG --";
+const PROG_LOAD_LOG_END: &str = "-- END PROG LOAD LOG --";
+
+/// Whether the kernel exposes its BTF
+pub fn btf_available() -> bool {
+    Path::new(VMLINUX_BTF).exists()
+}
+
+/// Value of `kernel.unprivileged_bpf_disabled`, if it can be read
+pub fn unprivileged_bpf_disabled() -> Option<u32> {
+    fs::read_to_string(UNPRIVILEGED_BPF_DISABLED)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Current kernel lockdown mode, e.g. `none` or `integrity`, if the kernel supports lockdown
+pub fn lockdown_mode() -> Option<String> {
+    parse_lockdown(&fs::read_to_string(LOCKDOWN).ok()?).map(str::to_string)
+}
+
+/// Extract the selected mode of e.g. `none [integrity] confidentiality`
+fn parse_lockdown(modes: &str) -> Option<&str> {
+    let start = modes.find('[')? + 1;
+    let end = start + modes[start..].find(']')?;
+    Some(&modes[start..end])
+}
+
+/// libbpf messages printed while capturing, which include the verifier logs
+static LIBBPF_LOG: Mutex<String> = Mutex::new(String::new());
+
+fn capture_print(level: PrintLevel, message: String) {
+    match level {
+        PrintLevel::Warn => warn!("libbpf: {}", message.trim_end()),
+        PrintLevel::Info | PrintLevel::Debug => debug!("libbpf: {}", message.trim_end()),
+    }
+    LIBBPF_LOG
+        .lock()
+        .expect("libbpf log lock poisoned")
+        .push_str(&message);
+}
+
+/// Run `f` while capturing the messages of libbpf, and return them too. Messages of other
+/// threads loading eBPF at the same time are captured as well
+pub(super) fn capture_libbpf_log<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let previous = libbpf_rs::set_print(Some((PrintLevel::Info, capture_print)));
+    let result = f();
+    libbpf_rs::set_print(previous);
+    let log = std::mem::take(&mut *LIBBPF_LOG.lock().expect("libbpf log lock poisoned"));
+    (result, log)
+}
+
+/// Find the program the verifier rejected and its log in the messages of libbpf
+fn verifier_log(libbpf_log: &str) -> Option<(String, String)> {
+    let begin = libbpf_log.find(PROG_LOAD_LOG_BEGIN)?;
+    let line_start = libbpf_log[..begin].rfind('\n').map_or(0, |i| i + 1);
+    let program = libbpf_log[line_start..begin]
+        .split('\'')
+        .nth(1)
+        .unwrap_or("unknown")
+        .to_string();
+
+    let body_start = begin + PROG_LOAD_LOG_BEGIN.len();
+    let body_end = libbpf_log[body_start..]
+        .find(PROG_LOAD_LOG_END)
+        .map_or(libbpf_log.len(), |i| body_start + i);
+    let log = libbpf_log[body_start..body_end].trim().to_string();
+    Some((program, log))
+}
+
+/// Explain a failure by the capabilities sikte lacks, or give the error back if it has them all
+fn diagnose_capabilities(source: libbpf_rs::Error) -> Result<EbpfError, libbpf_rs::Error> {
+    let missing = CapabilitySet::effective()
+        .map(CapabilitySet::missing_for_tracing)
+        .unwrap_or_default();
+    if missing.is_empty() {
+        return Err(source);
+    }
+
+    Ok(match unprivileged_bpf_disabled() {
+        Some(value) if value != 0 => EbpfError::UnprivilegedBpfDisabled {
+            value,
+            missing,
+            source,
+        },
+        _ => EbpfError::MissingCapabilities { missing, source },
+    })
+}
+
+/// Find out why attaching `program` to `attach_target` failed. Attaching needs `CAP_PERFMON`,
+/// which file capabilities may lack even though loading succeeded
+pub(super) fn diagnose_attach(
+    source: libbpf_rs::Error,
+    program: &'static str,
+    attach_target: &'static str,
+) -> EbpfError {
+    diagnose_capabilities(source)
+        .unwrap_or_else(|source| EbpfError::as_attach_error(source, program, attach_target))
+}
+
+/// Explain why `step` of loading failed, from the most to the least specific cause
+pub(super) fn diagnose(
+    source: libbpf_rs::Error,
+    step: &'static str,
+    libbpf_log: &str,
+) -> EbpfError {
+    if let Some(mode) = lockdown_mode()
+        && mode == LOCKDOWN_CONFIDENTIALITY
+    {
+        return EbpfError::Lockdown { mode, source };
+    }
+
+    let source = match diagnose_capabilities(source) {
+        Ok(error) => return error,
+        Err(source) => source,
+    };
+
+    if !btf_available() {
+        return EbpfError::MissingBtf { source };
+    }
+
+    if let Some((program, log)) = verifier_log(libbpf_log) {
+        return EbpfError::Verifier {
+            program,
+            log,
+            source,
+        };
+    }
+
+    EbpfError::as_load_error(source, step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lockdown() {
+        assert_eq!(
+            parse_lockdown("[none] integrity confidentiality\n"),
+            Some("none")
+        );
+        assert_eq!(
+            parse_lockdown("none integrity [confidentiality]\n"),
+            Some("confidentiality")
+        );
+        assert_eq!(parse_lockdown(""), None);
+    }
+
+    #[test]
+    fn test_verifier_log() {
+        let libbpf_log = "libbpf: prog 'sikte_raw_trace_point_at_enter': BPF program load \
+                          failed: Permission denied\n\
+                          libbpf: prog 'sikte_raw_trace_point_at_enter': -- BEGIN PROG LOAD LOG \
+                          --\n0: R1=ctx() R10=fp0\ninvalid mem access 'scalar'\n\
+                          -- END PROG LOAD LOG --\n\
+                          libbpf: failed to load object 'sikte'\n";
+
+        let (program, log) = verifier_log(libbpf_log).unwrap();
+        assert_eq!(program, "sikte_raw_trace_point_at_enter");
+        assert_eq!(log, "0: R1=ctx() R10=fp0\ninvalid mem access 'scalar'");
+        assert_eq!(verifier_log("libbpf: failed to open\n"), None);
+    }
+}
@@ -1,6 +1,10 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
+use std::io;
+
 use thiserror::Error;
 
+use super::diagnostics::VMLINUX_BTF;
+
 /// Error that may happen while dealing with eBPF
 #[derive(Error, Debug)]
 pub enum EbpfError {
@@ -20,6 +24,72 @@ pub enum EbpfError {
         attach_target: &'static str,
         source: libbpf_rs::Error,
     },
+    #[error("Problem when resizing eBPF map {}: {}", map, source)]
+    Resize {
+        map: &'static str,
+        source: libbpf_rs::Error,
+    },
+    #[error("Problem when disabling unused eBPF map {}: {}", map, source)]
+    DisableMap {
+        map: &'static str,
+        source: libbpf_rs::Error,
+    },
+    #[error(
+        "Invalid ring buffer size {size}: it must be a power of two and a multiple of the page \
+         size ({page_size} bytes)"
+    )]
+    InvalidRingBufferSize { size: u64, page_size: u32 },
+    #[error("Invalid maximum number of PIDs {0}: it must be at least 1")]
+    InvalidMaxPids(u32),
+    #[error(
+        "Missing capabilities {} to load eBPF programs: {}\nhint: run sikte as root, or grant it \
+         the capabilities with `sudo setcap cap_bpf,cap_perfmon+ep $(which sikte)`",
+        missing.join(", "),
+        source
+    )]
+    MissingCapabilities {
+        missing: Vec<&'static str>,
+        source: libbpf_rs::Error,
+    },
+    #[error(
+        "Unprivileged eBPF is disabled (kernel.unprivileged_bpf_disabled = {value}) and sikte \
+         lacks {}: {}\nhint: run sikte as root, or grant it CAP_BPF and CAP_PERFMON",
+        missing.join(", "),
+        source
+    )]
+    UnprivilegedBpfDisabled {
+        value: u32,
+        missing: Vec<&'static str>,
+        source: libbpf_rs::Error,
+    },
+    #[error(
+        "Kernel BTF is missing from {VMLINUX_BTF}, so CO-RE relocations can't be applied: \
+         {source}\nhint: use a kernel built with CONFIG_DEBUG_INFO_BTF=y"
+    )]
+    MissingBtf { source: libbpf_rs::Error },
+    #[error(
+        "Kernel lockdown in {mode} mode forbids eBPF programs from reading kernel memory: \
+         {source}\nhint: boot with lockdown=integrity, or disable Secure Boot"
+    )]
+    Lockdown {
+        mode: String,
+        source: libbpf_rs::Error,
+    },
+    #[error(
+        "The verifier rejected eBPF program {program}: {source}\n{log}\nhint: the kernel may be \
+         too old for sikte, please report an issue with this log"
+    )]
+    Verifier {
+        program: String,
+        log: String,
+        source: libbpf_rs::Error,
+    },
+    #[error(
+        "Failed to raise the locked memory limit: {0}\nhint: run sikte as root, grant it \
+         CAP_SYS_RESOURCE, or raise the limit with `ulimit -l unlimited`. Kernels since 5.11 don't \
+         need it"
+    )]
+    Memlock(#[source] io::Error),
     #[error("libbpf error: {0}")]
     Libbpf(#[from] libbpf_rs::Error),
 }
@@ -32,6 +102,14 @@ impl EbpfError {
         }
     }
 
+    pub fn as_resize_error(error: libbpf_rs::Error, map: &'static str) -> EbpfError {
+        EbpfError::Resize { map, source: error }
+    }
+
+    pub fn as_disable_map_error(error: libbpf_rs::Error, map: &'static str) -> EbpfError {
+        EbpfError::DisableMap { map, source: error }
+    }
+
     pub fn as_attach_error(
         error: libbpf_rs::Error,
         program: &'static str,
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Kernel features sikte prefers, but can do without on older kernels
+use libbpf_rs::{MapType, ProgramType};
+use log::{debug, warn};
+
+/// Kernel features the eBPF programs use when available. Missing ones are replaced by older
+/// mechanisms, so that a single binary runs on every supported kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelFeatures {
+    /// BPF ring buffer (Linux 5.8). Without it, events are written to per-CPU perf buffers
+    pub ring_buffer: bool,
+    /// Raw tracepoint programs (Linux 4.17). Without them, the classic `raw_syscalls`
+    /// tracepoints are used, which need tracefs
+    pub raw_tracepoints: bool,
+}
+
+impl KernelFeatures {
+    /// Every feature, as on recent kernels
+    pub const ALL: KernelFeatures = KernelFeatures {
+        ring_buffer: true,
+        raw_tracepoints: true,
+    };
+
+    /// Only the fallbacks, e.g. to test them on a recent kernel
+    pub const NONE: KernelFeatures = KernelFeatures {
+        ring_buffer: false,
+        raw_tracepoints: false,
+    };
+
+    /// Ask the kernel which features it supports. Probing needs the same privileges as loading,
+    /// so a feature that can't be probed is assumed to be supported, and loading reports the
+    /// actual problem
+    pub fn probe() -> KernelFeatures {
+        let features = KernelFeatures {
+            ring_buffer: supported("ring buffer", MapType::RingBuf.is_supported()),
+            raw_tracepoints: supported(
+                "raw tracepoints",
+                ProgramType::RawTracepoint.is_supported(),
+            ),
+        };
+        debug!("Probed kernel features: {features:?}");
+        features
+    }
+}
+
+fn supported(feature: &str, probed: libbpf_rs::Result<bool>) -> bool {
+    match probed {
+        Ok(true) => true,
+        Ok(false) => {
+            warn!("The kernel doesn't support {feature}, falling back to an older mechanism");
+            false
+        }
+        Err(e) => {
+            debug!("Failed to probe support of {feature}, assuming it is supported: {e}");
+            true
+        }
+    }
+}
@@ -1,8 +1,14 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{fs::File, marker::PhantomData, os::fd::AsRawFd};
+
 use libbpf_rs::MapCore;
 use libc::pid_t;
+use plain::Plain;
 
-use crate::common::generic_types::Unused;
+use crate::common::{
+    generated_types::{Comm, TraceConfig, dropped_event_kind},
+    generic_types::Unused,
+};
 
 /// Syscall ring buffer wrapper
 pub struct SyscallRingBuf<'a> {
@@ -20,24 +26,246 @@ impl<'a> SyscallRingBuf<'a> {
     }
 }
 
-/// PID allow list wrapper. It uses an eBPF hashmap internally, where the value is unused.
-pub struct PidAllowList<'a> {
+/// Per-CPU perf buffers wrapper, used when the kernel doesn't support ring buffers
+pub struct SyscallPerfBuf<'a> {
     map: &'a libbpf_rs::Map<'a>,
+    /// Size of each per-CPU buffer in pages
+    pages: usize,
 }
 
-impl<'a> PidAllowList<'a> {
-    pub fn new(map: &'a libbpf_rs::Map<'a>) -> Self {
-        PidAllowList { map }
+impl<'a> SyscallPerfBuf<'a> {
+    pub fn new(map: &'a libbpf_rs::Map<'a>, pages: usize) -> Self {
+        SyscallPerfBuf { map, pages }
+    }
+
+    /// Get reference to the underlying map
+    pub fn map(&self) -> &libbpf_rs::Map<'a> {
+        self.map
+    }
+
+    /// Size of each per-CPU buffer in pages
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+}
+
+/// Map the eBPF programs write syscall events to, depending on the kernel features
+pub enum SyscallEventsMap<'a> {
+    RingBuf(SyscallRingBuf<'a>),
+    PerfBuf(SyscallPerfBuf<'a>),
+}
+
+/// Set of keys of type `K` in an eBPF hash map, whose value is unused. It can also wrap a
+/// [`libbpf_rs::MapHandle`], which outlives the skeleton's borrow
+pub struct BpfSet<'a, K, M: MapCore = libbpf_rs::Map<'a>> {
+    map: &'a M,
+    key: PhantomData<K>,
+}
+
+impl<'a, K: Plain, M: MapCore> BpfSet<'a, K, M> {
+    pub fn new(map: &'a M) -> Self {
+        BpfSet {
+            map,
+            key: PhantomData,
+        }
     }
 
-    /// Insert a PID into the allowlist
-    pub fn insert(&self, pid: pid_t) -> Result<(), libbpf_rs::Error> {
-        let key = pid.to_ne_bytes();
+    /// Insert a key into the set
+    pub fn insert(&self, key: K) -> Result<(), libbpf_rs::Error> {
         let value: Unused = 0;
-        let value_bytes = value.to_ne_bytes();
+        // SAFETY: keys are integers or arrays of bytes, without padding
+        let key = unsafe { plain::as_bytes(&key) };
+        self.map
+            .update(key, &value.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+    }
 
+    /// Remove a key from the set
+    pub fn remove(&self, key: K) -> Result<(), libbpf_rs::Error> {
+        // SAFETY: keys are integers or arrays of bytes, without padding
+        self.map.delete(unsafe { plain::as_bytes(&key) })
+    }
+}
+
+/// PID allow list, as seen from the initial PID namespace
+pub type PidAllowList<'a, M = libbpf_rs::Map<'a>> = BpfSet<'a, pid_t, M>;
+
+/// PID deny list, checked when tracing every process
+pub type PidDenyList<'a> = BpfSet<'a, pid_t>;
+
+/// Deny list of command names, checked against the main thread of a process when tracing every
+/// process
+pub type CommDenyList<'a> = BpfSet<'a, Comm>;
+
+/// TID allow list, checked alongside the PID allow list, as seen from the initial PID namespace
+pub type TidAllowList<'a> = BpfSet<'a, pid_t>;
+
+/// Allow list of real user IDs
+pub type UidAllowList<'a> = BpfSet<'a, u32>;
+
+/// Allow list of real group IDs
+pub type GidAllowList<'a> = BpfSet<'a, u32>;
+
+/// Executable paths, as passed to `execve`, that start the tracing of the processes executing
+/// them
+pub type ExecPathList<'a> = BpfSet<'a, ExecPath>;
+
+/// Command names that start the tracing of the processes executing a file with that name
+pub type ExecCommList<'a> = BpfSet<'a, Comm>;
+
+/// Length of the executable paths compared on exec, including the NUL byte
+const EXEC_PATH_LEN: usize = 256;
+
+/// Executable path as compared on exec. Paths longer than 255 bytes are truncated and never
+/// match
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecPath([u8; EXEC_PATH_LEN]);
+
+// SAFETY: an array of bytes
+unsafe impl Plain for ExecPath {}
+
+impl From<&str> for ExecPath {
+    fn from(path: &str) -> ExecPath {
+        ExecPath(padded_key(path))
+    }
+}
+
+/// String truncated to leave room for a NUL byte, and padded with NUL bytes
+fn padded_key<const N: usize>(string: &str) -> [u8; N] {
+    let mut key = [0; N];
+    let len = string.len().min(N - 1);
+    key[..len].copy_from_slice(&string.as_bytes()[..len]);
+    key
+}
+
+/// Wrapper of the single-entry array holding the [`TraceConfig`] of the eBPF programs
+pub struct TraceConfigMap<'a> {
+    map: &'a libbpf_rs::Map<'a>,
+}
+
+impl<'a> TraceConfigMap<'a> {
+    pub fn new(map: &'a libbpf_rs::Map<'a>) -> Self {
+        TraceConfigMap { map }
+    }
+
+    /// Replace the configuration
+    pub fn set(&self, config: &TraceConfig) -> Result<(), libbpf_rs::Error> {
+        let key: u32 = 0;
+        // SAFETY: the struct only has fields of the same size, so no padding
+        let value = unsafe { plain::as_bytes(config) };
         self.map
-            .update(&key, &value_bytes, libbpf_rs::MapFlags::ANY)?;
-        Ok(())
+            .update(&key.to_ne_bytes(), value, libbpf_rs::MapFlags::ANY)
+    }
+}
+
+/// Wrapper of the single-entry cgroup array holding the cgroup v2 traced with its descendants
+pub struct TracedCgroupMap<'a> {
+    map: &'a libbpf_rs::Map<'a>,
+}
+
+impl<'a> TracedCgroupMap<'a> {
+    pub fn new(map: &'a libbpf_rs::Map<'a>) -> Self {
+        TracedCgroupMap { map }
+    }
+
+    /// Trace the cgroup of an open directory. The map keeps a reference to the cgroup, so the
+    /// directory may be closed afterwards
+    pub fn set(&self, dir: &File) -> Result<(), libbpf_rs::Error> {
+        let key: u32 = 0;
+        let fd = dir.as_raw_fd() as u32;
+        self.map.update(
+            &key.to_ne_bytes(),
+            &fd.to_ne_bytes(),
+            libbpf_rs::MapFlags::ANY,
+        )
+    }
+}
+
+/// Number of events dropped on every CPU because the ring buffer was full
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DroppedCounts {
+    /// Dropped sys_enter events, indexed by CPU
+    pub at_enter: Vec<u64>,
+    /// Dropped sys_exit events, indexed by CPU
+    pub at_exit: Vec<u64>,
+}
+
+impl DroppedCounts {
+    /// Dropped sys_enter events over all CPUs
+    pub fn total_at_enter(&self) -> u64 {
+        self.at_enter.iter().sum()
+    }
+
+    /// Dropped sys_exit events over all CPUs
+    pub fn total_at_exit(&self) -> u64 {
+        self.at_exit.iter().sum()
+    }
+
+    /// Dropped events over all CPUs
+    pub fn total(&self) -> u64 {
+        self.total_at_enter() + self.total_at_exit()
+    }
+
+    /// Iterate over `(cpu, dropped sys_enter, dropped sys_exit)` of the CPUs that dropped events
+    pub fn per_cpu(&self) -> impl Iterator<Item = (usize, u64, u64)> + '_ {
+        self.at_enter
+            .iter()
+            .zip(self.at_exit.iter())
+            .enumerate()
+            .map(|(cpu, (&at_enter, &at_exit))| (cpu, at_enter, at_exit))
+            .filter(|&(_, at_enter, at_exit)| at_enter > 0 || at_exit > 0)
+    }
+}
+
+/// Per-CPU counters of events the eBPF programs had to drop because the ring buffer was full.
+/// It owns a handle to the map so it can be moved into a publisher.
+pub struct DroppedEvents {
+    map: libbpf_rs::MapHandle,
+}
+
+impl DroppedEvents {
+    pub fn new(map: &libbpf_rs::Map) -> Result<Self, libbpf_rs::Error> {
+        Ok(DroppedEvents {
+            map: libbpf_rs::MapHandle::try_from(map)?,
+        })
+    }
+
+    /// Read the counters of every CPU
+    pub fn read(&self) -> Result<DroppedCounts, libbpf_rs::Error> {
+        Ok(DroppedCounts {
+            at_enter: self.read_kind(dropped_event_kind::AT_ENTER)?,
+            at_exit: self.read_kind(dropped_event_kind::AT_EXIT)?,
+        })
+    }
+
+    fn read_kind(&self, kind: u32) -> Result<Vec<u64>, libbpf_rs::Error> {
+        let values = self
+            .map
+            .lookup_percpu(&kind.to_ne_bytes(), libbpf_rs::MapFlags::ANY)?
+            .unwrap_or_default();
+
+        Ok(values
+            .iter()
+            .map(|value| {
+                value
+                    .get(..size_of::<u64>())
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map_or(0, u64::from_ne_bytes)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_key() {
+        assert_eq!(&padded_key::<16>("sshd")[..5], b"sshd\0");
+        assert_eq!(padded_key::<16>(""), [0; 16]);
+        let key = padded_key::<16>("kworker/u16:2-events_unbound");
+        assert_eq!(&key[..15], b"kworker/u16:2-e");
+        assert_eq!(key[15], 0);
     }
 }
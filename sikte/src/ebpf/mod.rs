@@ -1,6 +1,12 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
+mod config;
+pub mod diagnostics;
 mod error;
+mod features;
 pub mod map_types;
 mod sikte_ebpf;
 
-pub use sikte_ebpf::{SikteEbpf, SysEnterProgram, SysExitProgram};
+pub use config::EbpfConfig;
+pub use error::EbpfError;
+pub use features::KernelFeatures;
+pub use sikte_ebpf::{ExecPrograms, SikteEbpf, SysEnterProgram, SysExitProgram};
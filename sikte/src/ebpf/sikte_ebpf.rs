@@ -1,13 +1,22 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
-use std::mem::MaybeUninit;
+use std::{
+    mem::{ManuallyDrop, MaybeUninit},
+    ptr::NonNull,
+};
 
 use libbpf_rs::{
-    Link,
+    Link, OpenObject,
     skel::{OpenSkel, SkelBuilder},
 };
-use log::debug;
+use log::{debug, info};
 
-use super::error::EbpfError;
+use super::{
+    config::EbpfConfig,
+    diagnostics,
+    error::EbpfError,
+    features::KernelFeatures,
+    map_types::{SyscallEventsMap, SyscallPerfBuf, SyscallRingBuf},
+};
 use crate::common::constants::{attach_points::*, program_names::*};
 
 // Include generated skeleton (following libbpf-rs examples pattern)
@@ -20,76 +29,321 @@ mod sikte_skel {
 
 pub use sikte_skel::*;
 
-/// Central point for interacting with eBPF from user space
+/// Central point for interacting with eBPF from user space. Several instances can be loaded at
+/// once, and dropping one unloads its programs and maps
 pub struct SikteEbpf {
-    skel: SikteSkel<'static>,
+    /// Borrows `open_object`, so it must be dropped first
+    skel: ManuallyDrop<SikteSkel<'static>>,
+    /// Heap allocation backing the skeleton, owned by this struct. It doesn't move with it
+    open_object: NonNull<MaybeUninit<OpenObject>>,
+    /// Kernel features the programs were loaded with
+    features: KernelFeatures,
+    /// Size of each per-CPU perf buffer in pages, when ring buffers aren't supported
+    perf_buffer_pages: usize,
 }
 
 impl SikteEbpf {
-    /// Load eBPF programs with CO-RE support
+    /// Load eBPF programs with CO-RE support and default map sizes
     pub fn load() -> Result<SikteEbpf, EbpfError> {
+        Self::load_with(&EbpfConfig::default())
+    }
+
+    /// Load eBPF programs with CO-RE support, sizing the maps according to `config`. Features
+    /// the kernel lacks are replaced by fallbacks, unless `config` forces them
+    pub fn load_with(config: &EbpfConfig) -> Result<SikteEbpf, EbpfError> {
+        config.validate()?;
+        let features = config.features.unwrap_or_else(KernelFeatures::probe);
+        let cpus = libbpf_rs::num_possible_cpus()?;
+
+        let open_object = NonNull::from(Box::leak(Box::new(MaybeUninit::uninit())));
+        // SAFETY: the allocation is only freed after the skeleton borrowing it is dropped, either
+        // in Drop or right away if loading fails
+        match Self::open_and_load(config, features, unsafe { &mut *open_object.as_ptr() }) {
+            Ok(skel) => Ok(SikteEbpf {
+                skel: ManuallyDrop::new(skel),
+                open_object,
+                features,
+                perf_buffer_pages: config.perf_buffer_pages(cpus),
+            }),
+            Err(e) => {
+                // SAFETY: the skeleton was dropped before returning the error
+                drop(unsafe { Box::from_raw(open_object.as_ptr()) });
+                Err(e)
+            }
+        }
+    }
+
+    fn open_and_load(
+        config: &EbpfConfig,
+        features: KernelFeatures,
+        open_object: &'static mut MaybeUninit<OpenObject>,
+    ) -> Result<SikteSkel<'static>, EbpfError> {
         debug!("Opening eBPF skeleton");
 
         // Open skeleton (parses object but doesn't load into kernel)
         let skel_builder = SikteSkelBuilder::default();
-        let open_object = Box::leak(Box::new(MaybeUninit::uninit()));
-        let open_skel = skel_builder
-            .open(open_object)
-            .map_err(|e| EbpfError::as_load_error(e, "open skeleton"))?;
+        let (open_skel, libbpf_log) =
+            diagnostics::capture_libbpf_log(|| skel_builder.open(open_object));
+        let mut open_skel =
+            open_skel.map_err(|e| diagnostics::diagnose(e, "open skeleton", &libbpf_log))?;
+
+        // Only create the buffer the programs write to. The other one is never used, which the
+        // verifier sees through the read-only `use_perf_buffer`
+        open_skel
+            .maps
+            .rodata_data
+            .as_deref_mut()
+            .expect("rodata is not memory mapped")
+            .use_perf_buffer = !features.ring_buffer;
+        if features.ring_buffer {
+            open_skel
+                .maps
+                .PERF_SYSCALL_EVENTS
+                .set_autocreate(false)
+                .map_err(|e| EbpfError::as_disable_map_error(e, "PERF_SYSCALL_EVENTS"))?;
+        } else {
+            info!("Writing events to per-CPU perf buffers, since ring buffers aren't supported");
+            open_skel
+                .maps
+                .SYSCALL_EVENTS
+                .set_autocreate(false)
+                .map_err(|e| EbpfError::as_disable_map_error(e, "SYSCALL_EVENTS"))?;
+        }
+
+        // Only load the programs that can be attached
+        let progs = &mut open_skel.progs;
+        if features.raw_tracepoints {
+            progs.sikte_trace_point_at_enter.set_autoload(false);
+            progs.sikte_trace_point_at_exit.set_autoload(false);
+            progs.sikte_trace_point_at_exec.set_autoload(false);
+            progs.sikte_trace_point_at_process_exit.set_autoload(false);
+        } else {
+            info!("Attaching to classic tracepoints, since raw tracepoints aren't supported");
+            progs.sikte_raw_trace_point_at_enter.set_autoload(false);
+            progs.sikte_raw_trace_point_at_exit.set_autoload(false);
+            progs.sikte_raw_trace_point_at_exec.set_autoload(false);
+            progs
+                .sikte_raw_trace_point_at_process_exit
+                .set_autoload(false);
+        }
+
+        // Maps are created on load, so they can still be resized
+        debug!(
+            "Resizing maps: {} bytes of ring buffer, {} PIDs",
+            config.ring_buffer_size, config.max_pids
+        );
+        if features.ring_buffer {
+            open_skel
+                .maps
+                .SYSCALL_EVENTS
+                .set_max_entries(config.ring_buffer_size)
+                .map_err(|e| EbpfError::as_resize_error(e, "SYSCALL_EVENTS"))?;
+        }
+        open_skel
+            .maps
+            .PID_ALLOW_LIST
+            .set_max_entries(config.max_pids)
+            .map_err(|e| EbpfError::as_resize_error(e, "PID_ALLOW_LIST"))?;
 
         debug!("Loading eBPF programs into kernel (CO-RE relocations will be applied)");
 
         // Load programs into kernel (performs CO-RE relocations)
-        let skel = open_skel
-            .load()
-            .map_err(|e| EbpfError::as_load_error(e, "load skeleton"))?;
+        // Capture libbpf messages, which include the verifier log if a program is rejected
+        let (skel, libbpf_log) = diagnostics::capture_libbpf_log(|| open_skel.load());
+        let skel = skel.map_err(|e| diagnostics::diagnose(e, "load skeleton", &libbpf_log))?;
 
         debug!("eBPF programs loaded successfully with CO-RE support");
 
-        Ok(SikteEbpf { skel })
+        Ok(skel)
     }
 
-    /// Attach sys_enter raw tracepoint
+    /// Kernel features the programs were loaded with
+    pub fn features(&self) -> KernelFeatures {
+        self.features
+    }
+
+    /// Attach the sys_enter program, to the raw tracepoint or to the classic one as a fallback
     pub fn attach_sys_enter_program(&mut self) -> Result<SysEnterProgram, EbpfError> {
-        debug!("Attaching {SIKTE_RAW_TRACE_POINT_AT_ENTER} program to {SYS_ENTER}");
+        let progs = &self.skel.progs;
+        let (program, name, target) = if self.features.raw_tracepoints {
+            (
+                &progs.sikte_raw_trace_point_at_enter,
+                SIKTE_RAW_TRACE_POINT_AT_ENTER,
+                SYS_ENTER,
+            )
+        } else {
+            (
+                &progs.sikte_trace_point_at_enter,
+                SIKTE_TRACE_POINT_AT_ENTER,
+                RAW_SYSCALLS_SYS_ENTER,
+            )
+        };
+        debug!("Attaching {name} program to {target}");
 
-        let link = self
-            .skel
-            .progs
-            .sikte_raw_trace_point_at_enter
+        let link = program
             .attach()
-            .map_err(|e| {
-                EbpfError::as_attach_error(e, SIKTE_RAW_TRACE_POINT_AT_ENTER, SYS_ENTER)
-            })?;
+            .map_err(|e| diagnostics::diagnose_attach(e, name, target))?;
 
-        debug!("Successfully attached {SYS_ENTER} program");
+        debug!("Successfully attached {target} program");
         Ok(SysEnterProgram { _link: link })
     }
 
-    /// Attach sys_exit raw tracepoint
+    /// Attach the sys_exit program, to the raw tracepoint or to the classic one as a fallback
     pub fn attach_sys_exit_program(&mut self) -> Result<SysExitProgram, EbpfError> {
-        debug!("Attaching {SIKTE_RAW_TRACE_POINT_AT_EXIT} program to {SYS_EXIT}");
+        let progs = &self.skel.progs;
+        let (program, name, target) = if self.features.raw_tracepoints {
+            (
+                &progs.sikte_raw_trace_point_at_exit,
+                SIKTE_RAW_TRACE_POINT_AT_EXIT,
+                SYS_EXIT,
+            )
+        } else {
+            (
+                &progs.sikte_trace_point_at_exit,
+                SIKTE_TRACE_POINT_AT_EXIT,
+                RAW_SYSCALLS_SYS_EXIT,
+            )
+        };
+        debug!("Attaching {name} program to {target}");
 
-        let link = self
-            .skel
-            .progs
-            .sikte_raw_trace_point_at_exit
+        let link = program
             .attach()
-            .map_err(|e| EbpfError::as_attach_error(e, SIKTE_RAW_TRACE_POINT_AT_EXIT, SYS_EXIT))?;
+            .map_err(|e| diagnostics::diagnose_attach(e, name, target))?;
 
-        debug!("Successfully attached {SYS_EXIT} program");
+        debug!("Successfully attached {target} program");
         Ok(SysExitProgram { _link: link })
     }
 
+    /// Attach the programs that add processes to the allow list when they execute a file in the
+    /// exec lists, and remove processes from it when they exit. Like the syscall programs, they
+    /// fall back to classic tracepoints
+    pub fn attach_exec_programs(&mut self) -> Result<ExecPrograms, EbpfError> {
+        let progs = &self.skel.progs;
+        let [exec, exit] = if self.features.raw_tracepoints {
+            [
+                (
+                    &progs.sikte_raw_trace_point_at_exec,
+                    SIKTE_RAW_TRACE_POINT_AT_EXEC,
+                    SCHED_PROCESS_EXEC,
+                ),
+                (
+                    &progs.sikte_raw_trace_point_at_process_exit,
+                    SIKTE_RAW_TRACE_POINT_AT_PROCESS_EXIT,
+                    SCHED_PROCESS_EXIT,
+                ),
+            ]
+        } else {
+            [
+                (
+                    &progs.sikte_trace_point_at_exec,
+                    SIKTE_TRACE_POINT_AT_EXEC,
+                    SCHED_SCHED_PROCESS_EXEC,
+                ),
+                (
+                    &progs.sikte_trace_point_at_process_exit,
+                    SIKTE_TRACE_POINT_AT_PROCESS_EXIT,
+                    SCHED_SCHED_PROCESS_EXIT,
+                ),
+            ]
+        };
+        let attach = |(program, name, target): (&libbpf_rs::ProgramMut<'_>, _, _)| {
+            debug!("Attaching {name} program to {target}");
+            let link = program
+                .attach()
+                .map_err(|e| diagnostics::diagnose_attach(e, name, target))?;
+            debug!("Successfully attached {target} program");
+            Ok::<_, EbpfError>(link)
+        };
+
+        Ok(ExecPrograms {
+            _exec_link: attach(exec)?,
+            _exit_link: attach(exit)?,
+        })
+    }
+
+    /// Get the map syscall events are written to: the ring buffer, or the per-CPU perf buffers
+    /// if the kernel doesn't support it
+    pub fn syscall_events(&self) -> SyscallEventsMap<'_> {
+        if self.features.ring_buffer {
+            SyscallEventsMap::RingBuf(SyscallRingBuf::new(self.syscall_events_map()))
+        } else {
+            SyscallEventsMap::PerfBuf(SyscallPerfBuf::new(
+                &self.skel.maps.PERF_SYSCALL_EVENTS,
+                self.perf_buffer_pages,
+            ))
+        }
+    }
+
     /// Get reference to SYSCALL_EVENTS ring buffer map
     pub fn syscall_events_map(&self) -> &libbpf_rs::Map<'_> {
         &self.skel.maps.SYSCALL_EVENTS
     }
 
+    /// Get reference to DROPPED_EVENTS per-CPU array map
+    pub fn dropped_events_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.DROPPED_EVENTS
+    }
+
     /// Get reference to PID_ALLOW_LIST hash map
     pub fn pid_allow_list_map(&self) -> &libbpf_rs::Map<'_> {
         &self.skel.maps.PID_ALLOW_LIST
     }
+
+    /// Get reference to PID_DENY_LIST hash map
+    pub fn pid_deny_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.PID_DENY_LIST
+    }
+
+    /// Get reference to COMM_DENY_LIST hash map
+    pub fn comm_deny_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.COMM_DENY_LIST
+    }
+
+    /// Get reference to EXEC_PATH_LIST hash map
+    pub fn exec_path_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.EXEC_PATH_LIST
+    }
+
+    /// Get reference to EXEC_COMM_LIST hash map
+    pub fn exec_comm_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.EXEC_COMM_LIST
+    }
+
+    /// Get reference to TID_ALLOW_LIST hash map
+    pub fn tid_allow_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.TID_ALLOW_LIST
+    }
+
+    /// Get reference to UID_ALLOW_LIST hash map
+    pub fn uid_allow_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.UID_ALLOW_LIST
+    }
+
+    /// Get reference to GID_ALLOW_LIST hash map
+    pub fn gid_allow_list_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.GID_ALLOW_LIST
+    }
+
+    /// Get reference to TRACED_CGROUP cgroup array map
+    pub fn traced_cgroup_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.TRACED_CGROUP
+    }
+
+    /// Get reference to TRACE_CONFIG array map
+    pub fn trace_config_map(&self) -> &libbpf_rs::Map<'_> {
+        &self.skel.maps.TRACE_CONFIG
+    }
+}
+
+impl Drop for SikteEbpf {
+    fn drop(&mut self) {
+        // SAFETY: the skeleton is not used after this, and it no longer borrows the allocation
+        // when it is freed
+        unsafe {
+            ManuallyDrop::drop(&mut self.skel);
+            drop(Box::from_raw(self.open_object.as_ptr()));
+        }
+    }
 }
 
 /// Represents that the 'sys_enter' program has been loaded into the kernel. When dropped, detach
@@ -105,3 +359,12 @@ pub struct SysExitProgram {
     /// Private field that contains the link to `sys_exit`
     _link: Link,
 }
+
+/// Represents that the programs following execs and process exits have been loaded into the
+/// kernel. When dropped, detach them
+pub struct ExecPrograms {
+    /// Private field that contains the link to `sched_process_exec`
+    _exec_link: Link,
+    /// Private field that contains the link to `sched_process_exit`
+    _exit_link: Link,
+}
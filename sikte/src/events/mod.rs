@@ -0,0 +1,746 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+mod queue;
+
+use std::{
+    mem::size_of,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use log::{debug, error, warn};
+use queue::EventQueue;
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    common::generated_types::SyscallData,
+    publishers::EventPublisher,
+    subscribers::{AsyncEventSubscriber, EventSubscriber, SyncSubscriber},
+};
+
+/// Default number of events each subscriber can have waiting in memory
+pub const DEFAULT_BUS_CAPACITY: usize = 1 << 14;
+
+/// Default maximum size of the temporary file a subscriber spills events to, in bytes
+pub const DEFAULT_MAX_SPILL_SIZE: u64 = 1 << 30;
+
+/// Size of an event encoded as a record: a tag byte, padding and the largest payload
+const EVENT_RECORD_SIZE: usize = 8 + size_of::<SyscallData>();
+const SYSCALL_TAG: u8 = 0;
+const LOST_TAG: u8 = 1;
+
+/// Enum for representing all the possible eBPF events in this program
+#[derive(Clone)]
+pub enum Event {
+    /// Syscall event
+    Syscall(SyscallData),
+    /// The eBPF programs dropped events
+    Lost(LostEvents),
+}
+
+/// Events published together, shared by every subscriber instead of copied
+pub type EventBatch = Arc<[Event]>;
+
+/// Kind of an [`Event`], i.e. the topic it is published on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// [`Event::Syscall`]
+    Syscall,
+    /// [`Event::Lost`]
+    Lost,
+}
+
+/// Set of event kinds, e.g. the ones a subscriber consumes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventKinds(u32);
+
+impl EventKinds {
+    /// No event at all
+    pub const NONE: EventKinds = EventKinds(0);
+    /// Every kind of event, including the ones added in the future
+    pub const ALL: EventKinds = EventKinds(u32::MAX);
+
+    /// Set of the given kinds
+    pub const fn of(kinds: &[EventKind]) -> EventKinds {
+        let mut set = EventKinds::NONE;
+        let mut i = 0;
+        while i < kinds.len() {
+            set = set.with(kinds[i]);
+            i += 1;
+        }
+        set
+    }
+
+    /// This set plus `kind`
+    pub const fn with(self, kind: EventKind) -> EventKinds {
+        EventKinds(self.0 | 1 << kind as u32)
+    }
+
+    /// Whether `kind` is in the set
+    pub const fn contains(self, kind: EventKind) -> bool {
+        self.0 & 1 << kind as u32 != 0
+    }
+
+    /// Whether every kind of `other` is in the set
+    pub const fn contains_all(self, other: EventKinds) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl From<EventKind> for EventKinds {
+    fn from(kind: EventKind) -> Self {
+        EventKinds::NONE.with(kind)
+    }
+}
+
+impl FromIterator<EventKind> for EventKinds {
+    fn from_iter<I: IntoIterator<Item = EventKind>>(kinds: I) -> Self {
+        kinds.into_iter().fold(EventKinds::NONE, EventKinds::with)
+    }
+}
+
+/// Events the eBPF programs dropped since the previous report because the ring buffer was full,
+/// which leaves a gap in the trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LostEvents {
+    /// When the drops were noticed, in ns since boot like eBPF timestamps
+    pub timestamp: u64,
+    /// Dropped sys_enter events
+    pub at_enter: u64,
+    /// Dropped sys_exit events
+    pub at_exit: u64,
+}
+
+impl Event {
+    /// Kind of this event
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Syscall(_) => EventKind::Syscall,
+            Event::Lost(_) => EventKind::Lost,
+        }
+    }
+
+    /// Encode into a fixed-size record
+    fn to_record(&self) -> [u8; EVENT_RECORD_SIZE] {
+        let mut record = [0; EVENT_RECORD_SIZE];
+        match self {
+            Event::Syscall(syscall_data) => {
+                record[0] = SYSCALL_TAG;
+                // SAFETY: SyscallData is a plain C struct, read from the ring buffer as bytes too
+                let bytes = unsafe { plain::as_bytes(syscall_data) };
+                record[8..8 + bytes.len()].copy_from_slice(bytes);
+            }
+            Event::Lost(lost) => {
+                record[0] = LOST_TAG;
+                for (i, value) in [lost.timestamp, lost.at_enter, lost.at_exit]
+                    .into_iter()
+                    .enumerate()
+                {
+                    record[8 * (i + 1)..8 * (i + 2)].copy_from_slice(&value.to_ne_bytes());
+                }
+            }
+        }
+        record
+    }
+
+    /// Decode a record written by [`Event::to_record`]
+    fn from_record(record: &[u8]) -> Option<Event> {
+        let payload = record.get(8..EVENT_RECORD_SIZE)?;
+        match record[0] {
+            SYSCALL_TAG => {
+                let mut syscall_data = SyscallData::default();
+                plain::copy_from_bytes(&mut syscall_data, payload).ok()?;
+                Some(Event::Syscall(syscall_data))
+            }
+            LOST_TAG => {
+                let value = |i: usize| {
+                    u64::from_ne_bytes(payload[8 * i..8 * (i + 1)].try_into().expect("8 bytes"))
+                };
+                Some(Event::Lost(LostEvents {
+                    timestamp: value(0),
+                    at_enter: value(1),
+                    at_exit: value(2),
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl LostEvents {
+    /// Dropped events of any kind
+    pub fn total(&self) -> u64 {
+        self.at_enter + self.at_exit
+    }
+}
+
+/// What to do with an event when a subscriber's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backpressure {
+    /// Drop the event and count it as lost
+    Drop,
+    /// Make the publisher wait until the subscriber catches up. The ring buffer may then fill up
+    /// and the eBPF programs drop events instead
+    Block,
+    /// Write the event to a temporary file, and read it back once the subscriber catches up
+    Spill,
+}
+
+/// Configuration of an [`EventBus`]
+#[derive(Debug, Clone, Copy)]
+pub struct BusConfig {
+    /// Number of events each subscriber can have waiting in memory
+    pub capacity: usize,
+    /// What to do with events that don't fit in a subscriber's queue. Lossless subscribers
+    /// spill them rather than drop them
+    pub backpressure: Backpressure,
+    /// Maximum size of the temporary file of each spilling subscriber, in bytes. Events that
+    /// don't fit are dropped
+    pub max_spill_size: u64,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        BusConfig {
+            capacity: DEFAULT_BUS_CAPACITY,
+            backpressure: Backpressure::Drop,
+            max_spill_size: DEFAULT_MAX_SPILL_SIZE,
+        }
+    }
+}
+
+/// Health counters of a single subscription
+#[derive(Debug)]
+pub struct SubscriberStats {
+    /// Name of the subscriber
+    pub name: String,
+    /// Backpressure policy of the subscription
+    pub backpressure: Backpressure,
+    /// Events delivered to the subscriber
+    pub received: AtomicU64,
+    /// Events the subscriber missed because its queue was full
+    pub lost: AtomicU64,
+    /// Events written to a temporary file because its queue was full
+    pub spilled: AtomicU64,
+    /// Largest number of events waiting for the subscriber at once
+    pub max_lag: AtomicU64,
+}
+
+impl SubscriberStats {
+    fn new(name: &str, backpressure: Backpressure) -> SubscriberStats {
+        SubscriberStats {
+            name: name.to_string(),
+            backpressure,
+            received: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            max_lag: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Health counters of an [`EventBus`]
+#[derive(Debug, Default)]
+pub struct BusStats {
+    subscribers: Mutex<Vec<Arc<SubscriberStats>>>,
+}
+
+impl BusStats {
+    /// Stats of every subscription spawned so far
+    pub fn subscribers(&self) -> Vec<Arc<SubscriberStats>> {
+        self.subscribers
+            .lock()
+            .expect("bus stats lock poisoned")
+            .clone()
+    }
+
+    fn register(&self, name: &str, backpressure: Backpressure) -> Arc<SubscriberStats> {
+        let stats = Arc::new(SubscriberStats::new(name, backpressure));
+        self.subscribers
+            .lock()
+            .expect("bus stats lock poisoned")
+            .push(stats.clone());
+        stats
+    }
+}
+
+/// Queue of a subscriber, with the kinds of events it consumes
+struct SubscriberQueue {
+    interests: EventKinds,
+    queue: Arc<EventQueue>,
+}
+
+/// Publishes events to every subscriber of an [`EventBus`]
+#[derive(Clone, Default)]
+pub struct EventSender {
+    queues: Arc<RwLock<Arc<[Arc<SubscriberQueue>]>>>,
+}
+
+impl EventSender {
+    /// Send a single event. Publishers with several events at hand should send them as a batch
+    pub async fn send(&self, event: Event) {
+        self.send_batch(Arc::new([event])).await
+    }
+
+    /// Send a batch of events to every subscriber, keeping only the kinds each one is interested
+    /// in. Subscribers interested in every event of the batch share it. It waits only for
+    /// subscribers with the [`Backpressure::Block`] policy whose queue is full. If it is
+    /// cancelled, only some of the subscribers may get the batch
+    pub async fn send_batch(&self, batch: EventBatch) {
+        if batch.is_empty() {
+            return;
+        }
+        let queues = self
+            .queues
+            .read()
+            .expect("event sender lock poisoned")
+            .clone();
+        let kinds: EventKinds = batch.iter().map(Event::kind).collect();
+        for subscriber in queues.iter() {
+            if subscriber.interests.contains_all(kinds) {
+                subscriber.queue.push(batch.clone()).await;
+                continue;
+            }
+            let wanted: EventBatch = batch
+                .iter()
+                .filter(|event| subscriber.interests.contains(event.kind()))
+                .cloned()
+                .collect();
+            if !wanted.is_empty() {
+                subscriber.queue.push(wanted).await;
+            }
+        }
+    }
+
+    fn add(&self, interests: EventKinds, queue: Arc<EventQueue>) {
+        let subscriber = Arc::new(SubscriberQueue { interests, queue });
+        let mut queues = self.queues.write().expect("event sender lock poisoned");
+        *queues = queues.iter().cloned().chain([subscriber]).collect();
+    }
+
+    /// Close every queue, so that subscriptions end once they read the queued events
+    fn close(&self) {
+        for subscriber in self
+            .queues
+            .read()
+            .expect("event sender lock poisoned")
+            .iter()
+        {
+            subscriber.queue.close();
+        }
+    }
+}
+
+/// Multiple-publisher & multiple-consumer event bus. Every subscriber has its own queue, so a
+/// slow subscriber doesn't make the others miss events
+pub struct EventBus {
+    config: BusConfig,
+    sender: EventSender,
+    /// Cancelled to stop every publisher
+    stop: CancellationToken,
+    publishers: Vec<JoinHandle<()>>,
+    subscribers: Vec<JoinHandle<()>>,
+    stats: Arc<BusStats>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Create a new `EventBus` with the default configuration
+    pub fn new() -> EventBus {
+        EventBus::with_config(BusConfig::default())
+    }
+
+    /// Create a new `EventBus`
+    pub fn with_config(config: BusConfig) -> EventBus {
+        EventBus {
+            config,
+            sender: EventSender::default(),
+            stop: CancellationToken::new(),
+            publishers: vec![],
+            subscribers: vec![],
+            stats: Arc::new(BusStats::default()),
+        }
+    }
+
+    /// Get a sender to publish events to the bus
+    pub fn tx(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    /// Get the health counters of this bus
+    pub fn stats(&self) -> Arc<BusStats> {
+        self.stats.clone()
+    }
+
+    /// Spawn a publishment task that will run inside tokio
+    pub fn spawn_publishment<P>(&mut self, publisher: P)
+    where
+        P: EventPublisher + Send + 'static,
+    {
+        let tx = self.tx();
+        let handle = tokio::spawn(publishment(publisher, tx, self.stop.clone()));
+        self.publishers.push(handle);
+    }
+
+    /// Spawn a subscription task that will run inside tokio. It only receives the kinds of events
+    /// the subscriber is interested in. Subscribers that must not lose events spill them instead
+    /// of dropping them
+    pub fn spawn_subscription<S>(&mut self, subscriber: S)
+    where
+        S: EventSubscriber + Send + 'static,
+    {
+        self.spawn_async_subscription(SyncSubscriber(subscriber));
+    }
+
+    /// Spawn a subscription task for a subscriber whose handlers await, like
+    /// [`EventBus::spawn_subscription`]
+    pub fn spawn_async_subscription<S>(&mut self, subscriber: S)
+    where
+        S: AsyncEventSubscriber + Send + 'static,
+    {
+        let backpressure = match self.config.backpressure {
+            Backpressure::Drop if subscriber.is_lossless() => Backpressure::Spill,
+            backpressure => backpressure,
+        };
+        let stats = self.stats.register(subscriber.get_name(), backpressure);
+        let queue = Arc::new(EventQueue::new(
+            backpressure,
+            self.config.capacity,
+            self.config.max_spill_size,
+            stats.clone(),
+        ));
+        self.sender.add(subscriber.interests(), queue.clone());
+        let handle = tokio::spawn(subscription(subscriber, queue, stats));
+        self.subscribers.push(handle);
+    }
+
+    /// Shut down in order: stop the publishers and let them drain their pending events, close the
+    /// queues, and wait for every subscriber to read the remaining events and finish. Tasks still
+    /// running after `timeout` are aborted.
+    pub async fn shutdown(mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+
+        debug!("Stopping {} publishers", self.publishers.len());
+        self.stop.cancel();
+        for handle in self.publishers.drain(..) {
+            join_until(handle, deadline).await;
+        }
+
+        self.sender.close();
+        debug!("Waiting for {} subscribers", self.subscribers.len());
+        for handle in self.subscribers.drain(..) {
+            join_until(handle, deadline).await;
+        }
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        for handle in self.publishers.iter().chain(&self.subscribers) {
+            handle.abort();
+        }
+    }
+}
+
+/// Wait for a task until `deadline`, and abort it if it is still running by then
+async fn join_until(mut handle: JoinHandle<()>, deadline: Instant) {
+    match tokio::time::timeout_at(deadline, &mut handle).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => error!("Event bus task failed: {err}"),
+        Err(_) => {
+            warn!("Event bus task did not finish in time, aborting it");
+            handle.abort();
+        }
+    }
+}
+
+async fn publishment<P>(mut publisher: P, tx: EventSender, stop: CancellationToken)
+where
+    P: EventPublisher + Send + 'static,
+{
+    loop {
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            ready = publisher.wait_events() => {
+                if let Err(err) = ready {
+                    error!("Error while publishing: {err}");
+                    return;
+                }
+            }
+        }
+
+        // Not cancelled, so that a batch reaches either every subscriber or none
+        if let Err(err) = publisher.publish_events(&tx).await {
+            error!("Error while publishing: {err}");
+            return;
+        }
+    }
+
+    match publisher.drain(&tx).await {
+        Ok(num_events) => debug!(
+            "Stopped publisher {} after draining {num_events} events",
+            publisher.get_name()
+        ),
+        Err(err) => error!("Error while draining {}: {err}", publisher.get_name()),
+    }
+}
+
+async fn subscription<S>(mut subscriber: S, queue: Arc<EventQueue>, stats: Arc<SubscriberStats>)
+where
+    S: AsyncEventSubscriber + Send + 'static,
+{
+    while let Some(batch) = queue.pop().await {
+        stats
+            .received
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        subscriber.read_events(&batch).await;
+    }
+
+    debug!(
+        "Event bus was closed. Finished subscription for {}",
+        subscriber.get_name()
+    );
+    subscriber.finish().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscribers::test_utils::sys_enter;
+
+    /// Publishes nothing until stopped, then drains `pending` events
+    struct DrainingPublisher {
+        pending: u32,
+    }
+
+    impl EventPublisher for DrainingPublisher {
+        fn get_name(&self) -> &str {
+            "Draining"
+        }
+
+        async fn wait_events(&mut self) -> anyhow::Result<()> {
+            std::future::pending().await
+        }
+
+        async fn publish_events(&mut self, _tx: &EventSender) -> anyhow::Result<u32> {
+            Ok(0)
+        }
+
+        async fn drain(&mut self, tx: &EventSender) -> anyhow::Result<u32> {
+            for i in 0..self.pending {
+                tx.send(Event::Syscall(sys_enter(i.into(), 1, 1, 0))).await;
+            }
+            Ok(self.pending)
+        }
+    }
+
+    /// Has `pending` events ready once, and sends them one by one
+    struct OneShotPublisher {
+        pending: u32,
+        ready: bool,
+    }
+
+    impl EventPublisher for OneShotPublisher {
+        fn get_name(&self) -> &str {
+            "One-shot"
+        }
+
+        async fn wait_events(&mut self) -> anyhow::Result<()> {
+            if !std::mem::replace(&mut self.ready, false) {
+                std::future::pending::<()>().await;
+            }
+            Ok(())
+        }
+
+        async fn publish_events(&mut self, tx: &EventSender) -> anyhow::Result<u32> {
+            let pending = std::mem::take(&mut self.pending);
+            for i in 0..pending {
+                tx.send(Event::Syscall(sys_enter(i.into(), 1, 1, 0))).await;
+            }
+            Ok(pending)
+        }
+    }
+
+    /// Counts events, and whether it was finished after all of them
+    struct CountingSubscriber {
+        result: Arc<Mutex<(u32, bool)>>,
+    }
+
+    impl EventSubscriber for CountingSubscriber {
+        fn get_name(&self) -> &str {
+            "Counting"
+        }
+
+        fn read_syscall(&mut self, _syscall_data: &SyscallData) {
+            let mut result = self.result.lock().unwrap();
+            assert!(!result.1, "read an event after finishing");
+            result.0 += 1;
+        }
+
+        fn finish(&mut self) {
+            self.result.lock().unwrap().1 = true;
+        }
+    }
+
+    #[test]
+    fn test_event_records() {
+        let syscall = sys_enter(42, 1, 2, 3);
+        let record = Event::Syscall(syscall).to_record();
+        let Some(Event::Syscall(decoded)) = Event::from_record(&record) else {
+            panic!("not a syscall");
+        };
+        assert_eq!(unsafe { plain::as_bytes(&decoded) }, unsafe {
+            plain::as_bytes(&syscall)
+        });
+
+        let lost = LostEvents {
+            timestamp: 1,
+            at_enter: 2,
+            at_exit: 3,
+        };
+        let record = Event::Lost(lost).to_record();
+        assert!(matches!(Event::from_record(&record), Some(Event::Lost(l)) if l == lost));
+    }
+
+    /// Only interested in lost events
+    struct LostSubscriber {
+        lost: Arc<Mutex<Vec<LostEvents>>>,
+    }
+
+    impl EventSubscriber for LostSubscriber {
+        fn get_name(&self) -> &str {
+            "Lost"
+        }
+
+        fn interests(&self) -> EventKinds {
+            EventKinds::of(&[EventKind::Lost])
+        }
+
+        fn read_lost(&mut self, lost: &LostEvents) {
+            self.lost.lock().unwrap().push(*lost);
+        }
+    }
+
+    #[test]
+    fn test_event_kinds() {
+        let kinds = EventKinds::of(&[EventKind::Lost]);
+        assert!(kinds.contains(EventKind::Lost));
+        assert!(!kinds.contains(EventKind::Syscall));
+        assert_eq!(
+            [EventKind::Syscall, EventKind::Lost]
+                .into_iter()
+                .collect::<EventKinds>(),
+            EventKinds::from(EventKind::Syscall).with(EventKind::Lost)
+        );
+        assert!(EventKinds::ALL.contains(EventKind::Syscall));
+        assert!(!EventKinds::NONE.contains(EventKind::Syscall));
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_only_receive_their_interests() {
+        let mut bus = EventBus::new();
+        let lost = Arc::new(Mutex::new(vec![]));
+        bus.spawn_subscription(LostSubscriber { lost: lost.clone() });
+        let stats = bus.stats();
+
+        let tx = bus.tx();
+        tx.send(Event::Syscall(sys_enter(0, 1, 1, 0))).await;
+        let report = LostEvents {
+            timestamp: 1,
+            at_enter: 2,
+            at_exit: 0,
+        };
+        tx.send(Event::Lost(report)).await;
+        bus.shutdown(Duration::from_secs(1)).await;
+
+        assert_eq!(*lost.lock().unwrap(), [report]);
+        assert_eq!(stats.subscribers()[0].received.load(Ordering::Relaxed), 1);
+    }
+
+    /// Counts events after yielding to the runtime, like a subscriber waiting for I/O
+    struct YieldingSubscriber {
+        result: Arc<Mutex<(usize, bool)>>,
+        delay: Duration,
+    }
+
+    impl AsyncEventSubscriber for YieldingSubscriber {
+        fn get_name(&self) -> &str {
+            "Yielding"
+        }
+
+        async fn read_events(&mut self, events: &[Event]) {
+            tokio::time::sleep(self.delay).await;
+            self.result.lock().unwrap().0 += events.len();
+        }
+
+        async fn finish(&mut self) {
+            tokio::task::yield_now().await;
+            self.result.lock().unwrap().1 = true;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_subscription() {
+        let mut bus = EventBus::new();
+        let result = Arc::new(Mutex::new((0, false)));
+        bus.spawn_async_subscription(YieldingSubscriber {
+            result: result.clone(),
+            delay: Duration::ZERO,
+        });
+
+        let batch: EventBatch = (0..10)
+            .map(|i| Event::Syscall(sys_enter(i, 1, 1, 0)))
+            .collect();
+        bus.tx().send_batch(batch).await;
+        bus.shutdown(Duration::from_secs(1)).await;
+
+        assert_eq!(*result.lock().unwrap(), (10, true));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_publishers_and_finishes_subscribers() {
+        let mut bus = EventBus::new();
+        let result = Arc::new(Mutex::new((0, false)));
+        bus.spawn_subscription(CountingSubscriber {
+            result: result.clone(),
+        });
+        bus.spawn_publishment(DrainingPublisher { pending: 100 });
+
+        bus.shutdown(Duration::from_secs(1)).await;
+
+        assert_eq!(*result.lock().unwrap(), (100, true));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_does_not_interrupt_blocked_publisher() {
+        let mut bus = EventBus::with_config(BusConfig {
+            capacity: 1,
+            backpressure: Backpressure::Block,
+            ..BusConfig::default()
+        });
+        let result = Arc::new(Mutex::new((0, false)));
+        bus.spawn_async_subscription(YieldingSubscriber {
+            result: result.clone(),
+            delay: Duration::from_millis(1),
+        });
+        bus.spawn_publishment(OneShotPublisher {
+            pending: 100,
+            ready: true,
+        });
+
+        // Stop while the publisher waits for the slow subscriber
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        bus.shutdown(Duration::from_secs(5)).await;
+
+        assert_eq!(*result.lock().unwrap(), (100, true));
+    }
+}
@@ -0,0 +1,449 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Per-subscriber queues of event batches with a configurable backpressure policy
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use log::{error, warn};
+use tokio::sync::Notify;
+
+use super::{Backpressure, EVENT_RECORD_SIZE, Event, EventBatch, SubscriberStats};
+
+/// Queue of event batches between the publishers and a single subscriber. Batches are shared with
+/// the other subscribers rather than copied
+pub(super) struct EventQueue {
+    policy: Backpressure,
+    /// Maximum number of events kept in memory, unless a single batch is larger
+    capacity: usize,
+    /// Maximum number of events written to the spill file before it's read back
+    max_spilled: u64,
+    state: Mutex<QueueState>,
+    /// Events that didn't fit in memory, if any ever spilled. Only used from blocking tasks
+    spill: Arc<Mutex<Option<SpillFile>>>,
+    /// Notified when events are pushed or the queue is closed
+    readable: Notify,
+    /// Notified when events are popped or the queue is closed
+    writable: Notify,
+    stats: Arc<SubscriberStats>,
+}
+
+struct QueueState {
+    /// Batches in memory, always older than the spilled events
+    batches: VecDeque<EventBatch>,
+    /// Number of events in `batches`
+    len: usize,
+    /// Number of events in the spill file or being written to it
+    spilled: u64,
+    /// Whether the spill file was ever full, which is only logged once
+    spill_full: bool,
+    /// No more events will be pushed
+    closed: bool,
+}
+
+impl QueueState {
+    fn lag(&self) -> u64 {
+        self.len as u64 + self.spilled
+    }
+}
+
+impl EventQueue {
+    pub(super) fn new(
+        policy: Backpressure,
+        capacity: usize,
+        max_spill_size: u64,
+        stats: Arc<SubscriberStats>,
+    ) -> EventQueue {
+        EventQueue {
+            policy,
+            capacity: capacity.max(1),
+            max_spilled: max_spill_size / EVENT_RECORD_SIZE as u64,
+            state: Mutex::new(QueueState {
+                batches: VecDeque::new(),
+                len: 0,
+                spilled: 0,
+                spill_full: false,
+                closed: false,
+            }),
+            spill: Arc::new(Mutex::new(None)),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            stats,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("event queue lock poisoned")
+    }
+
+    /// Push a batch, applying the backpressure policy if it doesn't fit in the queue. Batches
+    /// must be pushed one at a time to keep their order
+    pub(super) async fn push(&self, batch: EventBatch) {
+        loop {
+            let spill = {
+                let mut state = self.lock();
+                if state.closed {
+                    self.stats
+                        .lost
+                        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    return;
+                }
+
+                // Once spilling, events go to the file until it's empty to keep their order. A
+                // batch larger than the capacity still goes through an empty queue
+                if state.spilled == 0
+                    && (state.len == 0 || state.len + batch.len() <= self.capacity)
+                {
+                    state.len += batch.len();
+                    state.batches.push_back(batch);
+                    self.stats.max_lag.fetch_max(state.lag(), Ordering::Relaxed);
+                    drop(state);
+                    self.readable.notify_one();
+                    return;
+                }
+
+                match self.policy {
+                    Backpressure::Drop => {
+                        self.stats
+                            .lost
+                            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        return;
+                    }
+                    Backpressure::Spill => {
+                        // Counted before writing, so that the next batches follow it
+                        state.spilled += batch.len() as u64;
+                        true
+                    }
+                    Backpressure::Block => false,
+                }
+            };
+
+            if spill {
+                self.spill(batch).await;
+                return;
+            }
+            // Wait until the subscriber makes room
+            self.writable.notified().await;
+        }
+    }
+
+    /// Write a batch to the spill file, without blocking the runtime
+    async fn spill(&self, batch: EventBatch) {
+        let len = batch.len() as u64;
+        let spill = self.spill.clone();
+        let max_spilled = self.max_spilled;
+        let result = blocking(move || {
+            let mut spill = spill.lock().expect("spill file lock poisoned");
+            let file = match &mut *spill {
+                Some(file) => file,
+                None => spill.insert(SpillFile::create()?),
+            };
+            if file.written + len > max_spilled {
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    "the spill file reached its maximum size",
+                ));
+            }
+            file.push(&batch)
+        })
+        .await;
+
+        let mut state = self.lock();
+        match result {
+            Ok(()) => {
+                self.stats.spilled.fetch_add(len, Ordering::Relaxed);
+                self.stats.max_lag.fetch_max(state.lag(), Ordering::Relaxed);
+                drop(state);
+                self.readable.notify_one();
+            }
+            Err(err) => {
+                state.spilled = state.spilled.saturating_sub(len);
+                self.stats.lost.fetch_add(len, Ordering::Relaxed);
+                if err.kind() != io::ErrorKind::StorageFull {
+                    error!("Failed to spill events of {}: {err}", self.stats.name);
+                } else if !state.spill_full {
+                    state.spill_full = true;
+                    warn!(
+                        "The spill file of {} is full, dropping events until it catches up",
+                        self.stats.name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Read the oldest spilled events back, without blocking the runtime. Empty if the events
+    /// counted as spilled are still being written
+    async fn unspill(&self) -> io::Result<Vec<Event>> {
+        let spill = self.spill.clone();
+        let max = self.capacity;
+        blocking(
+            move || match &mut *spill.lock().expect("spill file lock poisoned") {
+                Some(file) => file.pop_chunk(max),
+                None => Ok(vec![]),
+            },
+        )
+        .await
+    }
+
+    /// Pop the oldest batch, waiting for one if the queue is empty. Returns `None` once the queue
+    /// is closed and empty. There must be a single reader
+    pub(super) async fn pop(&self) -> Option<EventBatch> {
+        loop {
+            let spilled = {
+                let mut state = self.lock();
+                if let Some(batch) = state.batches.pop_front() {
+                    state.len -= batch.len();
+                    drop(state);
+                    self.writable.notify_one();
+                    return Some(batch);
+                }
+                if state.spilled == 0 && state.closed {
+                    return None;
+                }
+                state.spilled
+            };
+
+            if spilled > 0 {
+                match self.unspill().await {
+                    Ok(events) if events.is_empty() => {}
+                    Ok(events) => {
+                        let mut state = self.lock();
+                        state.spilled -= events.len() as u64;
+                        state.len += events.len();
+                        state.batches.push_back(events.into());
+                        continue;
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to read spilled events of {}: {err}",
+                            self.stats.name
+                        );
+                        let lost = std::mem::take(&mut self.lock().spilled);
+                        self.stats.lost.fetch_add(lost, Ordering::Relaxed);
+                        *self.spill.lock().expect("spill file lock poisoned") = None;
+                        continue;
+                    }
+                }
+            }
+
+            self.readable.notified().await;
+        }
+    }
+
+    /// Stop accepting events. The subscriber still reads the queued ones
+    pub(super) fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_one();
+        // Wake every blocked publisher, and keep a permit for one that is about to block
+        self.writable.notify_waiters();
+        self.writable.notify_one();
+    }
+}
+
+/// Run file I/O on the blocking thread pool
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)))
+}
+
+/// Unlinked temporary file holding events in fixed-size records
+struct SpillFile {
+    file: File,
+    /// Index of the next record to read
+    read: u64,
+    /// Index of the next record to write
+    written: u64,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<SpillFile> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("sikte-spill-{}-{id}", std::process::id()));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // The file lives on while open, and disappears even if sikte crashes
+        fs::remove_file(&path)?;
+
+        Ok(SpillFile {
+            file,
+            read: 0,
+            written: 0,
+        })
+    }
+
+    /// Number of events in the file
+    fn len(&self) -> u64 {
+        self.written - self.read
+    }
+
+    fn push(&mut self, batch: &[Event]) -> io::Result<()> {
+        let records: Vec<u8> = batch.iter().flat_map(Event::to_record).collect();
+        let offset = self.written * EVENT_RECORD_SIZE as u64;
+        self.file.write_all_at(&records, offset)?;
+        self.written += batch.len() as u64;
+        Ok(())
+    }
+
+    /// Read up to `max` of the oldest events
+    fn pop_chunk(&mut self, max: usize) -> io::Result<Vec<Event>> {
+        let count = self.len().min(max as u64) as usize;
+        let mut buf = vec![0; count * EVENT_RECORD_SIZE];
+        self.file
+            .read_exact_at(&mut buf, self.read * EVENT_RECORD_SIZE as u64)?;
+        self.read += count as u64;
+
+        if self.len() == 0 {
+            // Everything was read, so start over to keep the file small
+            self.file.set_len(0)?;
+            self.read = 0;
+            self.written = 0;
+        }
+
+        Ok(buf
+            .chunks_exact(EVENT_RECORD_SIZE)
+            .filter_map(Event::from_record)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscribers::test_utils::sys_enter;
+
+    fn queue(policy: Backpressure, capacity: usize) -> EventQueue {
+        EventQueue::new(
+            policy,
+            capacity,
+            u64::MAX,
+            Arc::new(SubscriberStats::new("test", policy)),
+        )
+    }
+
+    fn batch(timestamps: std::ops::Range<u64>) -> EventBatch {
+        timestamps
+            .map(|i| Event::Syscall(sys_enter(i, 1, 1, 0)))
+            .collect()
+    }
+
+    fn timestamps(batches: &[EventBatch]) -> Vec<u64> {
+        batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .map(|event| match event {
+                Event::Syscall(syscall) => syscall.timestamp,
+                Event::Lost(lost) => lost.timestamp,
+            })
+            .collect()
+    }
+
+    async fn pop_all(queue: &EventQueue) -> Vec<EventBatch> {
+        queue.close();
+        let mut batches = vec![];
+        while let Some(batch) = queue.pop().await {
+            batches.push(batch);
+        }
+        batches
+    }
+
+    #[tokio::test]
+    async fn test_drop_policy_counts_losses() {
+        let queue = queue(Backpressure::Drop, 2);
+        for i in 0..5 {
+            queue.push(batch(i..i + 1)).await;
+        }
+        // a batch larger than the capacity is dropped unless the queue is empty
+        queue.push(batch(5..8)).await;
+
+        assert_eq!(timestamps(&pop_all(&queue).await), [0, 1]);
+        assert_eq!(queue.stats.lost.load(Ordering::Relaxed), 6);
+        assert_eq!(queue.stats.max_lag.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_spill_policy_keeps_order() {
+        let queue = queue(Backpressure::Spill, 2);
+        queue.push(batch(0..1)).await;
+        queue.push(batch(1..2)).await;
+        queue.push(batch(2..5)).await;
+        // reading makes room in memory, but new events must come after the spilled ones
+        assert_eq!(timestamps(&[queue.pop().await.unwrap()]), [0]);
+        queue.push(batch(5..6)).await;
+
+        assert_eq!(timestamps(&pop_all(&queue).await), [1, 2, 3, 4, 5]);
+        assert_eq!(queue.stats.lost.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.stats.spilled.load(Ordering::Relaxed), 4);
+        assert_eq!(queue.stats.max_lag.load(Ordering::Relaxed), 5);
+    }
+
+    #[tokio::test]
+    async fn test_spill_file_size_is_capped() {
+        let queue = EventQueue::new(
+            Backpressure::Spill,
+            1,
+            3 * EVENT_RECORD_SIZE as u64,
+            Arc::new(SubscriberStats::new("test", Backpressure::Spill)),
+        );
+        queue.push(batch(0..1)).await;
+        queue.push(batch(1..3)).await;
+        // only one more event fits in the file
+        queue.push(batch(3..5)).await;
+        queue.push(batch(5..6)).await;
+
+        assert_eq!(timestamps(&pop_all(&queue).await), [0, 1, 2, 5]);
+        assert_eq!(queue.stats.spilled.load(Ordering::Relaxed), 3);
+        assert_eq!(queue.stats.lost.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_closed_queue_counts_losses() {
+        let queue = queue(Backpressure::Block, 2);
+        queue.push(batch(0..1)).await;
+        queue.close();
+        queue.push(batch(1..3)).await;
+
+        assert_eq!(timestamps(&pop_all(&queue).await), [0]);
+        assert_eq!(queue.stats.lost.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_block_policy_waits_for_subscriber() {
+        let queue = Arc::new(queue(Backpressure::Block, 2));
+        let publisher = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                for i in 0..10 {
+                    queue.push(batch(i..i + 1)).await;
+                }
+                queue.close();
+            }
+        });
+
+        let mut batches = vec![];
+        while let Some(batch) = queue.pop().await {
+            batches.push(batch);
+        }
+        publisher.await.unwrap();
+
+        assert_eq!(timestamps(&batches), (0..10).collect::<Vec<_>>());
+        assert_eq!(queue.stats.lost.load(Ordering::Relaxed), 0);
+        assert!(queue.stats.max_lag.load(Ordering::Relaxed) <= 2);
+    }
+}
@@ -1,8 +1,11 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 pub mod cli;
 pub mod common;
+pub mod doctor;
 pub mod ebpf;
 pub mod events;
 pub mod memlock_rlimit;
 pub mod publishers;
+pub mod session;
 pub mod subscribers;
+pub mod top;
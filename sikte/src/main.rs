@@ -1,116 +1,251 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
 };
 
-use anyhow::anyhow;
-use itertools::Itertools;
-use libc::pid_t;
 use log::info;
 use sikte::{
-    cli::args::{Cli, Commands, RecordArgs, Target, TargetArgs},
-    ebpf::{
-        SikteEbpf,
-        map_types::{PidAllowList, SyscallRingBuf},
+    cli::{
+        args::{Cli, Commands, DoctorArgs, OutputSpec, RecordArgs, Target, TargetArgs, TopArgs},
+        interval::spawn_interval_reports,
     },
-    events::EventBus,
+    common::capabilities::{
+        CapabilitySet, PROCFS_CAPABILITIES, drop_capabilities, raise_tracing_capabilities,
+    },
+    doctor::Report,
+    ebpf::map_types::DroppedCounts,
+    events::SubscriberStats,
     memlock_rlimit::bump_memlock_rlimit,
-    publishers::syscalls::{self, SyscallPublisher},
-    subscribers::ShellSubscriber,
+    session::{Session, SessionBuilder, SessionSummary},
+    subscribers::{
+        MetricsSubscriber, OtlpConfig, OtlpSubscriber, ShellSubscriber, SqliteSubscriber,
+        StatsSubscriber, spawn_metrics_server,
+    },
+    top,
 };
-use tokio::{process::Command, signal};
+use tokio::signal;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     let args = Cli::parse_args();
     env_logger::init();
-    bump_memlock_rlimit();
-
-    let mut ebpf = SikteEbpf::load()?;
 
-    let interrupted = Arc::new(AtomicBool::new(false));
+    // The doctor checks the limits as they are, before sikte changes them
+    let command = match args.command {
+        Commands::Doctor(DoctorArgs { json }) => doctor(json),
+        Commands::Record(record) => Tracing::Record(record),
+        Commands::Top(top) => Tracing::Top(top),
+    };
 
-    let mut event_bus = EventBus::new();
-    event_bus.spawn_subscription(ShellSubscriber::new());
+    // Everything that needs privileges happens first, on the only thread: capabilities belong
+    // to threads, so they have to be dropped before the runtime starts its own
+    if let Err(e) = raise_tracing_capabilities() {
+        eprintln!("Warning: failed to raise the permitted capabilities: {e}");
+    }
+    if let Err(e) = bump_memlock_rlimit() {
+        // Loading may still succeed on recent kernels, which account locked memory differently
+        eprintln!("Warning: {e}");
+    }
 
-    let child_process = match args.command {
-        Commands::Record(RecordArgs { target }) => {
-            let sys_enter = ebpf.attach_sys_enter_program()?;
-            let sys_exit = ebpf.attach_sys_exit_program()?;
-            let requirements = syscalls::Requirements::new(sys_enter, sys_exit);
+    let target = command.target().to_target();
+    // Watching reads the /proc entries of processes started later on, e.g. their executable
+    let keep = if matches!(target, Target::Matching { watch: true, .. }) {
+        PROCFS_CAPABILITIES
+    } else {
+        CapabilitySet::EMPTY
+    };
+    let mut builder = Session::builder()
+        .target(target)
+        .ebpf_config(args.ebpf.to_config())
+        .bus_config(args.bus.to_config())
+        .load()?;
+    // Output files may be somewhere only root can write to without its capabilities
+    if let Tracing::Record(RecordArgs { output, .. }) = &command {
+        builder = add_file_outputs(builder, output)?;
+    }
+    if let Err(e) = drop_capabilities(keep) {
+        eprintln!("Warning: failed to drop capabilities: {e}");
+    }
 
-            let pid_allow_list = PidAllowList::new(ebpf.pid_allow_list_map());
-            let child_process = add_pids_to_allowlist(target, &pid_allow_list).await?;
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(command, builder))
+}
 
-            let ring_buf = SyscallRingBuf::new(ebpf.syscall_events_map());
-            let tx = event_bus.tx();
-            let publisher = SyscallPublisher::new(requirements, ring_buf, interrupted.clone(), tx)?;
-            event_bus.spawn_publishment(publisher);
+/// The commands that trace, with the eBPF programs loaded
+enum Tracing {
+    Record(RecordArgs),
+    Top(TopArgs),
+}
 
-            child_process
+impl Tracing {
+    fn target(&self) -> &TargetArgs {
+        match self {
+            Tracing::Record(RecordArgs { target, .. }) | Tracing::Top(TopArgs { target, .. }) => {
+                target
+            }
         }
-    };
+    }
+}
+
+/// Print whether sikte can trace on this system and exit, with a non-zero status if it can't
+fn doctor(json: bool) -> ! {
+    let report = Report::run();
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{}", report.to_text());
+    }
+    std::process::exit(if report.ok { 0 } else { 1 });
+}
+
+/// Start tracing and wait until the traced processes exit or Ctrl-C
+async fn run(command: Tracing, mut builder: SessionBuilder) -> anyhow::Result<()> {
+    let mut interval_reports = None;
+    let session = match command {
+        Tracing::Record(RecordArgs {
+            output,
+            otlp_min_latency,
+            metrics_listen,
+            interval,
+            interval_format,
+            ..
+        }) => {
+            if let Some(interval) = interval {
+                let subscriber = StatsSubscriber::new();
+                interval_reports = Some(spawn_interval_reports(
+                    subscriber.stats(),
+                    interval,
+                    interval_format,
+                ));
+                builder = builder.subscriber(subscriber);
+            } else {
+                builder = builder.subscriber(ShellSubscriber::new());
+            }
 
-    // Wait for either Ctrl-C or child process completion
-    println!("Waiting for Ctrl-C...");
+            let mut metrics = None;
+            if metrics_listen.is_some() {
+                let subscriber = MetricsSubscriber::new();
+                metrics = Some(subscriber.metrics());
+                builder = builder.subscriber(subscriber);
+            }
+
+            builder = add_network_outputs(builder, output, otlp_min_latency);
+            let mut session = builder.start().await?;
 
-    if let Some(mut child) = child_process {
-        tokio::select! {
-            _ = signal::ctrl_c() => {
-                println!("Received Ctrl-C, exiting...");
+            if let (Some(addr), Some(metrics)) = (metrics_listen, metrics) {
+                spawn_metrics_server(addr, metrics, session.stats()).await?;
             }
-            result = child.wait() => {
-                match result {
-                    Ok(status) => println!("Traced process exited with status: {status}"),
-                    Err(e) => eprintln!("Error waiting for child process: {e}"),
-                }
+
+            // Wait for either Ctrl-C or child process completion
+            eprintln!("Waiting for Ctrl-C...");
+            eprintln!("{}", wait_for_exit(&mut session).await);
+            session
+        }
+        Tracing::Top(TopArgs { refresh, .. }) => {
+            let subscriber = StatsSubscriber::new();
+            let stats = subscriber.stats();
+            let mut session = builder.subscriber(subscriber).start().await?;
+
+            // The terminal is only usable again once the UI is gone
+            if let Some(exit) = top::run(stats, refresh, wait_for_exit(&mut session)).await? {
+                eprintln!("{exit}");
             }
+            session
         }
-    } else {
-        signal::ctrl_c().await?;
-        println!("Received Ctrl-C, exiting...");
-    }
+    };
 
-    interrupted.store(true, Ordering::Release);
+    let SessionSummary {
+        dropped,
+        subscribers,
+    } = session.stop().await?;
+    if let Some(reports) = interval_reports {
+        reports.finish().await;
+    }
+    show_dropped_events(&dropped);
+    show_subscriber_stats(&subscribers);
 
     Ok(())
 }
 
-#[allow(unstable_name_collisions)]
-async fn add_pids_to_allowlist(
-    target: TargetArgs,
-    pid_allow_list: &PidAllowList<'_>,
-) -> anyhow::Result<Option<tokio::process::Child>> {
-    match target.to_target() {
-        Target::Pid(pids) => {
-            for pid in &pids {
-                pid_allow_list.insert(*pid)?
-            }
+/// Add a subscriber for every output of the trace written to a file
+fn add_file_outputs(
+    mut builder: SessionBuilder,
+    outputs: &[OutputSpec],
+) -> anyhow::Result<SessionBuilder> {
+    for output in outputs {
+        if let OutputSpec::Sqlite(path) = output {
+            info!("Writing trace to SQLite database {}", path.display());
+            builder = builder.subscriber(SqliteSubscriber::create(path)?);
+        }
+    }
+    Ok(builder)
+}
 
-            info!(
-                "Tracing the following PIDs: {}",
-                pids.iter()
-                    .map(|pid| pid.to_string())
-                    .intersperse(", ".to_string())
-                    .collect::<String>()
-            );
-            Ok(None)
+/// Add a subscriber for every output of the trace sent over the network. Must be called inside a
+/// tokio runtime
+fn add_network_outputs(
+    mut builder: SessionBuilder,
+    outputs: Vec<OutputSpec>,
+    otlp_min_latency: Duration,
+) -> SessionBuilder {
+    for output in outputs {
+        if let OutputSpec::Otlp(endpoint) = output {
+            let mut config = OtlpConfig::new(endpoint);
+            config.min_latency = otlp_min_latency;
+            info!("Exporting syscalls as spans to {}", config.endpoint);
+            builder = builder.async_subscriber(OtlpSubscriber::spawn(config));
         }
-        Target::Command(command_args) => {
-            if command_args.is_empty() {
-                return Err(anyhow!("Command is empty"));
-            }
+    }
+    builder
+}
 
-            let program = &command_args[0];
-            let args = &command_args[1..];
+/// Print how many events the eBPF programs dropped, since the trace is incomplete if any
+fn show_dropped_events(counts: &DroppedCounts) {
+    if counts.total() == 0 {
+        eprintln!("Dropped events: 0");
+        return;
+    }
 
-            info!("Running program: {command_args:?}");
-            let child = Command::new(program).args(args).spawn()?;
-            let pid = child.id().expect("program shouldn't have stopped yet");
-            pid_allow_list.insert(pid as pid_t)?;
+    eprintln!(
+        "Dropped events: {} ({} sys_enter, {} sys_exit). The ring buffer was full and the trace \
+         is incomplete",
+        counts.total(),
+        counts.total_at_enter(),
+        counts.total_at_exit()
+    );
+    for (cpu, at_enter, at_exit) in counts.per_cpu() {
+        eprintln!("  CPU {cpu}: {at_enter} sys_enter, {at_exit} sys_exit");
+    }
+}
 
-            Ok(Some(child))
-        }
+/// Print how each subscriber kept up with the events, since slow ones may have missed some
+fn show_subscriber_stats(subscribers: &[Arc<SubscriberStats>]) {
+    for stats in subscribers {
+        eprintln!(
+            "Subscriber {} ({:?}): {} received, {} lost, {} spilled, max lag {} events",
+            stats.name,
+            stats.backpressure,
+            stats.received.load(Ordering::Relaxed),
+            stats.lost.load(Ordering::Relaxed),
+            stats.spilled.load(Ordering::Relaxed),
+            stats.max_lag.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Wait for either Ctrl-C or the traced processes to exit, and tell which one happened
+async fn wait_for_exit(session: &mut Session) -> String {
+    tokio::select! {
+        result = signal::ctrl_c() => match result {
+            Ok(()) => "Received Ctrl-C, exiting...".to_string(),
+            Err(e) => format!("Error waiting for Ctrl-C: {e}"),
+        },
+        result = session.wait() => match result {
+            Ok(Some(status)) => format!("Traced process exited with status: {status}"),
+            Ok(None) => "Traced processes exited".to_string(),
+            Err(e) => format!("Error waiting for traced processes: {e}"),
+        },
     }
 }
@@ -1,20 +1,29 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 use std::future::Future;
 
-use tokio::sync::broadcast::Sender;
-
-use crate::events::Event;
+use crate::events::EventSender;
 
 /// Extracts eBPF events from the kernel and publishes them
 pub trait EventPublisher {
     /// Get name
     fn get_name(&self) -> &str;
 
-    /// Publishes events to a given Sender<Event>.
-    /// This function may return even if there are some events left to publish so as not to hoard tokio's async queue.
-    /// Returns the number of published events before yielding, or an error.
+    /// Waits until there are events to publish, or returns early so as not to hoard tokio's
+    /// async queue.
+    /// It must be cancel safe, since it is cancelled when the publisher is stopped.
+    fn wait_events(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Publishes the events that are ready to a given EventSender.
+    /// Returns the number of published events, or an error.
+    /// It is never cancelled, so that every subscriber gets the events it sends.
     fn publish_events(
         &mut self,
-        tx: &Sender<Event>,
+        tx: &EventSender,
     ) -> impl Future<Output = anyhow::Result<u32>> + Send;
+
+    /// Publishes the events that are still pending once the publisher has been stopped.
+    /// Returns the number of published events, or an error.
+    fn drain(&mut self, _tx: &EventSender) -> impl Future<Output = anyhow::Result<u32>> + Send {
+        async { Ok(0) }
+    }
 }
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use crate::{common::generated_types::SyscallData, events::Event};
+
+/// Merges the per-CPU streams of perf buffers into a single one ordered by timestamp.
+///
+/// Each CPU writes its events in order, but the streams of different CPUs are read one after the
+/// other, so e.g. the exit of a syscall whose thread migrated may be read before its entry. Events
+/// are held until the caller knows that no older event can still arrive from another CPU.
+#[derive(Debug, Default)]
+pub struct PerCpuMerger {
+    /// Events not merged yet, indexed by CPU
+    streams: Vec<VecDeque<SyscallData>>,
+}
+
+impl PerCpuMerger {
+    pub fn new() -> PerCpuMerger {
+        PerCpuMerger::default()
+    }
+
+    /// Add an event read from the buffer of a CPU
+    pub fn push(&mut self, cpu: usize, event: SyscallData) {
+        if cpu >= self.streams.len() {
+            self.streams.resize_with(cpu + 1, VecDeque::new);
+        }
+        self.streams[cpu].push_back(event);
+    }
+
+    /// Whether events are being held
+    pub fn is_empty(&self) -> bool {
+        self.streams.iter().all(VecDeque::is_empty)
+    }
+
+    /// Remove the events with a timestamp up to `watermark`, ordered by timestamp
+    pub fn pop_until(&mut self, watermark: u64) -> Vec<Event> {
+        // Heads of the streams, the oldest one first. Ties are broken by CPU
+        let mut heads: BinaryHeap<_> = self
+            .streams
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, stream)| Some(Reverse((stream.front()?.timestamp, cpu))))
+            .collect();
+
+        let mut events = Vec::new();
+        while let Some(Reverse((timestamp, cpu))) = heads.pop() {
+            if timestamp > watermark {
+                break;
+            }
+            let stream = &mut self.streams[cpu];
+            if let Some(event) = stream.pop_front() {
+                events.push(Event::Syscall(event));
+            }
+            if let Some(next) = stream.front() {
+                heads.push(Reverse((next.timestamp, cpu)));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscribers::test_utils::{sys_enter, sys_exit};
+
+    fn timestamps(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|event| match event {
+                Event::Syscall(syscall) => syscall.timestamp,
+                Event::Lost(lost) => lost.timestamp,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_by_timestamp() {
+        let mut merger = PerCpuMerger::new();
+        // a thread migrated from CPU 0 to CPU 3 in the middle of a syscall
+        merger.push(3, sys_exit(20, 1, 1, 0));
+        merger.push(3, sys_enter(40, 1, 1, 0));
+        merger.push(0, sys_enter(10, 1, 1, 0));
+        merger.push(0, sys_enter(30, 2, 2, 0));
+        merger.push(0, sys_exit(50, 2, 2, 0));
+
+        assert_eq!(timestamps(&merger.pop_until(40)), [10, 20, 30, 40]);
+        assert!(!merger.is_empty());
+
+        // an older event may still arrive before the watermark passes it
+        merger.push(1, sys_enter(45, 3, 3, 0));
+        assert_eq!(timestamps(&merger.pop_until(u64::MAX)), [45, 50]);
+        assert!(merger.is_empty());
+        assert!(merger.pop_until(u64::MAX).is_empty());
+    }
+}
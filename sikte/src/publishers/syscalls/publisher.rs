@@ -1,23 +1,39 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
+    collections::VecDeque,
+    os::fd::{AsRawFd, RawFd},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use anyhow::anyhow;
 use log::warn;
-use tokio::sync::broadcast::Sender;
+use tokio::{
+    io::unix::AsyncFd,
+    time::{Interval, MissedTickBehavior},
+};
 
+use super::merge::PerCpuMerger;
 use crate::{
-    common::generated_types::SyscallData,
-    ebpf::{SysEnterProgram, SysExitProgram, map_types::SyscallRingBuf},
-    events::Event,
+    common::{clock, generated_types::SyscallData},
+    ebpf::{
+        SysEnterProgram, SysExitProgram,
+        map_types::{
+            DroppedCounts, DroppedEvents, SyscallEventsMap, SyscallPerfBuf, SyscallRingBuf,
+        },
+    },
+    events::{Event, EventBatch, EventSender, LostEvents},
     publishers::EventPublisher,
 };
 
+/// How often the dropped events counters are read
+const DROPPED_EVENTS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long events read from perf buffers are held, in case an older event from another CPU is
+/// read later. Perf buffers wake user space up on every event, so it only has to cover scheduling
+/// delays
+const REORDER_DELAY: Duration = Duration::from_millis(50);
+
 /// Requirements for SyscallPublisher
 pub struct Requirements {
     _sys_enter: SysEnterProgram,
@@ -33,52 +49,252 @@ impl Requirements {
     }
 }
 
-/// Publishes syscall data to an EventBus
-pub struct SyscallPublisher {
-    /// Requirements for creating this struct. These are just capability tokens
-    _requirements: Requirements,
-    /// Ring buffer for polling
-    ring_buffer: libbpf_rs::RingBuffer<'static>,
-    /// Boolean that tells us if the user interrupted the program
-    interrupted: Arc<AtomicBool>,
+/// Epoll file descriptor of a ring buffer. It is owned by the ring buffer, which must outlive it
+struct EpollFd(RawFd);
+
+impl AsRawFd for EpollFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
 }
 
-impl SyscallPublisher {
-    /// Create new SyscallPublisher with libbpf-rs RingBuffer callback pattern
-    pub fn new(
-        requirements: Requirements,
-        ring_buf: SyscallRingBuf,
-        interrupted: Arc<AtomicBool>,
-        tx: Sender<Event>,
-    ) -> Result<SyscallPublisher, libbpf_rs::Error> {
+/// Parse an event written by the eBPF programs
+fn parse_syscall_data(data: &[u8]) -> Option<SyscallData> {
+    let mut syscall_data = SyscallData::default();
+    // copy into struct to ensure memory alignment
+    match plain::copy_from_bytes(&mut syscall_data, data) {
+        Ok(()) => Some(syscall_data),
+        Err(e) => {
+            warn!("Failed to parse syscall data: {e:?}");
+            None
+        }
+    }
+}
+
+/// Kernel buffer the eBPF programs write events to. Its callbacks can't wait for the bus, so they
+/// keep the events they consume until they are taken
+enum SyscallBuffer {
+    /// Buffer shared by every CPU, whose events are already ordered
+    Ring {
+        buffer: libbpf_rs::RingBuffer<'static>,
+        consumed: Arc<Mutex<Vec<Event>>>,
+    },
+    /// Buffers of each CPU, whose streams have to be merged
+    Perf {
+        buffer: libbpf_rs::PerfBuffer<'static>,
+        merger: Arc<Mutex<PerCpuMerger>>,
+    },
+}
+
+impl SyscallBuffer {
+    fn ring(ring_buf: SyscallRingBuf) -> anyhow::Result<SyscallBuffer> {
         // Create ring buffer with callback
         let mut builder = libbpf_rs::RingBufferBuilder::new();
+        let consumed = Arc::new(Mutex::new(Vec::new()));
+        let events = consumed.clone();
 
         // Non-zero return values in the callback will stop ring buffer consumption early.
         builder.add(ring_buf.map(), move |data: &[u8]| -> i32 {
-            let mut syscall_data = SyscallData::default();
-            // copy into struct to ensure memory alignment
-            match plain::copy_from_bytes(&mut syscall_data, data) {
-                Ok(()) => {
-                    if let Err(e) = tx.send(Event::Syscall(syscall_data)) {
-                        warn!("Dropping syscall event (send failed): {e}");
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to parse syscall data: {e:?}");
-                }
-            };
+            if let Some(syscall_data) = parse_syscall_data(data) {
+                events
+                    .lock()
+                    .expect("consumed events lock poisoned")
+                    .push(Event::Syscall(syscall_data));
+            }
             0
         })?;
 
-        let ring_buffer = builder.build()?;
+        Ok(SyscallBuffer::Ring {
+            buffer: builder.build()?,
+            consumed,
+        })
+    }
+
+    fn perf(perf_buf: SyscallPerfBuf) -> anyhow::Result<SyscallBuffer> {
+        let merger = Arc::new(Mutex::new(PerCpuMerger::new()));
+        let events = merger.clone();
+
+        // Events lost because a buffer was full are counted by the eBPF programs, so there is no
+        // need for a lost callback
+        let buffer = libbpf_rs::PerfBufferBuilder::new(perf_buf.map())
+            .pages(perf_buf.pages())
+            .sample_cb(move |cpu: i32, data: &[u8]| {
+                if let Some(syscall_data) = parse_syscall_data(data) {
+                    events
+                        .lock()
+                        .expect("merger lock poisoned")
+                        .push(cpu as usize, syscall_data);
+                }
+            })
+            .build()?;
+
+        Ok(SyscallBuffer::Perf { buffer, merger })
+    }
+
+    fn epoll_fd(&self) -> RawFd {
+        match self {
+            SyscallBuffer::Ring { buffer, .. } => buffer.epoll_fd(),
+            SyscallBuffer::Perf { buffer, .. } => buffer.epoll_fd(),
+        }
+    }
+
+    /// Read the available events from the kernel, without waiting
+    fn consume(&self) -> anyhow::Result<()> {
+        match self {
+            SyscallBuffer::Ring { buffer, .. } => buffer.consume(),
+            SyscallBuffer::Perf { buffer, .. } => buffer.consume(),
+        }
+        .map_err(|e| anyhow!("Syscall buffer consume error: {}", e))
+    }
+
+    /// Take the consumed events that are ready to be sent, in timestamp order. Events from perf
+    /// buffers are ready once they are older than `watermark`
+    fn take(&self, watermark: u64) -> Vec<Event> {
+        match self {
+            // The buffer keeps its allocation for the next poll
+            SyscallBuffer::Ring { consumed, .. } => consumed
+                .lock()
+                .expect("consumed events lock poisoned")
+                .drain(..)
+                .collect(),
+            SyscallBuffer::Perf { merger, .. } => merger
+                .lock()
+                .expect("merger lock poisoned")
+                .pop_until(watermark),
+        }
+    }
+
+    /// Whether consumed events are held until they are ready
+    fn is_holding(&self) -> bool {
+        match self {
+            SyscallBuffer::Ring { .. } => false,
+            SyscallBuffer::Perf { merger, .. } => {
+                !merger.lock().expect("merger lock poisoned").is_empty()
+            }
+        }
+    }
+}
+
+/// Registration of a publisher with the tokio runtime. It is made when first polled, so that
+/// publishers can be created before the runtime is started
+struct Polling {
+    /// Becomes readable when the buffer has data. It must be deregistered before the buffer
+    /// closes the file descriptor
+    buffer_fd: AsyncFd<EpollFd>,
+    /// Ticks when the dropped events counters have to be checked again
+    dropped_check: Interval,
+    /// Ticks when held events may have become ready
+    reorder_check: Interval,
+}
+
+impl Polling {
+    fn new(buffer: &SyscallBuffer) -> anyhow::Result<Polling> {
+        let buffer_fd = AsyncFd::new(EpollFd(buffer.epoll_fd()))?;
+
+        let mut dropped_check = tokio::time::interval(DROPPED_EVENTS_CHECK_INTERVAL);
+        dropped_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut reorder_check = tokio::time::interval(REORDER_DELAY);
+        reorder_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Ok(Polling {
+            buffer_fd,
+            dropped_check,
+            reorder_check,
+        })
+    }
+}
+
+/// Publishes syscall data to an EventBus
+pub struct SyscallPublisher {
+    /// Requirements for creating this struct. These are just capability tokens, which detach the
+    /// programs when taken and dropped
+    requirements: Option<Requirements>,
+    /// Registration with the runtime, once polled. Declared before `buffer` so that it is dropped
+    /// first
+    polling: Option<Polling>,
+    /// Buffer to consume
+    buffer: SyscallBuffer,
+    /// Batches of events waiting to be sent to the bus
+    pending: VecDeque<EventBatch>,
+    /// Counters of events dropped in the kernel
+    dropped: DroppedEvents,
+    /// Counters as of the last check
+    last_dropped: DroppedCounts,
+}
+
+impl SyscallPublisher {
+    /// Create new SyscallPublisher with libbpf-rs buffer callback pattern. Opening perf buffers
+    /// needs `CAP_PERFMON`, so it is done right away. It doesn't need a tokio runtime
+    pub fn new(
+        requirements: Requirements,
+        events: SyscallEventsMap,
+        dropped: DroppedEvents,
+    ) -> anyhow::Result<SyscallPublisher> {
+        let buffer = match events {
+            SyscallEventsMap::RingBuf(ring_buf) => SyscallBuffer::ring(ring_buf)?,
+            SyscallEventsMap::PerfBuf(perf_buf) => SyscallBuffer::perf(perf_buf)?,
+        };
+        let last_dropped = dropped.read()?;
 
         Ok(SyscallPublisher {
-            _requirements: requirements,
-            ring_buffer,
-            interrupted,
+            requirements: Some(requirements),
+            polling: None,
+            buffer,
+            pending: VecDeque::new(),
+            dropped,
+            last_dropped,
         })
     }
+
+    /// Queue the consumed events that are ready to be sent as a single batch. Events up to
+    /// `watermark` are ready
+    fn take_consumed(&mut self, watermark: u64) {
+        let events = self.buffer.take(watermark);
+        if !events.is_empty() {
+            self.pending.push_back(events.into());
+        }
+    }
+
+    /// Timestamp up to which no event can still be on its way from another CPU
+    fn watermark() -> u64 {
+        clock::monotonic_ns().saturating_sub(REORDER_DELAY.as_nanos() as u64)
+    }
+
+    /// Send the pending batches to the bus
+    async fn send_pending(&mut self, tx: &EventSender) -> u32 {
+        let mut count = 0;
+        while let Some(batch) = self.pending.pop_front() {
+            count += batch.len() as u32;
+            tx.send_batch(batch).await;
+        }
+        count
+    }
+
+    /// Queue the events dropped in the kernel since the last check, if any
+    fn check_dropped_events(&mut self) {
+        let dropped = match self.dropped.read() {
+            Ok(dropped) => dropped,
+            Err(e) => {
+                warn!("Failed to read dropped events counters: {e}");
+                return;
+            }
+        };
+
+        let lost = LostEvents {
+            timestamp: clock::monotonic_ns(),
+            at_enter: dropped.total_at_enter() - self.last_dropped.total_at_enter(),
+            at_exit: dropped.total_at_exit() - self.last_dropped.total_at_exit(),
+        };
+        self.last_dropped = dropped;
+
+        if lost.total() > 0 {
+            warn!(
+                "Ring buffer full: dropped {} sys_enter and {} sys_exit events",
+                lost.at_enter, lost.at_exit
+            );
+            self.pending.push_back(Arc::new([Event::Lost(lost)]));
+        }
+    }
 }
 
 impl EventPublisher for SyscallPublisher {
@@ -86,20 +302,47 @@ impl EventPublisher for SyscallPublisher {
         "Syscall"
     }
 
-    async fn publish_events(&mut self, _tx: &Sender<Event>) -> anyhow::Result<u32> {
-        // Check for interruption
-        if self.interrupted.load(Ordering::Acquire) {
-            return Err(anyhow!("Interrupted by user"));
+    async fn wait_events(&mut self) -> anyhow::Result<()> {
+        let polling = match self.polling.take() {
+            Some(polling) => polling,
+            None => Polling::new(&self.buffer)?,
+        };
+        let polling = self.polling.insert(polling);
+
+        let holding = self.buffer.is_holding();
+        tokio::select! {
+            _ = polling.dropped_check.tick() => {
+                self.check_dropped_events();
+            }
+            _ = polling.reorder_check.tick(), if holding => {
+                self.take_consumed(Self::watermark());
+            }
+            guard = polling.buffer_fd.readable() => {
+                let mut guard = guard?;
+                // The callbacks registered in new() will collect events
+                let result = self.buffer.consume();
+                // Wait for the next epoll notification. One that arrived during consume() is kept
+                guard.clear_ready();
+                self.take_consumed(Self::watermark());
+                result?;
+            }
         }
+        Ok(())
+    }
+
+    async fn publish_events(&mut self, tx: &EventSender) -> anyhow::Result<u32> {
+        Ok(self.send_pending(tx).await)
+    }
 
-        // Poll ring buffer in a blocking task
-        // The callback registered in new() will send events
-        let rb = &mut self.ring_buffer;
-        let result = tokio::task::block_in_place(|| rb.poll(Duration::from_millis(100)));
+    async fn drain(&mut self, tx: &EventSender) -> anyhow::Result<u32> {
+        // Detach the programs first, so that no new events arrive while draining
+        self.requirements = None;
 
-        match result {
-            Ok(_) => Ok(0), // Event count tracked in callback
-            Err(e) => Err(anyhow!("Ring buffer poll error: {}", e)),
-        }
+        // Nothing older can arrive anymore, so every held event is ready
+        let result = self.buffer.consume();
+        self.take_consumed(u64::MAX);
+        self.check_dropped_events();
+        let count = self.send_pending(tx).await;
+        result.map(|()| count)
     }
 }
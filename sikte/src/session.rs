@@ -0,0 +1,683 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Tracing sessions for library users: load the eBPF programs, start tracing a target and
+//! publish its events to subscribers
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use itertools::Itertools;
+use libbpf_rs::MapHandle;
+use libc::pid_t;
+use log::{debug, info, warn};
+use tokio::{
+    process::{Child, Command},
+    task::JoinHandle,
+};
+
+use crate::{
+    cli::args::Target,
+    common::{
+        cgroup,
+        generated_types::{Comm, TraceConfig},
+        process_selector::{ProcessSelector, Selected},
+        procfs,
+    },
+    ebpf::{
+        EbpfConfig, ExecPrograms, SikteEbpf,
+        map_types::{
+            CommDenyList, DroppedCounts, DroppedEvents, ExecCommList, ExecPath, ExecPathList,
+            GidAllowList, PidAllowList, PidDenyList, TidAllowList, TraceConfigMap, TracedCgroupMap,
+            UidAllowList,
+        },
+    },
+    events::{BusConfig, BusStats, EventBus, SubscriberStats},
+    publishers::syscalls::{Requirements, SyscallPublisher},
+    subscribers::{AsyncEventSubscriber, EventSubscriber},
+};
+
+/// Default time given to subscribers to read the remaining events when stopping
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often traced processes are checked when waiting for them to exit
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often `/proc` is scanned for processes selected after the session started
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Spawns a subscriber on the bus once the session starts
+type SpawnSubscriber = Box<dyn FnOnce(&mut EventBus) + Send>;
+
+/// eBPF programs loaded and attached, with the publisher of their events
+struct Loaded {
+    /// Declared before `ebpf` so that the programs are detached before they are unloaded
+    publisher: SyscallPublisher,
+    exec_programs: ExecPrograms,
+    ebpf: SikteEbpf,
+}
+
+impl Loaded {
+    /// Every step that needs privileges
+    fn new(config: &EbpfConfig) -> anyhow::Result<Loaded> {
+        let mut ebpf = SikteEbpf::load_with(config)?;
+        let sys_enter = ebpf.attach_sys_enter_program()?;
+        let sys_exit = ebpf.attach_sys_exit_program()?;
+        let requirements = Requirements::new(sys_enter, sys_exit);
+        // Always attached, since the target may not be known yet when privileges are dropped
+        let exec_programs = ebpf.attach_exec_programs()?;
+
+        let dropped = DroppedEvents::new(ebpf.dropped_events_map())?;
+        let publisher = SyscallPublisher::new(requirements, ebpf.syscall_events(), dropped)?;
+        Ok(Loaded {
+            publisher,
+            exec_programs,
+            ebpf,
+        })
+    }
+}
+
+/// Target with the processes or cgroup it selects looked up, which may need privileges, e.g. to
+/// read `/proc/<pid>/exe` or `/proc/<pid>/ns/pid` of other users' processes
+enum ResolvedTarget {
+    /// Targets that select processes, threads or cgroups by ID, or only once tracing started, or
+    /// that are looked up when starting
+    Target(Target),
+    /// Processes selected when the target was resolved
+    Matching {
+        selector: ProcessSelector,
+        watch: bool,
+        pids: Vec<pid_t>,
+    },
+}
+
+impl ResolvedTarget {
+    fn new(target: Target) -> anyhow::Result<ResolvedTarget> {
+        let target = match target {
+            Target::NamespacedPid { pids, namespace } => {
+                let pid_ns = fs::metadata(&namespace)
+                    .map_err(|e| anyhow!("Invalid PID namespace {}: {e}", namespace.display()))?
+                    .ino();
+                let host_pids = procfs::host_pids(pid_ns, &pids)?;
+                if let Some(i) = host_pids.iter().position(Option::is_none) {
+                    return Err(anyhow!(
+                        "No process {} in PID namespace {}",
+                        pids[i],
+                        namespace.display()
+                    ));
+                }
+                for (pid, host_pid) in pids.iter().zip(&host_pids) {
+                    debug!("PID {pid} in pid:[{pid_ns}] is {}", host_pid.unwrap());
+                }
+                Target::Pid(host_pids.into_iter().flatten().collect())
+            }
+            Target::Matching { selector, watch } => {
+                let Selected { pids, unreadable } = selector.find()?;
+                if let Some((pid, e)) = unreadable.first() {
+                    warn!(
+                        "Couldn't tell whether {} processes have {selector}, e.g. {pid}: {e}",
+                        unreadable.len()
+                    );
+                }
+                if pids.is_empty() && !watch {
+                    return Err(anyhow!("No process with {selector}"));
+                }
+                return Ok(ResolvedTarget::Matching {
+                    selector,
+                    watch,
+                    pids,
+                });
+            }
+            Target::Container(id) => {
+                let path = cgroup::find_container(Path::new(cgroup::CGROUP_ROOT), &id)?;
+                info!("Container {id} is in cgroup {}", path.display());
+                Target::Cgroup(path)
+            }
+            target => target,
+        };
+        Ok(ResolvedTarget::Target(target))
+    }
+
+    /// Whether processes can start being traced until the session stops
+    fn is_open_ended(&self) -> bool {
+        match self {
+            ResolvedTarget::Target(target) => matches!(
+                target,
+                Target::Users { .. }
+                    | Target::Matching { watch: true, .. }
+                    | Target::OnExec(_)
+                    | Target::All { .. }
+            ),
+            ResolvedTarget::Matching { watch, .. } => *watch,
+        }
+    }
+}
+
+/// Builder of a tracing [`Session`]
+pub struct SessionBuilder {
+    /// eBPF programs loaded ahead of [`SessionBuilder::start`], if any
+    loaded: Option<Loaded>,
+    target: Option<Target>,
+    /// Target resolved ahead of [`SessionBuilder::start`], if any
+    resolved: Option<ResolvedTarget>,
+    ebpf: EbpfConfig,
+    bus: BusConfig,
+    shutdown_timeout: Duration,
+    subscribers: Vec<SpawnSubscriber>,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> SessionBuilder {
+        SessionBuilder {
+            loaded: None,
+            target: None,
+            resolved: None,
+            ebpf: EbpfConfig::default(),
+            bus: BusConfig::default(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            subscribers: vec![],
+        }
+    }
+
+    /// What to trace
+    pub fn target(mut self, target: Target) -> SessionBuilder {
+        self.target = Some(target);
+        self.resolved = None;
+        self
+    }
+
+    /// Trace running processes
+    pub fn pids(self, pids: impl IntoIterator<Item = pid_t>) -> SessionBuilder {
+        self.target(Target::Pid(pids.into_iter().collect()))
+    }
+
+    /// Trace running threads, without the other threads of their processes
+    pub fn tids(self, tids: impl IntoIterator<Item = pid_t>) -> SessionBuilder {
+        self.target(Target::Tid {
+            tids: tids.into_iter().collect(),
+            pids: vec![],
+        })
+    }
+
+    /// Trace the processes whose real user is one of `uids` or whose real group is one of
+    /// `gids`, including those started later on
+    pub fn users(
+        self,
+        uids: impl IntoIterator<Item = u32>,
+        gids: impl IntoIterator<Item = u32>,
+    ) -> SessionBuilder {
+        self.target(Target::Users {
+            uids: uids.into_iter().collect(),
+            gids: gids.into_iter().collect(),
+        })
+    }
+
+    /// Run a command when starting, and trace it
+    pub fn command(self, args: impl IntoIterator<Item = impl Into<String>>) -> SessionBuilder {
+        self.target(Target::Command(args.into_iter().map(Into::into).collect()))
+    }
+
+    /// Trace the running processes selected, and with `watch` those selected later on
+    pub fn matching(self, selector: ProcessSelector, watch: bool) -> SessionBuilder {
+        self.target(Target::Matching { selector, watch })
+    }
+
+    /// Trace the processes that execute one of these files after the session started, given by
+    /// path as passed to `execve` or by command name
+    pub fn on_exec(self, patterns: impl IntoIterator<Item = impl Into<String>>) -> SessionBuilder {
+        self.target(Target::OnExec(
+            patterns.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    /// Trace the processes in a cgroup v2 directory or its descendants
+    pub fn cgroup(self, path: impl Into<PathBuf>) -> SessionBuilder {
+        self.target(Target::Cgroup(path.into()))
+    }
+
+    /// Trace the processes in a container, given by full or short ID
+    pub fn container(self, id: impl Into<String>) -> SessionBuilder {
+        self.target(Target::Container(id.into()))
+    }
+
+    /// Trace every process except sikte and its ancestors up to its session leader, the given
+    /// PIDs, and the processes whose main thread has one of the given command names
+    pub fn all(
+        self,
+        exclude_pids: impl IntoIterator<Item = pid_t>,
+        exclude_comms: impl IntoIterator<Item = impl Into<String>>,
+    ) -> SessionBuilder {
+        self.target(Target::All {
+            exclude_pids: exclude_pids.into_iter().collect(),
+            exclude_comms: exclude_comms.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Sizes of the eBPF maps. It must be set before [`SessionBuilder::load`]
+    pub fn ebpf_config(mut self, config: EbpfConfig) -> SessionBuilder {
+        self.ebpf = config;
+        self
+    }
+
+    /// Capacity and backpressure policy of the event bus
+    pub fn bus_config(mut self, config: BusConfig) -> SessionBuilder {
+        self.bus = config;
+        self
+    }
+
+    /// Time given to subscribers to read the remaining events when stopping
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> SessionBuilder {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Add a subscriber to the events of the session
+    pub fn subscriber<S>(mut self, subscriber: S) -> SessionBuilder
+    where
+        S: EventSubscriber + Send + 'static,
+    {
+        self.subscribers
+            .push(Box::new(|bus| bus.spawn_subscription(subscriber)));
+        self
+    }
+
+    /// Add a subscriber whose handlers await to the events of the session
+    pub fn async_subscriber<S>(mut self, subscriber: S) -> SessionBuilder
+    where
+        S: AsyncEventSubscriber + Send + 'static,
+    {
+        self.subscribers
+            .push(Box::new(|bus| bus.spawn_async_subscription(subscriber)));
+        self
+    }
+
+    /// Load and attach the eBPF programs now rather than when starting, and look up the processes
+    /// or cgroup of the target, if already set. These are the only steps that need privileges,
+    /// so they can be dropped before the session starts, see
+    /// [`crate::common::capabilities::drop_capabilities`]. It doesn't need a tokio runtime
+    pub fn load(mut self) -> anyhow::Result<SessionBuilder> {
+        if self.loaded.is_none() {
+            self.loaded = Some(Loaded::new(&self.ebpf)?);
+        }
+        if let Some(target) = self.target.take() {
+            self.resolved = Some(ResolvedTarget::new(target)?);
+        }
+        Ok(self)
+    }
+
+    /// Load the eBPF programs and look up the target unless they already are, and start tracing
+    /// it. Must be called inside a tokio runtime
+    pub async fn start(self) -> anyhow::Result<Session> {
+        let target = self
+            .resolved
+            .or_else(|| self.target.map(ResolvedTarget::Target))
+            .ok_or_else(|| anyhow!("No target to trace"))?;
+        let Loaded {
+            publisher,
+            exec_programs,
+            ebpf,
+        } = match self.loaded {
+            Some(loaded) => loaded,
+            None => Loaded::new(&self.ebpf)?,
+        };
+
+        let mut event_bus = EventBus::with_config(self.bus);
+        for spawn in self.subscribers {
+            spawn(&mut event_bus);
+        }
+        event_bus.spawn_publishment(publisher);
+
+        let open_ended = target.is_open_ended();
+        let StartedTarget {
+            pids,
+            child,
+            watcher,
+            cgroup,
+        } = start_target(target, &ebpf)?;
+
+        Ok(Session {
+            event_bus,
+            _exec_programs: exec_programs,
+            ebpf,
+            open_ended,
+            pids,
+            cgroup,
+            child,
+            watcher,
+            shutdown_timeout: self.shutdown_timeout,
+        })
+    }
+}
+
+/// Processes traced once the target started
+#[derive(Default)]
+struct StartedTarget {
+    pids: Vec<pid_t>,
+    child: Option<Child>,
+    watcher: Option<JoinHandle<()>>,
+    /// Traced cgroup, if any
+    cgroup: Option<PathBuf>,
+}
+
+/// Add the target's processes to the allow list, running the command first if needed, or
+/// configure the eBPF programs to trace every process
+#[allow(unstable_name_collisions)]
+fn start_target(target: ResolvedTarget, ebpf: &SikteEbpf) -> anyhow::Result<StartedTarget> {
+    let pid_allow_list = PidAllowList::new(ebpf.pid_allow_list_map());
+    let target = match target {
+        ResolvedTarget::Target(target) => target,
+        ResolvedTarget::Matching {
+            selector,
+            watch,
+            pids,
+        } => {
+            for pid in &pids {
+                pid_allow_list.insert(*pid)?
+            }
+            info!("Tracing the processes with {selector}: {pids:?}");
+
+            let watcher = watch
+                .then(|| -> anyhow::Result<_> {
+                    let allow_list = MapHandle::try_from(ebpf.pid_allow_list_map())?;
+                    let traced = pids.iter().copied().collect();
+                    Ok(tokio::spawn(watch_processes(selector, allow_list, traced)))
+                })
+                .transpose()?;
+            return Ok(StartedTarget {
+                pids,
+                watcher,
+                ..Default::default()
+            });
+        }
+    };
+    match target {
+        Target::Pid(pids) => {
+            for pid in &pids {
+                pid_allow_list.insert(*pid)?
+            }
+
+            info!(
+                "Tracing the following PIDs: {}",
+                pids.iter()
+                    .map(|pid| pid.to_string())
+                    .intersperse(", ".to_string())
+                    .collect::<String>()
+            );
+            Ok(StartedTarget {
+                pids,
+                ..Default::default()
+            })
+        }
+        Target::Tid { tids, pids } => {
+            let tid_allow_list = TidAllowList::new(ebpf.tid_allow_list_map());
+            for tid in &tids {
+                tid_allow_list.insert(*tid)?
+            }
+            for pid in &pids {
+                pid_allow_list.insert(*pid)?
+            }
+
+            info!("Tracing the following threads: {tids:?}, and processes: {pids:?}");
+            Ok(StartedTarget {
+                pids: tids.into_iter().chain(pids).collect(),
+                ..Default::default()
+            })
+        }
+        Target::Users { uids, gids } => {
+            let uid_allow_list = UidAllowList::new(ebpf.uid_allow_list_map());
+            for uid in &uids {
+                uid_allow_list.insert(*uid)?;
+            }
+            let gid_allow_list = GidAllowList::new(ebpf.gid_allow_list_map());
+            for gid in &gids {
+                gid_allow_list.insert(*gid)?;
+            }
+
+            TraceConfigMap::new(ebpf.trace_config_map()).set(&TraceConfig {
+                trace_users: 1,
+                ..Default::default()
+            })?;
+            info!("Tracing the processes of users {uids:?} and groups {gids:?}");
+            Ok(StartedTarget::default())
+        }
+        Target::Command(command_args) => {
+            if command_args.is_empty() {
+                return Err(anyhow!("Command is empty"));
+            }
+
+            let program = &command_args[0];
+            let args = &command_args[1..];
+
+            info!("Running program: {command_args:?}");
+            let child = Command::new(program).args(args).spawn()?;
+            let pid = child.id().expect("program shouldn't have stopped yet") as pid_t;
+            pid_allow_list.insert(pid)?;
+
+            Ok(StartedTarget {
+                pids: vec![pid],
+                child: Some(child),
+                ..Default::default()
+            })
+        }
+        Target::OnExec(patterns) => {
+            if patterns.is_empty() {
+                return Err(anyhow!("No executable to wait for"));
+            }
+            let exec_path_list = ExecPathList::new(ebpf.exec_path_list_map());
+            let exec_comm_list = ExecCommList::new(ebpf.exec_comm_list_map());
+            for pattern in &patterns {
+                // Paths as passed to execve, or else command names, which have no `/`
+                if pattern.contains('/') {
+                    exec_path_list.insert(ExecPath::from(pattern.as_str()))?;
+                } else {
+                    exec_comm_list.insert(Comm::from(pattern.as_str()))?;
+                }
+            }
+
+            info!("Tracing the processes executing {patterns:?}");
+            Ok(StartedTarget::default())
+        }
+        Target::Cgroup(path) => start_cgroup(path, ebpf),
+        // Looked up now, when the target was set after loading the eBPF programs
+        target
+        @ (Target::NamespacedPid { .. } | Target::Matching { .. } | Target::Container(_)) => {
+            start_target(ResolvedTarget::new(target)?, ebpf)
+        }
+        Target::All {
+            exclude_pids,
+            exclude_comms,
+        } => {
+            let pid_deny_list = PidDenyList::new(ebpf.pid_deny_list_map());
+            // Tracing sikte would feed back its own syscalls, e.g. writing the trace, and so
+            // would tracing the processes relaying its output, e.g. sudo or the shell
+            let own_pids = procfs::session_ancestors(std::process::id() as pid_t);
+            for pid in own_pids.iter().chain(&exclude_pids) {
+                pid_deny_list.insert(*pid)?;
+            }
+            let comm_deny_list = CommDenyList::new(ebpf.comm_deny_list_map());
+            for comm in &exclude_comms {
+                comm_deny_list.insert(Comm::from(comm.as_str()))?;
+            }
+
+            TraceConfigMap::new(ebpf.trace_config_map()).set(&TraceConfig {
+                trace_all: 1,
+                ..Default::default()
+            })?;
+            info!(
+                "Tracing every process except sikte and its ancestors {own_pids:?}, PIDs \
+                 {exclude_pids:?} and commands {exclude_comms:?}"
+            );
+            Ok(StartedTarget::default())
+        }
+    }
+}
+
+/// Set the traced cgroup, and configure the eBPF programs to check it and its descendants instead
+/// of the PIDs
+fn start_cgroup(path: PathBuf, ebpf: &SikteEbpf) -> anyhow::Result<StartedTarget> {
+    cgroup::validate(&path)?;
+    let dir = fs::File::open(&path)
+        .map_err(|e| anyhow!("Failed to open cgroup {}: {e}", path.display()))?;
+    TracedCgroupMap::new(ebpf.traced_cgroup_map()).set(&dir)?;
+
+    TraceConfigMap::new(ebpf.trace_config_map()).set(&TraceConfig {
+        trace_cgroups: 1,
+        ..Default::default()
+    })?;
+    info!(
+        "Tracing the processes in cgroup {} and its descendants",
+        path.display()
+    );
+    Ok(StartedTarget {
+        cgroup: Some(path),
+        ..Default::default()
+    })
+}
+
+/// Keep the allow list in sync with the processes selected, as they start and exit, until
+/// aborted
+async fn watch_processes(
+    selector: ProcessSelector,
+    allow_list: MapHandle,
+    mut traced: HashSet<pid_t>,
+) {
+    let allow_list = PidAllowList::new(&allow_list);
+    let mut unreadable = HashSet::new();
+    let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let found = match selector.find() {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Failed to look for processes with {selector}: {e}");
+                continue;
+            }
+        };
+        let selected: HashSet<pid_t> = found.pids.into_iter().collect();
+        // Only warned about once, as they are checked again on every scan
+        for (pid, e) in &found.unreadable {
+            if !unreadable.contains(pid) {
+                warn!("Couldn't tell whether process {pid} has {selector}: {e}");
+            }
+        }
+        unreadable = found.unreadable.into_iter().map(|(pid, _)| pid).collect();
+
+        for &pid in selected.difference(&traced) {
+            match allow_list.insert(pid) {
+                Ok(()) => info!("Tracing new process {pid} with {selector}"),
+                Err(e) => warn!("Failed to trace new process {pid}: {e}"),
+            }
+        }
+        // Exited processes leave room in the allow list, and their PIDs may be reused
+        for &pid in traced.difference(&selected) {
+            debug!("Process {pid} exited or isn't selected anymore");
+            if let Err(e) = allow_list.remove(pid) {
+                warn!("Failed to stop tracing process {pid}: {e}");
+            }
+        }
+        traced = selected;
+    }
+}
+
+/// Running tracing session. Events are published to its subscribers until it is stopped
+pub struct Session {
+    /// Declared before `ebpf` so that tracing stops before the programs are unloaded
+    event_bus: EventBus,
+    _exec_programs: ExecPrograms,
+    ebpf: SikteEbpf,
+    /// Whether processes can start being traced until the session stops, besides `pids`
+    open_ended: bool,
+    /// Traced processes, or threads
+    pids: Vec<pid_t>,
+    /// Traced cgroup, if any
+    cgroup: Option<PathBuf>,
+    /// Process started to be traced, if any
+    child: Option<Child>,
+    /// Task adding the processes selected after the session started, if any
+    watcher: Option<JoinHandle<()>>,
+    shutdown_timeout: Duration,
+}
+
+impl Session {
+    /// Start building a session
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+
+    /// Health counters of the event bus
+    pub fn stats(&self) -> Arc<BusStats> {
+        self.event_bus.stats()
+    }
+
+    /// Events the eBPF programs dropped so far
+    pub fn dropped_events(&self) -> anyhow::Result<DroppedCounts> {
+        Ok(DroppedEvents::new(self.ebpf.dropped_events_map())?.read()?)
+    }
+
+    /// Wait until the traced processes exit, or the traced cgroup is removed. Returns the exit
+    /// status of the command, if the session started one. Never returns when processes can
+    /// start being traced later, e.g. when tracing every process
+    pub async fn wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+        if self.open_ended {
+            return std::future::pending().await;
+        }
+        if let Some(child) = &mut self.child {
+            return Ok(Some(child.wait().await?));
+        }
+
+        let mut ticker = tokio::time::interval(EXIT_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let exited = match &self.cgroup {
+                // Container runtimes remove the cgroup once the container stopped
+                Some(cgroup) => !cgroup.exists(),
+                None => !self.pids.iter().any(|&pid| procfs::exists(pid)),
+            };
+            if exited {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Stop tracing, let the subscribers read the remaining events and finish, and unload the
+    /// eBPF programs. A command started by the session keeps running
+    pub async fn stop(self) -> anyhow::Result<SessionSummary> {
+        let Session {
+            event_bus,
+            ebpf,
+            watcher,
+            shutdown_timeout,
+            ..
+        } = self;
+        if let Some(watcher) = watcher {
+            watcher.abort();
+        }
+        let stats = event_bus.stats();
+        event_bus.shutdown(shutdown_timeout).await;
+
+        Ok(SessionSummary {
+            dropped: DroppedEvents::new(ebpf.dropped_events_map())?.read()?,
+            subscribers: stats.subscribers(),
+        })
+    }
+}
+
+/// What happened during a session, once stopped
+#[derive(Debug)]
+pub struct SessionSummary {
+    /// Events the eBPF programs dropped because the ring buffer was full
+    pub dropped: DroppedCounts,
+    /// How each subscriber kept up with the events
+    pub subscribers: Vec<Arc<SubscriberStats>>,
+}
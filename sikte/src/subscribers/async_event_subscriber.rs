@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::future::Future;
+
+use super::EventSubscriber;
+use crate::events::{Event, EventKinds};
+
+/// Reads eBPF events with handlers that can await, so that subscribers writing to a socket or a
+/// database don't block the runtime. Events come in batches, one per publisher poll, and only of
+/// the kinds the subscriber is interested in
+pub trait AsyncEventSubscriber {
+    /// Get name
+    fn get_name(&self) -> &str;
+    /// Kinds of events to deliver to this subscriber
+    fn interests(&self) -> EventKinds {
+        EventKinds::ALL
+    }
+    /// Reads a batch of events
+    fn read_events(&mut self, events: &[Event]) -> impl Future<Output = ()> + Send;
+    /// Called once every event has been read, before shutting down
+    fn finish(&mut self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+    /// See [`EventSubscriber::is_lossless`]
+    fn is_lossless(&self) -> bool {
+        false
+    }
+}
+
+/// Runs an [`EventSubscriber`] as an [`AsyncEventSubscriber`] whose handlers complete immediately
+pub struct SyncSubscriber<S>(pub S);
+
+impl<S> AsyncEventSubscriber for SyncSubscriber<S>
+where
+    S: EventSubscriber + Send,
+{
+    fn get_name(&self) -> &str {
+        self.0.get_name()
+    }
+
+    fn interests(&self) -> EventKinds {
+        self.0.interests()
+    }
+
+    async fn read_events(&mut self, events: &[Event]) {
+        self.0.read_events(events)
+    }
+
+    async fn finish(&mut self) {
+        self.0.finish()
+    }
+
+    fn is_lossless(&self) -> bool {
+        self.0.is_lossless()
+    }
+}
@@ -1,10 +1,38 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
-use crate::common::generated_types::SyscallData;
+use crate::{
+    common::generated_types::SyscallData,
+    events::{Event, EventKinds, LostEvents},
+};
 
-/// Reads eBPF events
+/// Reads eBPF events. The bus only delivers the kinds of events the subscriber is interested in,
+/// and the handlers of the other kinds do nothing by default
 pub trait EventSubscriber {
     /// Get name
     fn get_name(&self) -> &str;
+    /// Kinds of events to deliver to this subscriber
+    fn interests(&self) -> EventKinds {
+        EventKinds::ALL
+    }
+    /// Reads a batch of events, only of the kinds the subscriber is interested in. By default it
+    /// hands every event to its handler; subscribers can override it to process whole slices
+    fn read_events(&mut self, events: &[Event]) {
+        for event in events {
+            match event {
+                Event::Syscall(syscall_data) => self.read_syscall(syscall_data),
+                Event::Lost(lost) => self.read_lost(lost),
+            }
+        }
+    }
     /// Reads a Syscall event's data
-    fn read_syscall(&mut self, syscall_data: &SyscallData);
+    fn read_syscall(&mut self, _syscall_data: &SyscallData) {}
+    /// Reads a report of events dropped by the eBPF programs. Events around it may be missing
+    fn read_lost(&mut self, _lost: &LostEvents) {}
+    /// Called once every event has been read, before shutting down
+    fn finish(&mut self) {}
+    /// Whether the subscriber must not lose events, e.g. because it writes a trace file. The bus
+    /// spills the events of lossless subscribers to disk rather than dropping them, unless it
+    /// was configured to block instead
+    fn is_lossless(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+/// Upper bounds of the latency buckets, in ns
+pub const LATENCY_BOUNDS_NS: &[u64] = &[
+    1_000,
+    2_500,
+    5_000,
+    10_000,
+    25_000,
+    50_000,
+    100_000,
+    250_000,
+    500_000,
+    1_000_000,
+    2_500_000,
+    5_000_000,
+    10_000_000,
+    25_000_000,
+    50_000_000,
+    100_000_000,
+    250_000_000,
+    500_000_000,
+    1_000_000_000,
+    2_500_000_000,
+    5_000_000_000,
+    10_000_000_000,
+];
+
+/// Fixed-bucket histogram of syscall latencies
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    /// Count of values per bucket. The last bucket holds values above every bound
+    buckets: [u64; LATENCY_BOUNDS_NS.len() + 1],
+    /// Number of recorded values
+    count: u64,
+    /// Sum of recorded values in ns
+    sum_ns: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: [0; LATENCY_BOUNDS_NS.len() + 1],
+            count: 0,
+            sum_ns: 0,
+        }
+    }
+
+    /// Record a latency in ns
+    pub fn record(&mut self, latency_ns: u64) {
+        let bucket = LATENCY_BOUNDS_NS.partition_point(|&bound| bound < latency_ns);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ns = self.sum_ns.saturating_add(latency_ns);
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of recorded values in ns
+    pub fn sum_ns(&self) -> u64 {
+        self.sum_ns
+    }
+
+    /// Mean of recorded values in ns
+    pub fn mean_ns(&self) -> u64 {
+        self.sum_ns.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Estimate the `q`-quantile (`0.0..=1.0`) in ns, interpolating linearly inside its bucket
+    pub fn quantile_ns(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let target = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            if count == 0 || seen + count < target {
+                seen += count;
+                continue;
+            }
+
+            let Some(&upper) = LATENCY_BOUNDS_NS.get(i) else {
+                // overflow bucket has no upper bound
+                return *LATENCY_BOUNDS_NS.last().expect("bounds are not empty");
+            };
+            let lower = if i == 0 { 0 } else { LATENCY_BOUNDS_NS[i - 1] };
+            let fraction = (target - seen) as f64 / count as f64;
+            return lower + ((upper - lower) as f64 * fraction) as u64;
+        }
+
+        unreachable!("target is never above the total count")
+    }
+
+    /// Add the values recorded by another histogram
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum_ns = self.sum_ns.saturating_add(other.sum_ns);
+    }
+
+    /// Iterate over `(upper bound in ns, cumulative count)` pairs, excluding the overflow bucket
+    pub fn cumulative_buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        LATENCY_BOUNDS_NS
+            .iter()
+            .zip(self.buckets.iter())
+            .scan(0, |total, (&bound, &count)| {
+                *total += count;
+                Some((bound, *total))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles() {
+        let mut histogram = LatencyHistogram::new();
+        assert_eq!(histogram.quantile_ns(0.99), 0);
+
+        // 99 fast values in (0, 1us] and one slow value in (5ms, 10ms]
+        for _ in 0..99 {
+            histogram.record(500);
+        }
+        histogram.record(7_000_000);
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean_ns(), (99 * 500 + 7_000_000) / 100);
+        assert!(histogram.quantile_ns(0.5) <= 1_000);
+        assert!(histogram.quantile_ns(0.99) <= 1_000);
+        let p100 = histogram.quantile_ns(1.0);
+        assert!((5_000_000..=10_000_000).contains(&p100), "p100 = {p100}");
+    }
+}
@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::Duration,
+};
+
+use libc::pid_t;
+use log::info;
+use tokio::net::TcpListener;
+
+use super::{
+    EventSubscriber,
+    histogram::LatencyHistogram,
+    syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall},
+};
+use crate::{
+    common::{
+        generated_types::{CommExt, SyscallData},
+        http::{self, Response},
+    },
+    events::{BusStats, EventKind, EventKinds, LostEvents},
+};
+
+/// Content type of the Prometheus text exposition format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counters of a single `(syscall, comm)` pair
+#[derive(Debug, Default)]
+struct SyscallSeries {
+    errors: u64,
+    latency: LatencyHistogram,
+}
+
+/// Metrics collected by a [`MetricsSubscriber`]
+#[derive(Debug, Default)]
+pub struct SyscallMetrics {
+    /// Series indexed by `(syscall name, process comm)`
+    series: BTreeMap<(&'static str, Arc<str>), SyscallSeries>,
+    /// Events read by the subscriber
+    events_total: u64,
+    /// Events read during the last second
+    events_per_second: u64,
+    /// sys_enter events dropped by the eBPF programs
+    dropped_at_enter: u64,
+    /// sys_exit events dropped by the eBPF programs
+    dropped_at_exit: u64,
+}
+
+/// Metrics shared between the subscriber and the HTTP server
+pub type SharedMetrics = Arc<Mutex<SyscallMetrics>>;
+
+/// Event Subscriber that aggregates syscalls into Prometheus metrics
+pub struct MetricsSubscriber {
+    /// Matches sys_enter and sys_exit events
+    tracker: SyscallTracker,
+    /// Aggregated metrics
+    metrics: SharedMetrics,
+    /// Last command name of every process seen so far, shared by its series
+    comms: HashMap<pid_t, Arc<str>>,
+}
+
+impl Default for MetricsSubscriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsSubscriber {
+    pub fn new() -> MetricsSubscriber {
+        MetricsSubscriber {
+            tracker: SyscallTracker::new(),
+            metrics: SharedMetrics::default(),
+            comms: HashMap::new(),
+        }
+    }
+
+    /// Get a handle to the aggregated metrics
+    pub fn metrics(&self) -> SharedMetrics {
+        self.metrics.clone()
+    }
+
+    fn comm(&mut self, syscall: &CompletedSyscall) -> Arc<str> {
+        let name = syscall.process_comm.to_string_lossy();
+        let comm = self
+            .comms
+            .entry(syscall.pid)
+            .or_insert_with(|| name.as_ref().into());
+        // The process executed another file since
+        if **comm != *name {
+            *comm = name.as_ref().into();
+        }
+        comm.clone()
+    }
+}
+
+impl EventSubscriber for MetricsSubscriber {
+    fn get_name(&self) -> &'static str {
+        "Metrics"
+    }
+
+    fn interests(&self) -> EventKinds {
+        EventKinds::of(&[EventKind::Syscall, EventKind::Lost])
+    }
+
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        let completed = match self.tracker.track(syscall_data) {
+            TrackedSyscall::Completed(syscall) => Some((syscall, self.comm(&syscall))),
+            _ => None,
+        };
+
+        let mut metrics = self.metrics.lock().expect("metrics lock poisoned");
+        metrics.events_total += 1;
+
+        if let Some((syscall, comm)) = completed {
+            let series = metrics.series.entry((syscall.name(), comm)).or_default();
+            series.latency.record(syscall.duration_ns());
+            if syscall.ret < 0 {
+                series.errors += 1;
+            }
+        }
+    }
+
+    fn read_lost(&mut self, lost: &LostEvents) {
+        let mut metrics = self.metrics.lock().expect("metrics lock poisoned");
+        metrics.dropped_at_enter += lost.at_enter;
+        metrics.dropped_at_exit += lost.at_exit;
+    }
+}
+
+/// Bind `addr` and serve `/metrics` from a background task
+pub async fn spawn_metrics_server(
+    addr: SocketAddr,
+    metrics: SharedMetrics,
+    bus_stats: Arc<BusStats>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving Prometheus metrics on http://{addr}/metrics");
+
+    tokio::spawn(sample_event_rate(metrics.clone()));
+    tokio::spawn(http::serve(listener, move |path| match path {
+        "/metrics" => Response::ok(CONTENT_TYPE, render(&metrics, &bus_stats)),
+        _ => Response::not_found(),
+    }));
+
+    Ok(())
+}
+
+/// Update the events per second gauge once a second
+async fn sample_event_rate(metrics: SharedMetrics) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut last_total = 0;
+    loop {
+        ticker.tick().await;
+        let mut metrics = metrics.lock().expect("metrics lock poisoned");
+        metrics.events_per_second = metrics.events_total - last_total;
+        last_total = metrics.events_total;
+    }
+}
+
+/// Render all metrics in the Prometheus text exposition format
+fn render(metrics: &SharedMetrics, bus_stats: &BusStats) -> String {
+    let metrics = metrics.lock().expect("metrics lock poisoned");
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "sikte_syscalls_total",
+        "counter",
+        "Completed syscalls",
+    );
+    for ((syscall, comm), series) in &metrics.series {
+        let labels = labels(syscall, comm);
+        let _ = writeln!(
+            out,
+            "sikte_syscalls_total{{{labels}}} {}",
+            series.latency.count()
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_syscall_errors_total",
+        "counter",
+        "Completed syscalls that returned an error",
+    );
+    for ((syscall, comm), series) in &metrics.series {
+        let labels = labels(syscall, comm);
+        let _ = writeln!(
+            out,
+            "sikte_syscall_errors_total{{{labels}}} {}",
+            series.errors
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_syscall_duration_seconds",
+        "histogram",
+        "Time spent inside syscalls",
+    );
+    for ((syscall, comm), series) in &metrics.series {
+        let labels = labels(syscall, comm);
+        for (bound_ns, count) in series.latency.cumulative_buckets() {
+            let _ = writeln!(
+                out,
+                "sikte_syscall_duration_seconds_bucket{{{labels},le=\"{}\"}} {count}",
+                seconds(bound_ns)
+            );
+        }
+        let count = series.latency.count();
+        let _ = writeln!(
+            out,
+            "sikte_syscall_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(
+            out,
+            "sikte_syscall_duration_seconds_sum{{{labels}}} {}",
+            seconds(series.latency.sum_ns())
+        );
+        let _ = writeln!(
+            out,
+            "sikte_syscall_duration_seconds_count{{{labels}}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_events_total",
+        "counter",
+        "eBPF events received",
+    );
+    let _ = writeln!(out, "sikte_events_total {}", metrics.events_total);
+
+    header(
+        &mut out,
+        "sikte_events_per_second",
+        "gauge",
+        "eBPF events received during the last second",
+    );
+    let _ = writeln!(out, "sikte_events_per_second {}", metrics.events_per_second);
+
+    header(
+        &mut out,
+        "sikte_dropped_events_total",
+        "counter",
+        "eBPF events dropped because the ring buffer was full",
+    );
+    let _ = writeln!(
+        out,
+        "sikte_dropped_events_total{{kind=\"sys_enter\"}} {}",
+        metrics.dropped_at_enter
+    );
+    let _ = writeln!(
+        out,
+        "sikte_dropped_events_total{{kind=\"sys_exit\"}} {}",
+        metrics.dropped_at_exit
+    );
+
+    let subscribers = bus_stats.subscribers();
+    header(
+        &mut out,
+        "sikte_subscriber_lost_events_total",
+        "counter",
+        "Events a subscriber missed because its queue was full",
+    );
+    for stats in &subscribers {
+        let _ = writeln!(
+            out,
+            "sikte_subscriber_lost_events_total{{subscriber=\"{}\"}} {}",
+            escape(&stats.name),
+            stats.lost.load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_subscriber_spilled_events_total",
+        "counter",
+        "Events written to a temporary file because a subscriber's queue was full",
+    );
+    for stats in &subscribers {
+        let _ = writeln!(
+            out,
+            "sikte_subscriber_spilled_events_total{{subscriber=\"{}\"}} {}",
+            escape(&stats.name),
+            stats.spilled.load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_subscriber_max_lag_events",
+        "gauge",
+        "Largest number of events waiting for a subscriber at once",
+    );
+    for stats in &subscribers {
+        let _ = writeln!(
+            out,
+            "sikte_subscriber_max_lag_events{{subscriber=\"{}\"}} {}",
+            escape(&stats.name),
+            stats.max_lag.load(Ordering::Relaxed)
+        );
+    }
+
+    header(
+        &mut out,
+        "sikte_subscriber_received_events_total",
+        "counter",
+        "Events delivered to a subscriber",
+    );
+    for stats in &subscribers {
+        let _ = writeln!(
+            out,
+            "sikte_subscriber_received_events_total{{subscriber=\"{}\"}} {}",
+            escape(&stats.name),
+            stats.received.load(Ordering::Relaxed)
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}.");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn labels(syscall: &str, comm: &str) -> String {
+    format!("syscall=\"{}\",comm=\"{}\"", escape(syscall), escape(comm))
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(ns: u64) -> f64 {
+    ns as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        publishers::syscalls::SyscallID,
+        subscribers::test_utils::{named, sys_enter, sys_exit},
+    };
+
+    #[test]
+    fn test_render_syscall_metrics() {
+        let mut subscriber = MetricsSubscriber::new();
+
+        let openat = SyscallID::openat as i64;
+        subscriber.read_syscall(&sys_enter(0, 42, 42, openat));
+        subscriber.read_syscall(&named(sys_exit(3_000, 42, 42, -2), "nginx", "nginx"));
+        subscriber.read_syscall(&sys_enter(10_000, 42, 43, openat));
+        subscriber.read_syscall(&named(sys_exit(10_500, 42, 43, 3), "nginx", "worker-1"));
+        subscriber.read_lost(&LostEvents {
+            timestamp: 11_000,
+            at_enter: 2,
+            at_exit: 1,
+        });
+
+        let out = render(&subscriber.metrics(), &BusStats::default());
+        let labels = "syscall=\"openat\",comm=\"nginx\"";
+        for line in [
+            format!("sikte_syscalls_total{{{labels}}} 2"),
+            format!("sikte_syscall_errors_total{{{labels}}} 1"),
+            format!("sikte_syscall_duration_seconds_bucket{{{labels},le=\"0.000001\"}} 1"),
+            format!("sikte_syscall_duration_seconds_bucket{{{labels},le=\"0.0000025\"}} 1"),
+            format!("sikte_syscall_duration_seconds_bucket{{{labels},le=\"0.000005\"}} 2"),
+            format!("sikte_syscall_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 2"),
+            format!("sikte_syscall_duration_seconds_count{{{labels}}} 2"),
+            "sikte_events_total 4".to_string(),
+            "sikte_dropped_events_total{kind=\"sys_enter\"} 2".to_string(),
+            "sikte_dropped_events_total{kind=\"sys_exit\"} 1".to_string(),
+        ] {
+            assert!(
+                out.lines().any(|l| l == line),
+                "missing '{line}' in:\n{out}"
+            );
+        }
+    }
+}
@@ -1,6 +1,24 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
+mod async_event_subscriber;
 mod event_subscriber;
+mod histogram;
+mod metrics;
+mod otlp;
 mod shell;
+mod sqlite;
+mod stats;
+mod syscall_tracker;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
+pub use async_event_subscriber::{AsyncEventSubscriber, SyncSubscriber};
 pub use event_subscriber::EventSubscriber;
+pub use histogram::{LATENCY_BOUNDS_NS, LatencyHistogram};
+pub use metrics::{MetricsSubscriber, SharedMetrics, SyscallMetrics, spawn_metrics_server};
+pub use otlp::{OtlpConfig, OtlpSubscriber};
 pub use shell::ShellSubscriber;
+pub use sqlite::SqliteSubscriber;
+pub use stats::{
+    SharedStats, StatsSubscriber, StatsWindow, SyscallStats, SyscallTotals, ThreadTotals,
+};
+pub use syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall};
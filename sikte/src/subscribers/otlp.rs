@@ -0,0 +1,563 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{
+    collections::{BTreeMap, hash_map::RandomState},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use libc::pid_t;
+use log::{debug, error, warn};
+use serde_json::{Value, json};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender, error::TrySendError},
+    task::JoinHandle,
+};
+
+use super::{
+    AsyncEventSubscriber,
+    syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall},
+};
+use crate::{
+    common::{
+        clock::WallClock,
+        generated_types::{CommExt, SyscallData},
+        http::{self, HttpUrl},
+    },
+    events::{Event, EventKind, EventKinds},
+};
+
+/// Path used when the endpoint doesn't specify one, as defined by OTLP/HTTP
+const DEFAULT_TRACES_PATH: &str = "/v1/traces";
+/// `SPAN_KIND_INTERNAL`
+const SPAN_KIND_INTERNAL: u8 = 1;
+/// `STATUS_CODE_ERROR`
+const STATUS_CODE_ERROR: u8 = 2;
+/// How long a finishing subscriber waits for the exporter to send the last spans
+const FINISH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Configuration for [`OtlpSubscriber`]
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP collector endpoint, e.g. `http://localhost:4318`
+    pub endpoint: HttpUrl,
+    /// Only export syscalls that took at least this long
+    pub min_latency: Duration,
+    /// Maximum number of spans per export request
+    pub batch_size: usize,
+    /// Maximum time a span waits before being exported
+    pub flush_interval: Duration,
+    /// How many times a failed export is retried before its spans are dropped
+    pub max_retries: u32,
+    /// Number of spans that can be queued for the exporter before new ones are dropped
+    pub queue_capacity: usize,
+}
+
+impl OtlpConfig {
+    pub fn new(mut endpoint: HttpUrl) -> OtlpConfig {
+        if endpoint.path.is_empty() || endpoint.path == "/" {
+            endpoint.path = DEFAULT_TRACES_PATH.to_string();
+        }
+
+        OtlpConfig {
+            endpoint,
+            min_latency: Duration::ZERO,
+            batch_size: 512,
+            flush_interval: Duration::from_secs(1),
+            max_retries: 5,
+            queue_capacity: 8192,
+        }
+    }
+}
+
+/// Event Subscriber that exports completed syscalls as OpenTelemetry spans over OTLP/HTTP.
+///
+/// Spans are handed over to a background exporter task, which batches them and retries failed
+/// exports, so that network I/O never blocks the subscription loop. If the exporter can't keep
+/// up, spans are dropped and counted.
+pub struct OtlpSubscriber {
+    /// Matches sys_enter and sys_exit events
+    tracker: SyscallTracker,
+    /// Minimum syscall duration in ns
+    min_latency_ns: u64,
+    /// Queue towards the exporter task. Closed when finishing
+    tx: Option<Sender<CompletedSyscall>>,
+    /// Exporter task
+    exporter: Option<JoinHandle<()>>,
+    /// Spans dropped because the exporter queue was full or the exporter stopped
+    dropped: u64,
+}
+
+impl OtlpSubscriber {
+    /// Create the subscriber and spawn its exporter task. Must be called inside a tokio runtime.
+    pub fn spawn(config: OtlpConfig) -> OtlpSubscriber {
+        let (tx, rx) = mpsc::channel(config.queue_capacity);
+        let min_latency_ns = config.min_latency.as_nanos() as u64;
+
+        let exporter = tokio::spawn(OtlpExporter::new(config).run(rx));
+
+        OtlpSubscriber {
+            tracker: SyscallTracker::new(),
+            min_latency_ns,
+            tx: Some(tx),
+            exporter: Some(exporter),
+            dropped: 0,
+        }
+    }
+}
+
+impl OtlpSubscriber {
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        let TrackedSyscall::Completed(syscall) = self.tracker.track(syscall_data) else {
+            return;
+        };
+        if syscall.duration_ns() < self.min_latency_ns {
+            return;
+        }
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        // Never waits for the exporter, which may be retrying against an unavailable collector
+        match tx.try_send(syscall) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            Err(TrySendError::Closed(_)) => {
+                debug!("OTLP exporter has stopped, dropping span");
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+impl AsyncEventSubscriber for OtlpSubscriber {
+    fn get_name(&self) -> &'static str {
+        "OTLP"
+    }
+
+    fn interests(&self) -> EventKinds {
+        EventKinds::of(&[EventKind::Syscall])
+    }
+
+    async fn read_events(&mut self, events: &[Event]) {
+        for event in events {
+            if let Event::Syscall(syscall_data) = event {
+                self.read_syscall(syscall_data);
+            }
+        }
+    }
+
+    async fn finish(&mut self) {
+        // Closing the queue makes the exporter send the last batch and stop
+        self.tx = None;
+        let Some(exporter) = self.exporter.take() else {
+            return;
+        };
+        if tokio::time::timeout(FINISH_TIMEOUT, exporter)
+            .await
+            .is_err()
+        {
+            warn!("OTLP exporter did not send the last spans in time");
+        }
+    }
+}
+
+impl Drop for OtlpSubscriber {
+    fn drop(&mut self) {
+        if self.dropped > 0 {
+            warn!(
+                "OTLP exporter couldn't keep up: {} spans were dropped",
+                self.dropped
+            );
+        }
+    }
+}
+
+/// Background task that batches spans and sends them to the collector
+struct OtlpExporter {
+    config: OtlpConfig,
+    clock: WallClock,
+    ids: IdGenerator,
+}
+
+impl OtlpExporter {
+    fn new(config: OtlpConfig) -> OtlpExporter {
+        OtlpExporter {
+            config,
+            clock: WallClock::new(),
+            ids: IdGenerator::new(),
+        }
+    }
+
+    async fn run(mut self, mut rx: Receiver<CompletedSyscall>) {
+        let mut batch = Vec::with_capacity(self.config.batch_size);
+        let mut ticker = tokio::time::interval(self.config.flush_interval);
+
+        loop {
+            tokio::select! {
+                received = rx.recv() => match received {
+                    Some(syscall) => {
+                        batch.push(syscall);
+                        if batch.len() >= self.config.batch_size {
+                            self.export(&mut batch).await;
+                        }
+                    }
+                    None => {
+                        self.export(&mut batch).await;
+                        debug!("OTLP subscriber was dropped. Finished exporter");
+                        break;
+                    }
+                },
+                _ = ticker.tick() => self.export(&mut batch).await,
+            }
+        }
+    }
+
+    /// Send a batch to the collector, retrying with exponential backoff
+    async fn export(&mut self, batch: &mut Vec<CompletedSyscall>) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let body = self.encode(batch).to_string();
+        let mut backoff = Duration::from_millis(100);
+
+        for attempt in 0..=self.config.max_retries {
+            match http::post(&self.config.endpoint, "application/json", body.as_bytes()).await {
+                Ok(status) if (200..300).contains(&status) => {
+                    debug!("Exported {} spans to {}", batch.len(), self.config.endpoint);
+                    batch.clear();
+                    return;
+                }
+                Ok(status) if matches!(status, 429 | 502 | 503 | 504) => {
+                    debug!("OTLP collector answered {status} (attempt {attempt})");
+                }
+                Ok(status) => {
+                    error!("OTLP collector rejected {} spans: {status}", batch.len());
+                    batch.clear();
+                    return;
+                }
+                Err(err) => debug!("OTLP export failed (attempt {attempt}): {err}"),
+            }
+
+            if attempt < self.config.max_retries {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(5));
+            }
+        }
+
+        error!(
+            "Giving up exporting {} spans to {}",
+            batch.len(),
+            self.config.endpoint
+        );
+        batch.clear();
+    }
+
+    /// Encode spans as an OTLP/JSON `ExportTraceServiceRequest`, with one resource per thread
+    fn encode(&mut self, batch: &[CompletedSyscall]) -> Value {
+        // Spans of every thread, with its first syscall for the attributes of the resource
+        let mut by_thread: BTreeMap<(pid_t, pid_t), (CompletedSyscall, Vec<Value>)> =
+            BTreeMap::new();
+        for syscall in batch {
+            let span = self.encode_span(syscall);
+            by_thread
+                .entry((syscall.pid, syscall.tid))
+                .or_insert_with(|| (*syscall, vec![]))
+                .1
+                .push(span);
+        }
+
+        let resource_spans: Vec<Value> = by_thread
+            .into_values()
+            .map(|(thread, spans)| {
+                let mut attributes = vec![
+                    string_attribute("service.name", "sikte"),
+                    int_attribute("process.pid", thread.pid as i64),
+                    string_attribute(
+                        "process.executable.name",
+                        &thread.process_comm.to_string_lossy(),
+                    ),
+                    int_attribute("thread.id", thread.tid as i64),
+                    string_attribute("thread.name", &thread.comm.to_string_lossy()),
+                ];
+                if thread.is_namespaced() {
+                    attributes.extend([
+                        int_attribute("process.ns_pid", thread.ns_pid as i64),
+                        int_attribute("thread.ns_id", thread.ns_tid as i64),
+                        int_attribute("process.pid_ns", thread.pid_ns as i64),
+                    ]);
+                }
+                json!({
+                    "resource": { "attributes": attributes },
+                    "scopeSpans": [{
+                        "scope": {
+                            "name": "sikte",
+                            "version": env!("CARGO_PKG_VERSION"),
+                        },
+                        "spans": spans,
+                    }]
+                })
+            })
+            .collect();
+
+        json!({ "resourceSpans": resource_spans })
+    }
+
+    fn encode_span(&mut self, syscall: &CompletedSyscall) -> Value {
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.ids.next_trace_id()),
+            "spanId": format!("{:016x}", self.ids.next_span_id()),
+            "name": syscall.name(),
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": self.clock.to_unix_ns(syscall.enter_timestamp).to_string(),
+            "endTimeUnixNano": self.clock.to_unix_ns(syscall.exit_timestamp).to_string(),
+            "attributes": [
+                int_attribute("syscall.id", syscall.syscall_id),
+                int_attribute("syscall.ret", syscall.ret),
+            ],
+        });
+
+        if syscall.ret < 0 {
+            span["status"] = json!({
+                "code": STATUS_CODE_ERROR,
+                "message": format!("errno {}", -syscall.ret),
+            });
+        }
+
+        span
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP/JSON encodes 64-bit integers as strings
+fn int_attribute(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+/// Non-cryptographic generator for trace and span IDs (splitmix64)
+struct IdGenerator {
+    state: u64,
+}
+
+impl IdGenerator {
+    fn new() -> IdGenerator {
+        IdGenerator {
+            state: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// IDs must not be all zeroes
+    fn next_span_id(&mut self) -> u64 {
+        self.next_u64().max(1)
+    }
+
+    fn next_trace_id(&mut self) -> u128 {
+        (((self.next_u64() as u128) << 64) | self.next_u64() as u128).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    };
+
+    use super::*;
+    use crate::{
+        publishers::syscalls::SyscallID,
+        subscribers::test_utils::{sys_enter, sys_exit},
+    };
+
+    /// Read one HTTP request and return its body
+    async fn read_request(stream: &mut TcpStream) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before request was complete");
+            buf.extend_from_slice(&chunk[..n]);
+
+            if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map(|len| len.trim().parse().unwrap())
+                    .unwrap();
+                if buf.len() >= end + 4 + length {
+                    return buf[end + 4..end + 4 + length].to_vec();
+                }
+            }
+        }
+    }
+
+    /// Mock OTLP collector. It answers the first `failures` requests with 503, and forwards the
+    /// bodies of the successful ones
+    async fn mock_collector(failures: usize) -> (HttpUrl, mpsc::UnboundedReceiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut remaining_failures = failures;
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let body = read_request(&mut stream).await;
+                let status = if remaining_failures > 0 {
+                    remaining_failures -= 1;
+                    "503 Service Unavailable"
+                } else {
+                    tx.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                    "200 OK"
+                };
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let url = format!("http://127.0.0.1:{port}").parse().unwrap();
+        (url, rx)
+    }
+
+    /// Mock OTLP collector that accepts connections but never answers
+    async fn stalled_collector() -> HttpUrl {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                connections.push(stream);
+            }
+        });
+
+        format!("http://127.0.0.1:{port}").parse().unwrap()
+    }
+
+    fn spans(request: &Value) -> Vec<&Value> {
+        request["resourceSpans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|rs| rs["scopeSpans"][0]["spans"].as_array().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_export_slow_syscalls_as_spans() {
+        let (endpoint, mut requests) = mock_collector(0).await;
+        let mut config = OtlpConfig::new(endpoint);
+        config.min_latency = Duration::from_micros(10);
+
+        let mut subscriber = OtlpSubscriber::spawn(config);
+        let read = SyscallID::read as i64;
+        let write = SyscallID::write as i64;
+
+        let events = [
+            // slow read, exported
+            sys_enter(1_000, 100, 101, read),
+            sys_exit(51_000, 100, 101, -11),
+            // fast write, filtered out
+            sys_enter(60_000, 100, 102, write),
+            sys_exit(61_000, 100, 102, 1),
+        ]
+        .map(Event::Syscall);
+        subscriber.read_events(&events).await;
+        subscriber.finish().await;
+
+        let request = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await
+            .expect("collector received nothing")
+            .unwrap();
+
+        let spans = spans(&request);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0]["name"], "read");
+        assert_eq!(spans[0]["status"]["code"], 2);
+
+        let start: u64 = spans[0]["startTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let end: u64 = spans[0]["endTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(end - start, 50_000);
+
+        let attributes = request["resourceSpans"][0]["resource"]["attributes"]
+            .as_array()
+            .unwrap();
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|attr| attr["key"] == key)
+                .map(|attr| attr["value"].clone())
+        };
+        assert_eq!(attribute("process.pid").unwrap()["intValue"], "100");
+        assert_eq!(attribute("thread.id").unwrap()["intValue"], "101");
+    }
+
+    #[tokio::test]
+    async fn test_export_retries_when_collector_is_unavailable() {
+        let (endpoint, mut requests) = mock_collector(2).await;
+        let mut subscriber = OtlpSubscriber::spawn(OtlpConfig::new(endpoint));
+
+        let getpid = SyscallID::getpid as i64;
+        let events = [
+            sys_enter(1_000, 200, 200, getpid),
+            sys_exit(2_000, 200, 200, 200),
+        ]
+        .map(Event::Syscall);
+        subscriber.read_events(&events).await;
+        // the exporter keeps retrying in the background
+        drop(subscriber);
+
+        let request = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await
+            .expect("collector received nothing")
+            .unwrap();
+        assert_eq!(spans(&request)[0]["name"], "getpid");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_collector_does_not_block_subscriber() {
+        let mut config = OtlpConfig::new(stalled_collector().await);
+        config.batch_size = 1;
+        config.queue_capacity = 4;
+        let mut subscriber = OtlpSubscriber::spawn(config);
+
+        let getpid = SyscallID::getpid as i64;
+        let events: Vec<Event> = (0..100)
+            .flat_map(|i| {
+                [
+                    sys_enter(i * 1_000, 300, 300, getpid),
+                    sys_exit(i * 1_000 + 500, 300, 300, 300),
+                ]
+            })
+            .map(Event::Syscall)
+            .collect();
+
+        // The exporter is stuck sending the first span, so the queue fills up
+        tokio::time::timeout(Duration::from_secs(1), subscriber.read_events(&events))
+            .await
+            .expect("subscriber waited for the collector");
+        assert!(subscriber.dropped > 0);
+    }
+}
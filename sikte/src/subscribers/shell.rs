@@ -1,21 +1,21 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
-use std::collections::HashMap;
-
-use libc::pid_t;
-use log::{trace, warn};
-
-use super::EventSubscriber;
+use super::{
+    EventSubscriber,
+    syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall, thread_label},
+};
 use crate::{
-    common::generated_types::{SyscallData, SyscallStateExt, syscall_state_tag},
-    publishers::syscalls::SyscallID,
+    common::generated_types::SyscallData,
+    events::{EventKind, EventKinds, LostEvents},
 };
 
 /// Event Subscriber that writes to stdout
 pub struct ShellSubscriber {
-    /// Match a thread to its last registered sys_enter event
-    thr_to_last_sys_enter: HashMap<pid_t, SyscallData>,
+    /// Matches sys_enter and sys_exit events
+    tracker: SyscallTracker,
     /// Total time spent on syscalls in us
     total_syscalls_time: f64,
+    /// Events dropped by the eBPF programs
+    lost_events: u64,
 }
 
 impl Default for ShellSubscriber {
@@ -27,8 +27,9 @@ impl Default for ShellSubscriber {
 impl ShellSubscriber {
     pub fn new() -> ShellSubscriber {
         ShellSubscriber {
-            thr_to_last_sys_enter: HashMap::new(),
+            tracker: SyscallTracker::new(),
             total_syscalls_time: 0f64,
+            lost_events: 0,
         }
     }
 }
@@ -36,6 +37,12 @@ impl ShellSubscriber {
 impl ShellSubscriber {
     fn show_summary(&self) {
         println!("Spent time on syscalls: {:.2} us", self.total_syscalls_time);
+        if self.lost_events > 0 {
+            println!(
+                "Missed {} events, the time spent on syscalls is incomplete",
+                self.lost_events
+            );
+        }
     }
 }
 
@@ -44,49 +51,54 @@ impl EventSubscriber for ShellSubscriber {
         "Shell"
     }
 
-    fn read_syscall(&mut self, syscall_data: &SyscallData) {
-        let SyscallData {
-            timestamp,
-            state,
-            // convert from kernel tgid/pid notation -> userspace pid/tid
-            tgid: pid,
-            pid: tid,
-        } = *syscall_data;
-
-        match state.tag {
-            syscall_state_tag::AT_ENTER => {
-                trace!("sys_enter: pid {pid}, tid {tid}");
-                self.thr_to_last_sys_enter.insert(tid, *syscall_data);
-            }
-            syscall_state_tag::AT_EXIT => {
-                trace!("sys_exit: pid {pid}, tid {tid}");
+    fn interests(&self) -> EventKinds {
+        EventKinds::of(&[EventKind::Syscall, EventKind::Lost])
+    }
 
-                match self.thr_to_last_sys_enter.remove(&tid) {
-                    Some(last_data) => match last_data.state.syscall_id() {
-                        Some(syscall_id) => {
-                            let syscall_name = SyscallID::try_from(syscall_id)
-                                .map(|id| id.as_str())
-                                .unwrap_or("???");
-                            let time_ns = timestamp.saturating_sub(last_data.timestamp);
-                            let time_us = time_ns as f64 / 1000f64;
-                            println!("({pid}/{tid}) {syscall_name} (took {time_us:.2} us)");
-                            self.total_syscalls_time += time_us;
-                        }
-                        None => warn!("Unexpected non-AT_ENTER stored for tid {tid}"),
-                    },
-                    None => println!("({pid}/{tid}) ??? (took ??? us)"),
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        match self.tracker.track(syscall_data) {
+            TrackedSyscall::Completed(syscall) => {
+                let CompletedSyscall {
+                    ns_pid,
+                    ns_tid,
+                    pid_ns,
+                    ..
+                } = syscall;
+                let thread = syscall.thread_label();
+                let syscall_name = syscall.name();
+                let time_us = syscall.duration_ns() as f64 / 1000f64;
+                if syscall.is_namespaced() {
+                    println!(
+                        "{thread} ({ns_pid}/{ns_tid} in pid:[{pid_ns}]) {syscall_name} (took \
+                         {time_us:.2} us)"
+                    );
+                } else {
+                    println!("{thread} {syscall_name} (took {time_us:.2} us)");
                 }
+                self.total_syscalls_time += time_us;
             }
-            _ => trace!(
-                "Unknown syscall state tag {} for pid {pid}, tid {tid}",
-                state.tag
+            TrackedSyscall::Unmatched {
+                pid,
+                tid,
+                comm,
+                process_comm,
+            } => println!(
+                "{} ??? (took ??? us)",
+                thread_label(&process_comm, pid, &comm, tid)
             ),
+            TrackedSyscall::Entered | TrackedSyscall::Unknown => {}
         }
     }
-}
 
-impl Drop for ShellSubscriber {
-    fn drop(&mut self) {
+    fn read_lost(&mut self, lost: &LostEvents) {
+        println!(
+            "--- gap: {} sys_enter and {} sys_exit events were dropped ---",
+            lost.at_enter, lost.at_exit
+        );
+        self.lost_events += lost.total();
+    }
+
+    fn finish(&mut self) {
         self.show_summary();
     }
 }
@@ -0,0 +1,480 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{collections::HashMap, path::Path};
+
+use anyhow::anyhow;
+use libc::pid_t;
+use log::{debug, error, warn};
+use rusqlite::{Connection, params};
+
+use super::{
+    EventSubscriber,
+    syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall},
+};
+use crate::{
+    common::generated_types::{CommExt, SyscallData},
+    events::{EventKind, EventKinds, LostEvents},
+};
+
+/// Number of completed syscalls inserted per transaction
+const BATCH_SIZE: usize = 4096;
+
+/// Completed syscalls kept while the database can't be written, retrying every batch. Past it,
+/// the subscriber gives up
+const MAX_PENDING: usize = 64 * BATCH_SIZE;
+
+/// Tables, indexes and views of a trace database
+const SCHEMA: &str = "
+-- Processes and threads are identified by their ID and the start time of the process, in ns since
+-- boot, since IDs are reused. ns_pid and ns_tid are the IDs in the PID namespace pid_ns, e.g. of
+-- a container, and comm is the last command name seen
+CREATE TABLE IF NOT EXISTS processes (
+    pid           INTEGER NOT NULL,
+    start_ns      INTEGER NOT NULL,
+    comm          TEXT    NOT NULL,
+    ns_pid        INTEGER NOT NULL,
+    pid_ns        INTEGER NOT NULL,
+    first_seen_ns INTEGER NOT NULL,
+    last_seen_ns  INTEGER NOT NULL,
+    PRIMARY KEY (pid, start_ns)
+);
+
+CREATE TABLE IF NOT EXISTS threads (
+    tid           INTEGER NOT NULL,
+    pid           INTEGER NOT NULL,
+    start_ns      INTEGER NOT NULL,
+    comm          TEXT    NOT NULL,
+    ns_tid        INTEGER NOT NULL,
+    first_seen_ns INTEGER NOT NULL,
+    last_seen_ns  INTEGER NOT NULL,
+    PRIMARY KEY (tid, start_ns),
+    FOREIGN KEY (pid, start_ns) REFERENCES processes(pid, start_ns)
+);
+
+CREATE TABLE IF NOT EXISTS syscalls (
+    id          INTEGER PRIMARY KEY,
+    pid         INTEGER NOT NULL,
+    tid         INTEGER NOT NULL,
+    start_ns    INTEGER NOT NULL,
+    syscall_id  INTEGER NOT NULL,
+    name        TEXT    NOT NULL,
+    enter_ns    INTEGER NOT NULL,
+    exit_ns     INTEGER NOT NULL,
+    duration_ns INTEGER NOT NULL,
+    ret         INTEGER NOT NULL,
+    FOREIGN KEY (pid, start_ns) REFERENCES processes(pid, start_ns),
+    FOREIGN KEY (tid, start_ns) REFERENCES threads(tid, start_ns)
+);
+
+-- Points where the eBPF programs dropped events, so syscalls around them may be missing
+CREATE TABLE IF NOT EXISTS gaps (
+    id               INTEGER PRIMARY KEY,
+    timestamp_ns     INTEGER NOT NULL,
+    dropped_at_enter INTEGER NOT NULL,
+    dropped_at_exit  INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS threads_pid ON threads(pid, start_ns);
+CREATE INDEX IF NOT EXISTS syscalls_pid ON syscalls(pid, start_ns);
+CREATE INDEX IF NOT EXISTS syscalls_tid_enter ON syscalls(tid, enter_ns);
+CREATE INDEX IF NOT EXISTS syscalls_name ON syscalls(name);
+
+-- Latency statistics per syscall name
+CREATE VIEW IF NOT EXISTS syscall_latency_summary AS
+SELECT
+    name,
+    COUNT(*)                   AS calls,
+    SUM(ret < 0)               AS errors,
+    SUM(duration_ns)           AS total_ns,
+    AVG(duration_ns)           AS avg_ns,
+    MIN(duration_ns)           AS min_ns,
+    MAX(duration_ns)           AS max_ns
+FROM syscalls
+GROUP BY name;
+
+-- Time each thread spent inside syscalls, relative to its traced lifetime
+CREATE VIEW IF NOT EXISTS thread_kernel_time AS
+SELECT
+    pid,
+    tid,
+    start_ns,
+    COUNT(*)                   AS calls,
+    SUM(duration_ns)           AS kernel_ns,
+    MAX(exit_ns) - MIN(enter_ns) AS traced_ns,
+    100.0 * SUM(duration_ns) / MAX(MAX(exit_ns) - MIN(enter_ns), 1) AS kernel_pct
+FROM syscalls
+GROUP BY pid, tid, start_ns;
+";
+
+/// Event Subscriber that writes completed syscalls into a SQLite database
+pub struct SqliteSubscriber {
+    /// Open database connection
+    conn: Connection,
+    /// Matches sys_enter and sys_exit events
+    tracker: SyscallTracker,
+    /// Completed syscalls waiting to be inserted
+    pending: Vec<CompletedSyscall>,
+    /// Gaps waiting to be inserted along with the syscalls around them
+    pending_gaps: Vec<LostEvents>,
+    /// Whether writing failed for good, so events are ignored
+    failed: bool,
+}
+
+impl SqliteSubscriber {
+    /// Create the database at `path` and set up its schema. An existing database is only used if
+    /// it's empty, so that traces are never mixed up
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<SqliteSubscriber> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)?;
+        let objects: i64 =
+            conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+        if objects > 0 {
+            return Err(anyhow!(
+                "{} already holds a database, e.g. an earlier trace. Write to a new file instead",
+                path.display()
+            ));
+        }
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Ok(Self::with_connection(conn)?)
+    }
+
+    /// Use an already open connection and set up its schema
+    pub fn with_connection(conn: Connection) -> rusqlite::Result<SqliteSubscriber> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteSubscriber {
+            conn,
+            tracker: SyscallTracker::new(),
+            pending: Vec::with_capacity(BATCH_SIZE),
+            pending_gaps: vec![],
+            failed: false,
+        })
+    }
+
+    /// Insert all pending syscalls and gaps in a single transaction
+    fn flush(&mut self) -> rusqlite::Result<()> {
+        if self.pending.is_empty() && self.pending_gaps.is_empty() {
+            return Ok(());
+        }
+
+        // last syscall, whose names and IDs are written, and first and last timestamps of every
+        // process and thread in this batch
+        let mut processes: HashMap<(pid_t, u64), (&CompletedSyscall, u64, u64)> = HashMap::new();
+        let mut threads: HashMap<(pid_t, u64), (&CompletedSyscall, u64, u64)> = HashMap::new();
+        for syscall in &self.pending {
+            for (map, id) in [(&mut processes, syscall.pid), (&mut threads, syscall.tid)] {
+                let entry = map.entry((id, syscall.process_start)).or_insert((
+                    syscall,
+                    syscall.enter_timestamp,
+                    syscall.exit_timestamp,
+                ));
+                if syscall.exit_timestamp >= entry.2 {
+                    entry.0 = syscall;
+                    entry.2 = syscall.exit_timestamp;
+                }
+                entry.1 = entry.1.min(syscall.enter_timestamp);
+            }
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert_process = tx.prepare_cached(
+                "INSERT INTO processes
+                    (pid, start_ns, comm, ns_pid, pid_ns, first_seen_ns, last_seen_ns)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(pid, start_ns) DO UPDATE SET
+                    comm = excluded.comm,
+                    first_seen_ns = MIN(first_seen_ns, excluded.first_seen_ns),
+                    last_seen_ns = MAX(last_seen_ns, excluded.last_seen_ns)",
+            )?;
+            for ((pid, start), (syscall, first, last)) in processes {
+                insert_process.execute(params![
+                    pid,
+                    start as i64,
+                    syscall.process_comm.to_string_lossy(),
+                    syscall.ns_pid,
+                    syscall.pid_ns,
+                    first as i64,
+                    last as i64
+                ])?;
+            }
+
+            let mut insert_thread = tx.prepare_cached(
+                "INSERT INTO threads
+                    (tid, pid, start_ns, comm, ns_tid, first_seen_ns, last_seen_ns)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(tid, start_ns) DO UPDATE SET
+                    comm = excluded.comm,
+                    first_seen_ns = MIN(first_seen_ns, excluded.first_seen_ns),
+                    last_seen_ns = MAX(last_seen_ns, excluded.last_seen_ns)",
+            )?;
+            for ((tid, start), (syscall, first, last)) in threads {
+                insert_thread.execute(params![
+                    tid,
+                    syscall.pid,
+                    start as i64,
+                    syscall.comm.to_string_lossy(),
+                    syscall.ns_tid,
+                    first as i64,
+                    last as i64
+                ])?;
+            }
+
+            let mut insert_syscall = tx.prepare_cached(
+                "INSERT INTO syscalls
+                    (pid, tid, start_ns, syscall_id, name, enter_ns, exit_ns, duration_ns, ret)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for syscall in &self.pending {
+                insert_syscall.execute(params![
+                    syscall.pid,
+                    syscall.tid,
+                    syscall.process_start as i64,
+                    syscall.syscall_id,
+                    syscall.name(),
+                    syscall.enter_timestamp as i64,
+                    syscall.exit_timestamp as i64,
+                    syscall.duration_ns() as i64,
+                    syscall.ret,
+                ])?;
+            }
+
+            let mut insert_gap = tx.prepare_cached(
+                "INSERT INTO gaps (timestamp_ns, dropped_at_enter, dropped_at_exit)
+                 VALUES (?1, ?2, ?3)",
+            )?;
+            for lost in &self.pending_gaps {
+                insert_gap.execute(params![
+                    lost.timestamp as i64,
+                    lost.at_enter as i64,
+                    lost.at_exit as i64
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        debug!(
+            "Inserted {} syscalls and {} gaps into SQLite",
+            self.pending.len(),
+            self.pending_gaps.len()
+        );
+        self.pending.clear();
+        self.pending_gaps.clear();
+        Ok(())
+    }
+
+    /// Insert the pending syscalls, keeping them for the next attempt if it fails, e.g. while
+    /// the database is locked or the disk is full
+    fn try_flush(&mut self) {
+        if let Err(err) = self.flush() {
+            if self.pending.len() >= MAX_PENDING {
+                error!(
+                    "Failed to write syscalls to SQLite: {err}. Giving up and discarding {} \
+                     syscalls, the trace ends here",
+                    self.pending.len()
+                );
+                self.pending = vec![];
+                self.pending_gaps = vec![];
+                self.failed = true;
+            } else {
+                warn!("Failed to write syscalls to SQLite, retrying with the next batch: {err}");
+            }
+        }
+    }
+}
+
+impl EventSubscriber for SqliteSubscriber {
+    fn get_name(&self) -> &'static str {
+        "SQLite"
+    }
+
+    fn interests(&self) -> EventKinds {
+        EventKinds::of(&[EventKind::Syscall, EventKind::Lost])
+    }
+
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        if self.failed {
+            return;
+        }
+        if let TrackedSyscall::Completed(syscall) = self.tracker.track(syscall_data) {
+            self.pending.push(syscall);
+            if self.pending.len().is_multiple_of(BATCH_SIZE) {
+                self.try_flush();
+            }
+        }
+    }
+
+    fn read_lost(&mut self, lost: &LostEvents) {
+        if !self.failed {
+            self.pending_gaps.push(*lost);
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.failed {
+            return;
+        }
+        if let Err(err) = self.flush() {
+            error!(
+                "Failed to write the last {} syscalls to SQLite: {err}",
+                self.pending.len()
+            );
+            self.failed = true;
+        }
+    }
+
+    fn is_lossless(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for SqliteSubscriber {
+    fn drop(&mut self) {
+        // Nothing is pending if the subscriber was finished
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        publishers::syscalls::SyscallID,
+        subscribers::test_utils::{named, sys_enter, sys_exit},
+    };
+
+    #[test]
+    fn test_completed_syscalls_are_written() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut subscriber = SqliteSubscriber::with_connection(conn).unwrap();
+
+        let read = SyscallID::read as i64;
+        subscriber.read_syscall(&sys_enter(1_000, 10, 11, read));
+        subscriber.read_syscall(&sys_exit(3_000, 10, 11, 8));
+        subscriber.read_syscall(&sys_enter(4_000, 10, 12, read));
+        subscriber.read_syscall(&named(sys_exit(5_000, 10, 12, -11), "nginx", "worker-1"));
+        // never completed, so it must not be written
+        subscriber.read_syscall(&sys_enter(6_000, 10, 11, read));
+        subscriber.read_lost(&LostEvents {
+            timestamp: 7_000,
+            at_enter: 3,
+            at_exit: 0,
+        });
+        subscriber.flush().unwrap();
+        let conn = &subscriber.conn;
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("processes"), 1);
+        assert_eq!(count("threads"), 2);
+        assert_eq!(count("syscalls"), 2);
+        assert_eq!(count("gaps"), 1);
+
+        let (calls, errors, total_ns): (i64, i64, i64) = conn
+            .query_row(
+                "SELECT calls, errors, total_ns FROM syscall_latency_summary WHERE name = 'read'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((calls, errors, total_ns), (2, 1, 3_000));
+
+        let kernel_ns: i64 = conn
+            .query_row(
+                "SELECT kernel_ns FROM thread_kernel_time WHERE tid = 11",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(kernel_ns, 2_000);
+
+        let comms: (String, String) = conn
+            .query_row(
+                "SELECT processes.comm, threads.comm FROM threads JOIN processes USING (pid)
+                 WHERE tid = 12",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(comms, ("nginx".to_string(), "worker-1".to_string()));
+    }
+
+    #[test]
+    fn test_reused_pids_are_told_apart() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut subscriber = SqliteSubscriber::with_connection(conn).unwrap();
+
+        let read = SyscallID::read as i64;
+        for (timestamp, start) in [(1_000, 100), (9_000, 8_000)] {
+            let mut enter = sys_enter(timestamp, 10, 10, read);
+            let mut exit = sys_exit(timestamp + 500, 10, 10, 0);
+            enter.process_start = start;
+            exit.process_start = start;
+            subscriber.read_syscall(&enter);
+            subscriber.read_syscall(&exit);
+        }
+        subscriber.flush().unwrap();
+
+        let starts: Vec<i64> = subscriber
+            .conn
+            .prepare("SELECT start_ns FROM processes WHERE pid = 10 ORDER BY start_ns")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(starts, [100, 8_000]);
+    }
+
+    #[test]
+    fn test_failed_flush_is_retried() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut subscriber = SqliteSubscriber::with_connection(conn).unwrap();
+        subscriber.conn.execute_batch("DROP VIEW syscall_latency_summary; DROP VIEW thread_kernel_time; DROP TABLE syscalls").unwrap();
+
+        let read = SyscallID::read as i64;
+        subscriber.read_syscall(&sys_enter(1_000, 10, 11, read));
+        subscriber.read_lost(&LostEvents {
+            timestamp: 2_000,
+            at_enter: 1,
+            at_exit: 1,
+        });
+        subscriber.read_syscall(&sys_exit(3_000, 10, 11, 8));
+        subscriber.try_flush();
+        assert_eq!(subscriber.pending.len(), 1);
+        assert_eq!(subscriber.pending_gaps.len(), 1);
+
+        subscriber.conn.execute_batch(SCHEMA).unwrap();
+        subscriber.finish();
+        assert!(subscriber.pending.is_empty());
+        assert!(subscriber.pending_gaps.is_empty());
+        let count = |table: &str| -> i64 {
+            subscriber
+                .conn
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count("syscalls"), 1);
+        assert_eq!(count("gaps"), 1);
+    }
+
+    #[test]
+    fn test_existing_database_is_refused() {
+        let path =
+            std::env::temp_dir().join(format!("sikte-sqlite-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut subscriber = SqliteSubscriber::create(&path).unwrap();
+        subscriber.finish();
+        drop(subscriber);
+        let second = SqliteSubscriber::create(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let err = second.err().expect("an earlier trace was reused");
+        assert!(err.to_string().contains("already holds a database"));
+    }
+}
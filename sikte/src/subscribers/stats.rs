@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libc::pid_t;
+
+use super::{
+    EventSubscriber,
+    histogram::LatencyHistogram,
+    syscall_tracker::{CompletedSyscall, SyscallTracker, TrackedSyscall},
+};
+use crate::{
+    common::generated_types::{Comm, SyscallData},
+    events::{Event, EventKind, EventKinds, LostEvents},
+};
+
+/// Aggregated numbers of a group of completed syscalls
+#[derive(Debug, Clone, Default)]
+pub struct SyscallTotals {
+    /// Syscalls that returned an error
+    pub errors: u64,
+    /// Latency distribution, which also holds the number of calls and the total time
+    pub latency: LatencyHistogram,
+}
+
+impl SyscallTotals {
+    fn record(&mut self, syscall: &CompletedSyscall) {
+        self.latency.record(syscall.duration_ns());
+        if syscall.ret < 0 {
+            self.errors += 1;
+        }
+    }
+
+    /// Add the numbers of another group
+    pub fn merge(&mut self, other: &SyscallTotals) {
+        self.errors += other.errors;
+        self.latency.merge(&other.latency);
+    }
+
+    /// Number of completed syscalls
+    pub fn calls(&self) -> u64 {
+        self.latency.count()
+    }
+
+    /// Total time spent inside syscalls in ns
+    pub fn kernel_ns(&self) -> u64 {
+        self.latency.sum_ns()
+    }
+}
+
+/// Aggregated numbers of a single thread
+#[derive(Debug, Clone, Default)]
+pub struct ThreadTotals {
+    /// Process the thread belongs to
+    pub pid: pid_t,
+    /// Last command name of the thread
+    pub comm: Comm,
+    /// Last command name of its process
+    pub process_comm: Comm,
+    pub totals: SyscallTotals,
+}
+
+/// Syscalls completed during a window of time
+#[derive(Debug, Clone)]
+pub struct StatsWindow {
+    /// Length of the window
+    pub duration: Duration,
+    /// Totals indexed by `(pid, syscall name)`
+    pub by_process_syscall: BTreeMap<(pid_t, &'static str), SyscallTotals>,
+    /// Totals indexed by tid
+    pub by_thread: BTreeMap<pid_t, ThreadTotals>,
+    /// Events dropped by the eBPF programs, so the totals may be too low
+    pub lost: u64,
+}
+
+impl StatsWindow {
+    /// Totals per syscall name, optionally restricted to a single process
+    pub fn by_syscall(&self, pid: Option<pid_t>) -> BTreeMap<&'static str, SyscallTotals> {
+        let mut by_syscall: BTreeMap<&'static str, SyscallTotals> = BTreeMap::new();
+        for ((_, name), totals) in self
+            .by_process_syscall
+            .iter()
+            .filter(|((p, _), _)| pid.is_none_or(|pid| pid == *p))
+        {
+            by_syscall.entry(name).or_default().merge(totals);
+        }
+        by_syscall
+    }
+
+    /// Totals over every syscall, optionally restricted to a single process
+    pub fn total(&self, pid: Option<pid_t>) -> SyscallTotals {
+        let mut total = SyscallTotals::default();
+        for totals in self.by_syscall(pid).values() {
+            total.merge(totals);
+        }
+        total
+    }
+
+    /// Convert a count into a per-second rate over this window
+    pub fn rate(&self, count: u64) -> f64 {
+        count as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Percentage of this window spent inside syscalls. May exceed 100% when several threads are
+    /// aggregated
+    pub fn kernel_pct(&self, totals: &SyscallTotals) -> f64 {
+        100.0 * totals.kernel_ns() as f64 / (self.duration.as_nanos() as f64).max(1.0)
+    }
+}
+
+/// Syscall statistics accumulated since the start of the current window
+#[derive(Debug)]
+pub struct SyscallStats {
+    started: Instant,
+    by_process_syscall: BTreeMap<(pid_t, &'static str), SyscallTotals>,
+    by_thread: BTreeMap<pid_t, ThreadTotals>,
+    lost: u64,
+}
+
+impl Default for SyscallStats {
+    fn default() -> Self {
+        SyscallStats {
+            started: Instant::now(),
+            by_process_syscall: BTreeMap::new(),
+            by_thread: BTreeMap::new(),
+            lost: 0,
+        }
+    }
+}
+
+impl SyscallStats {
+    fn record(&mut self, syscall: &CompletedSyscall) {
+        self.by_process_syscall
+            .entry((syscall.pid, syscall.name()))
+            .or_default()
+            .record(syscall);
+
+        let thread = self.by_thread.entry(syscall.tid).or_default();
+        thread.pid = syscall.pid;
+        thread.comm = syscall.comm;
+        thread.process_comm = syscall.process_comm;
+        thread.totals.record(syscall);
+    }
+
+    /// Close the current window and start a new one
+    pub fn take_window(&mut self) -> StatsWindow {
+        let now = Instant::now();
+        let window = StatsWindow {
+            duration: now - self.started,
+            by_process_syscall: std::mem::take(&mut self.by_process_syscall),
+            by_thread: std::mem::take(&mut self.by_thread),
+            lost: std::mem::take(&mut self.lost),
+        };
+        self.started = now;
+        window
+    }
+}
+
+/// Statistics shared between a [`StatsSubscriber`] and whoever reports them
+pub type SharedStats = Arc<Mutex<SyscallStats>>;
+
+/// Event Subscriber that aggregates syscalls per process, thread and syscall name.
+/// Someone else is expected to periodically call [`SyscallStats::take_window`] and report them.
+pub struct StatsSubscriber {
+    /// Matches sys_enter and sys_exit events
+    tracker: SyscallTracker,
+    /// Aggregated statistics
+    stats: SharedStats,
+}
+
+impl Default for StatsSubscriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsSubscriber {
+    pub fn new() -> StatsSubscriber {
+        StatsSubscriber {
+            tracker: SyscallTracker::new(),
+            stats: SharedStats::default(),
+        }
+    }
+
+    /// Get a handle to the aggregated statistics
+    pub fn stats(&self) -> SharedStats {
+        self.stats.clone()
+    }
+}
+
+impl EventSubscriber for StatsSubscriber {
+    fn get_name(&self) -> &'static str {
+        "Stats"
+    }
+
+    fn interests(&self) -> EventKinds {
+        EventKinds::of(&[EventKind::Syscall, EventKind::Lost])
+    }
+
+    /// Takes the lock once for the whole batch, so that reporters wait less
+    fn read_events(&mut self, events: &[Event]) {
+        let mut stats = self.stats.lock().expect("stats lock poisoned");
+        for event in events {
+            match event {
+                Event::Syscall(syscall_data) => {
+                    if let TrackedSyscall::Completed(syscall) = self.tracker.track(syscall_data) {
+                        stats.record(&syscall);
+                    }
+                }
+                Event::Lost(lost) => stats.lost += lost.total(),
+            }
+        }
+    }
+
+    fn read_syscall(&mut self, syscall_data: &SyscallData) {
+        if let TrackedSyscall::Completed(syscall) = self.tracker.track(syscall_data) {
+            self.stats
+                .lock()
+                .expect("stats lock poisoned")
+                .record(&syscall);
+        }
+    }
+
+    fn read_lost(&mut self, lost: &LostEvents) {
+        self.stats.lock().expect("stats lock poisoned").lost += lost.total();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::generated_types::CommExt,
+        publishers::syscalls::SyscallID,
+        subscribers::test_utils::{named, sys_enter, sys_exit},
+    };
+
+    #[test]
+    fn test_window_aggregation() {
+        let mut subscriber = StatsSubscriber::new();
+        let read = SyscallID::read as i64;
+        let write = SyscallID::write as i64;
+
+        subscriber.read_syscall(&sys_enter(0, 1, 1, read));
+        subscriber.read_syscall(&sys_exit(1_000, 1, 1, -11));
+        subscriber.read_syscall(&sys_enter(0, 1, 2, read));
+        subscriber.read_syscall(&named(sys_exit(3_000, 1, 2, 8), "nginx", "worker-1"));
+        subscriber.read_syscall(&sys_enter(0, 7, 7, write));
+        subscriber.read_syscall(&sys_exit(500, 7, 7, 8));
+
+        let window = subscriber.stats().lock().unwrap().take_window();
+
+        let all = window.by_syscall(None);
+        assert_eq!(all["read"].calls(), 2);
+        assert_eq!(all["read"].errors, 1);
+        assert_eq!(all["read"].kernel_ns(), 4_000);
+        assert_eq!(all["write"].calls(), 1);
+
+        let only_7 = window.by_syscall(Some(7));
+        assert!(!only_7.contains_key("read"));
+        assert_eq!(window.total(Some(7)).calls(), 1);
+
+        assert_eq!(window.by_thread[&2].pid, 1);
+        assert_eq!(window.by_thread[&2].totals.kernel_ns(), 3_000);
+        assert_eq!(window.by_thread[&2].comm.to_string_lossy(), "worker-1");
+
+        // the next window starts empty
+        let next = subscriber.stats().lock().unwrap().take_window();
+        assert_eq!(next.total(None).calls(), 0);
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+use std::collections::HashMap;
+
+use libc::pid_t;
+use log::{trace, warn};
+
+use crate::{
+    common::{
+        generated_types::{Comm, CommExt, SyscallData, SyscallStateExt, syscall_state_tag},
+        procfs::PROC_PID_INIT_INO,
+    },
+    publishers::syscalls::SyscallID,
+};
+
+/// A syscall whose sys_enter and sys_exit events have both been seen
+#[derive(Debug, Clone, Copy)]
+pub struct CompletedSyscall {
+    /// Userspace process ID
+    pub pid: pid_t,
+    /// Userspace thread ID
+    pub tid: pid_t,
+    /// Process ID in the PID namespace of the process, e.g. of its container
+    pub ns_pid: pid_t,
+    /// Thread ID in the PID namespace of the process
+    pub ns_tid: pid_t,
+    /// Inode number of the PID namespace of the process
+    pub pid_ns: u32,
+    /// Start time of the process in ns since boot, which tells apart processes reusing a PID
+    pub process_start: u64,
+    /// Command name of the thread when the syscall returned
+    pub comm: Comm,
+    /// Command name of the process when the syscall returned
+    pub process_comm: Comm,
+    /// Raw syscall number
+    pub syscall_id: i64,
+    /// Kernel timestamp of sys_enter in ns
+    pub enter_timestamp: u64,
+    /// Kernel timestamp of sys_exit in ns
+    pub exit_timestamp: u64,
+    /// Syscall return value
+    pub ret: i64,
+}
+
+impl CompletedSyscall {
+    /// Syscall name, or "???" if the number is unknown
+    pub fn name(&self) -> &'static str {
+        SyscallID::try_from(self.syscall_id)
+            .map(|id| id.as_str())
+            .unwrap_or("???")
+    }
+
+    /// Names and IDs of the process and thread, like `nginx[1234]/worker-3[1240]`
+    pub fn thread_label(&self) -> String {
+        thread_label(&self.process_comm, self.pid, &self.comm, self.tid)
+    }
+
+    /// Whether the process is in a PID namespace other than the initial one, so its IDs may
+    /// differ
+    pub fn is_namespaced(&self) -> bool {
+        u64::from(self.pid_ns) != PROC_PID_INIT_INO
+    }
+
+    /// Time spent inside the syscall in ns
+    pub fn duration_ns(&self) -> u64 {
+        self.exit_timestamp.saturating_sub(self.enter_timestamp)
+    }
+}
+
+/// Names and IDs of a process and one of its threads, like `nginx[1234]/worker-3[1240]`
+pub fn thread_label(process_comm: &Comm, pid: pid_t, comm: &Comm, tid: pid_t) -> String {
+    format!(
+        "{}[{pid}]/{}[{tid}]",
+        process_comm.to_string_lossy(),
+        comm.to_string_lossy()
+    )
+}
+
+/// Result of feeding a [`SyscallData`] event into a [`SyscallTracker`]
+#[derive(Debug, Clone, Copy)]
+pub enum TrackedSyscall {
+    /// A sys_enter event was stored and is waiting for its sys_exit
+    Entered,
+    /// A sys_exit event matched a previous sys_enter
+    Completed(CompletedSyscall),
+    /// A sys_exit event arrived without a previous sys_enter (e.g. tracing started mid-syscall)
+    Unmatched {
+        pid: pid_t,
+        tid: pid_t,
+        comm: Comm,
+        process_comm: Comm,
+    },
+    /// The event could not be interpreted
+    Unknown,
+}
+
+/// Matches sys_enter and sys_exit events of the same thread
+#[derive(Default)]
+pub struct SyscallTracker {
+    /// Match a thread to its last registered sys_enter event
+    thr_to_last_sys_enter: HashMap<pid_t, SyscallData>,
+}
+
+impl SyscallTracker {
+    pub fn new() -> SyscallTracker {
+        SyscallTracker::default()
+    }
+
+    /// Feed a new event into the tracker
+    pub fn track(&mut self, syscall_data: &SyscallData) -> TrackedSyscall {
+        let SyscallData {
+            timestamp,
+            state,
+            // convert from kernel tgid/pid notation -> userspace pid/tid
+            tgid: pid,
+            pid: tid,
+            ns_tgid: ns_pid,
+            ns_pid: ns_tid,
+            pid_ns,
+            process_start,
+            comm,
+            process_comm,
+            ..
+        } = *syscall_data;
+
+        match state.tag {
+            syscall_state_tag::AT_ENTER => {
+                trace!("sys_enter: pid {pid}, tid {tid}");
+                self.thr_to_last_sys_enter.insert(tid, *syscall_data);
+                TrackedSyscall::Entered
+            }
+            syscall_state_tag::AT_EXIT => {
+                trace!("sys_exit: pid {pid}, tid {tid}");
+
+                match self.thr_to_last_sys_enter.remove(&tid) {
+                    Some(last_data) => match last_data.state.syscall_id() {
+                        Some(syscall_id) => TrackedSyscall::Completed(CompletedSyscall {
+                            pid,
+                            tid,
+                            ns_pid,
+                            ns_tid,
+                            pid_ns,
+                            process_start,
+                            comm,
+                            process_comm,
+                            syscall_id,
+                            enter_timestamp: last_data.timestamp,
+                            exit_timestamp: timestamp,
+                            ret: state.syscall_ret().unwrap_or_default(),
+                        }),
+                        None => {
+                            warn!("Unexpected non-AT_ENTER stored for tid {tid}");
+                            TrackedSyscall::Unknown
+                        }
+                    },
+                    None => TrackedSyscall::Unmatched {
+                        pid,
+                        tid,
+                        comm,
+                        process_comm,
+                    },
+                }
+            }
+            _ => {
+                trace!(
+                    "Unknown syscall state tag {} for pid {pid}, tid {tid}",
+                    state.tag
+                );
+                TrackedSyscall::Unknown
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscribers::test_utils::{sys_enter, sys_exit};
+
+    fn complete(enter: SyscallData, exit: SyscallData) -> CompletedSyscall {
+        let mut tracker = SyscallTracker::new();
+        tracker.track(&enter);
+        match tracker.track(&exit) {
+            TrackedSyscall::Completed(syscall) => syscall,
+            _ => panic!("syscall should be completed"),
+        }
+    }
+
+    #[test]
+    fn test_is_namespaced() {
+        let syscall = complete(sys_enter(1, 10, 11, 0), sys_exit(2, 10, 11, 0));
+        assert!(!syscall.is_namespaced());
+
+        // IDs may be the same in both namespaces, e.g. for a process started early on the host
+        let mut exit = sys_exit(2, 10, 11, 0);
+        exit.pid_ns = 4026532612;
+        let syscall = complete(sys_enter(1, 10, 11, 0), exit);
+        assert!(syscall.is_namespaced());
+    }
+}
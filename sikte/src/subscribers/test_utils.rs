@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Helpers for building events in unit tests
+use libc::pid_t;
+
+use crate::common::{
+    generated_types::{
+        AtEnter, AtExit, Comm, SyscallData, SyscallState, SyscallStateData, syscall_state_tag,
+    },
+    procfs::PROC_PID_INIT_INO,
+};
+
+pub fn sys_enter(timestamp: u64, pid: pid_t, tid: pid_t, syscall_id: i64) -> SyscallData {
+    SyscallData {
+        timestamp,
+        tgid: pid,
+        pid: tid,
+        ns_tgid: pid,
+        ns_pid: tid,
+        pid_ns: PROC_PID_INIT_INO as u32,
+        state: SyscallState {
+            tag: syscall_state_tag::AT_ENTER,
+            _padding: 0,
+            data: SyscallStateData {
+                at_enter: AtEnter { syscall_id },
+            },
+        },
+        ..Default::default()
+    }
+}
+
+pub fn sys_exit(timestamp: u64, pid: pid_t, tid: pid_t, syscall_ret: i64) -> SyscallData {
+    SyscallData {
+        timestamp,
+        tgid: pid,
+        pid: tid,
+        ns_tgid: pid,
+        ns_pid: tid,
+        pid_ns: PROC_PID_INIT_INO as u32,
+        state: SyscallState {
+            tag: syscall_state_tag::AT_EXIT,
+            _padding: 0,
+            data: SyscallStateData {
+                at_exit: AtExit { syscall_ret },
+            },
+        },
+        ..Default::default()
+    }
+}
+
+/// Set the command names of the process and thread of an event
+pub fn named(mut data: SyscallData, process_comm: &str, comm: &str) -> SyscallData {
+    data.process_comm = Comm::from(process_comm);
+    data.comm = Comm::from(comm);
+    data
+}
@@ -0,0 +1,513 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Live, top-like terminal view of the busiest syscalls and threads
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{self, Stdout, Write},
+    thread,
+    time::Duration,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use libc::pid_t;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::{
+    common::{generated_types::CommExt, procfs, units::format_ns},
+    subscribers::{SharedStats, StatsWindow, SyscallTotals},
+};
+
+/// Which table is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Syscalls,
+    Threads,
+}
+
+/// Column the rows are sorted by, in descending order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Calls,
+    Errors,
+    AvgLatency,
+    P99Latency,
+    KernelTime,
+}
+
+impl SortKey {
+    fn next(self) -> SortKey {
+        match self {
+            SortKey::Calls => SortKey::Errors,
+            SortKey::Errors => SortKey::AvgLatency,
+            SortKey::AvgLatency => SortKey::P99Latency,
+            SortKey::P99Latency => SortKey::KernelTime,
+            SortKey::KernelTime => SortKey::Calls,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Calls => "calls/s",
+            SortKey::Errors => "errors/s",
+            SortKey::AvgLatency => "avg",
+            SortKey::P99Latency => "p99",
+            SortKey::KernelTime => "%kernel",
+        }
+    }
+
+    fn value(self, totals: &SyscallTotals) -> u64 {
+        match self {
+            SortKey::Calls => totals.calls(),
+            SortKey::Errors => totals.errors,
+            SortKey::AvgLatency => totals.latency.mean_ns(),
+            SortKey::P99Latency => totals.latency.quantile_ns(0.99),
+            SortKey::KernelTime => totals.kernel_ns(),
+        }
+    }
+}
+
+/// A line of the table
+struct Row {
+    /// Leading columns identifying the row
+    label: String,
+    /// Process of the row, used for drill-down
+    pid: Option<pid_t>,
+    totals: SyscallTotals,
+}
+
+/// What the user is looking at
+struct TopState {
+    view: View,
+    sort: SortKey,
+    paused: bool,
+    /// Only show this process
+    focus: Option<pid_t>,
+    /// Index of the highlighted row
+    selected: usize,
+    /// Last closed window
+    window: Option<StatsWindow>,
+    /// Command names of processes, last seen in a window or else read from `/proc`
+    comms: HashMap<pid_t, String>,
+}
+
+impl TopState {
+    fn new() -> TopState {
+        TopState {
+            view: View::Syscalls,
+            sort: SortKey::Calls,
+            paused: false,
+            focus: None,
+            selected: 0,
+            window: None,
+            comms: HashMap::new(),
+        }
+    }
+
+    fn comm(&mut self, pid: pid_t) -> String {
+        self.comms
+            .entry(pid)
+            .or_insert_with(|| procfs::comm(pid).unwrap_or_else(|| "?".to_string()))
+            .clone()
+    }
+
+    /// Show a new window, and remember the names of its processes. Names of processes that are
+    /// gone from it are forgotten, unless focused
+    fn set_window(&mut self, window: StatsWindow) {
+        let focus = self.focus;
+        self.comms.retain(|&pid, _| focus == Some(pid));
+        for thread in window.by_thread.values() {
+            self.comms.insert(
+                thread.pid,
+                thread.process_comm.to_string_lossy().into_owned(),
+            );
+        }
+        self.window = Some(window);
+    }
+
+    fn rows(&mut self) -> Vec<Row> {
+        let Some(window) = self.window.take() else {
+            return vec![];
+        };
+
+        let focus = self.focus;
+        let mut rows: Vec<Row> = match self.view {
+            View::Syscalls => window
+                .by_syscall(focus)
+                .into_iter()
+                .map(|(name, totals)| Row {
+                    label: format!("{name:<24}"),
+                    pid: focus,
+                    totals,
+                })
+                .collect(),
+            View::Threads => window
+                .by_thread
+                .iter()
+                .filter(|(_, thread)| focus.is_none_or(|pid| pid == thread.pid))
+                .map(|(&tid, thread)| Row {
+                    label: format!(
+                        "{:>8} {:>8} {:<16}",
+                        thread.pid,
+                        tid,
+                        truncate(&thread.comm.to_string_lossy(), 16)
+                    ),
+                    pid: Some(thread.pid),
+                    totals: thread.totals.clone(),
+                })
+                .collect(),
+        };
+
+        let sort = self.sort;
+        rows.sort_by_key(|row| std::cmp::Reverse(sort.value(&row.totals)));
+        self.window = Some(window);
+        rows
+    }
+
+    /// Handle a key press. Returns false if the user wants to quit
+    fn handle_key(&mut self, key: KeyEvent, rows: &[Row]) -> bool {
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Esc if self.focus.is_none() => return false,
+            KeyCode::Esc | KeyCode::Backspace => {
+                self.focus = None;
+                self.selected = 0;
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') => self.paused = !self.paused,
+            KeyCode::Char('s') => self.sort = self.sort.next(),
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::Syscalls => View::Threads,
+                    View::Threads => View::Syscalls,
+                };
+                self.selected = 0;
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(rows.len().saturating_sub(1)),
+            KeyCode::Enter => {
+                if let Some(pid) = rows.get(self.selected).and_then(|row| row.pid) {
+                    self.focus = Some(pid);
+                    self.view = View::Syscalls;
+                    self.selected = 0;
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, out: &mut Stdout, rows: &[Row]) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        queue!(out, MoveTo(0, 0), Clear(ClearType::All))?;
+
+        let mut lines = vec![];
+        let scope = match self.focus {
+            Some(pid) => format!("process {} [{pid}]", self.comm(pid)),
+            None => "all processes".to_string(),
+        };
+        lines.push(format!(
+            "sikte top - {scope} - sorted by {}{}",
+            self.sort.as_str(),
+            if self.paused { " - PAUSED" } else { "" }
+        ));
+
+        if let Some(window) = &self.window {
+            let total = window.total(self.focus);
+            lines.push(format!(
+                "{:.0} syscalls/s, {:.0} errors/s, {:.1}% in kernel",
+                window.rate(total.calls()),
+                window.rate(total.errors),
+                window.kernel_pct(&total)
+            ));
+            if window.lost > 0 {
+                lines.push(format!(
+                    "{} events dropped, numbers are too low",
+                    window.lost
+                ));
+            }
+        } else {
+            lines.push("waiting for data...".to_string());
+        }
+        lines.push(
+            "q: quit  p: pause  s: sort  tab: syscalls/threads  enter: drill down  esc: back"
+                .to_string(),
+        );
+        lines.push(String::new());
+
+        let header_label = match self.view {
+            View::Syscalls => format!("{:<24}", "SYSCALL"),
+            View::Threads => format!("{:>8} {:>8} {:<16}", "PID", "TID", "COMM"),
+        };
+        lines.push(format!(
+            "{header_label} {:>10} {:>10} {:>10} {:>10} {:>8}",
+            "CALLS/S", "ERRORS/S", "AVG", "P99", "%KERNEL"
+        ));
+
+        let header_lines = lines.len();
+        if let Some(window) = &self.window {
+            for row in rows {
+                lines.push(format!(
+                    "{} {:>10.0} {:>10.0} {:>10} {:>10} {:>8.1}",
+                    row.label,
+                    window.rate(row.totals.calls()),
+                    window.rate(row.totals.errors),
+                    format_ns(row.totals.latency.mean_ns()),
+                    format_ns(row.totals.latency.quantile_ns(0.99)),
+                    window.kernel_pct(&row.totals)
+                ));
+            }
+        }
+
+        for (i, line) in lines.iter().take(height as usize).enumerate() {
+            if i == header_lines + self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            } else if i == header_lines - 1 || i == 0 {
+                queue!(out, SetAttribute(Attribute::Bold))?;
+            }
+            queue!(
+                out,
+                MoveTo(0, i as u16),
+                Print(truncate(line, width)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+
+        out.flush()
+    }
+}
+
+fn truncate(s: &str, width: usize) -> &str {
+    match s.char_indices().nth(width) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+/// Puts the terminal into full-screen raw mode, and restores it when dropped
+struct TerminalGuard {
+    out: Stdout,
+}
+
+impl TerminalGuard {
+    fn new() -> io::Result<TerminalGuard> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, Hide)?;
+        Ok(TerminalGuard { out })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Forward key presses from a blocking thread, until the receiver is dropped
+fn read_keys(tx: UnboundedSender<KeyEvent>) {
+    while !tx.is_closed() {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => {
+                if let Ok(Event::Key(key)) = event::read()
+                    && key.kind == KeyEventKind::Press
+                    && tx.send(key).is_err()
+                {
+                    break;
+                }
+            }
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// Run the UI until the user quits or `exit` completes. Statistics are refreshed every `refresh`.
+/// Returns the output of `exit` if it completed, once the terminal is restored
+pub async fn run<T>(
+    stats: SharedStats,
+    refresh: Duration,
+    exit: impl Future<Output = T>,
+) -> io::Result<Option<T>> {
+    let mut guard = TerminalGuard::new()?;
+    let (tx, mut keys) = mpsc::unbounded_channel();
+    thread::spawn(move || read_keys(tx));
+
+    let mut state = TopState::new();
+    let mut ticker = tokio::time::interval(refresh);
+    let mut rows = vec![];
+    let mut exited = None;
+    tokio::pin!(exit);
+
+    loop {
+        tokio::select! {
+            output = &mut exit => {
+                exited = Some(output);
+                break;
+            }
+            _ = ticker.tick() => {
+                let window = stats.lock().expect("stats lock poisoned").take_window();
+                if !state.paused {
+                    state.set_window(window);
+                }
+            }
+            key = keys.recv() => match key {
+                Some(key) => {
+                    if !state.handle_key(key, &rows) {
+                        break;
+                    }
+                }
+                None => break,
+            },
+        }
+
+        rows = state.rows();
+        state.selected = state.selected.min(rows.len().saturating_sub(1));
+        state.draw(&mut guard.out, &rows)?;
+    }
+
+    drop(guard);
+    Ok(exited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        publishers::syscalls::SyscallID,
+        subscribers::{
+            EventSubscriber, StatsSubscriber,
+            test_utils::{named, sys_enter, sys_exit},
+        },
+    };
+
+    /// Window of the given syscalls, each one `(pid, tid, syscall, duration, ret)`
+    fn window(syscalls: &[(pid_t, pid_t, SyscallID, u64, i64)]) -> StatsWindow {
+        let mut subscriber = StatsSubscriber::new();
+        for &(pid, tid, id, duration, ret) in syscalls {
+            subscriber.read_syscall(&sys_enter(0, pid, tid, id as i64));
+            let exit = sys_exit(duration, pid, tid, ret);
+            subscriber.read_syscall(&named(exit, &format!("proc-{pid}"), "thread"));
+        }
+        subscriber.stats().lock().unwrap().take_window()
+    }
+
+    /// Process 1 reads from two threads, process 7 makes slow writes and failing getpids
+    fn busy_window() -> StatsWindow {
+        window(&[
+            (1, 1, SyscallID::read, 100, 8),
+            (1, 1, SyscallID::read, 100, 8),
+            (1, 2, SyscallID::read, 100, 8),
+            (7, 7, SyscallID::write, 10_000, -5),
+            (7, 7, SyscallID::getpid, 50, -1),
+            (7, 7, SyscallID::getpid, 50, -1),
+        ])
+    }
+
+    fn press(state: &mut TopState, code: KeyCode) -> bool {
+        let rows = state.rows();
+        state.handle_key(KeyEvent::from(code), &rows)
+    }
+
+    fn labels(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|row| row.label.trim_end()).collect()
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let mut state = TopState::new();
+        state.set_window(busy_window());
+
+        assert_eq!(state.sort, SortKey::Calls);
+        assert_eq!(labels(&state.rows()), ["read", "getpid", "write"]);
+        press(&mut state, KeyCode::Char('s'));
+        assert_eq!(state.sort, SortKey::Errors);
+        assert_eq!(labels(&state.rows()), ["getpid", "write", "read"]);
+        press(&mut state, KeyCode::Char('s'));
+        assert_eq!(state.sort, SortKey::AvgLatency);
+        assert_eq!(labels(&state.rows()), ["write", "read", "getpid"]);
+        press(&mut state, KeyCode::Char('s'));
+        assert_eq!(state.sort, SortKey::P99Latency);
+        assert_eq!(labels(&state.rows())[0], "write");
+        press(&mut state, KeyCode::Char('s'));
+        assert_eq!(state.sort, SortKey::KernelTime);
+        assert_eq!(labels(&state.rows()), ["write", "read", "getpid"]);
+        press(&mut state, KeyCode::Char('s'));
+        assert_eq!(state.sort, SortKey::Calls);
+    }
+
+    #[test]
+    fn test_drill_down_and_back() {
+        let mut state = TopState::new();
+        state.set_window(busy_window());
+
+        press(&mut state, KeyCode::Tab);
+        assert_eq!(state.view, View::Threads);
+        let tids: Vec<String> = state
+            .rows()
+            .iter()
+            .map(|row| row.label.split_whitespace().nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(tids, ["7", "1", "2"]);
+
+        // The second row is a thread of process 1
+        press(&mut state, KeyCode::Down);
+        press(&mut state, KeyCode::Enter);
+        assert_eq!(state.focus, Some(1));
+        assert_eq!(state.view, View::Syscalls);
+        let rows = state.rows();
+        assert_eq!(labels(&rows), ["read"]);
+        assert_eq!(rows[0].totals.calls(), 3);
+
+        press(&mut state, KeyCode::Tab);
+        assert!(state.rows().iter().all(|row| row.pid == Some(1)));
+        assert_eq!(state.rows().len(), 2);
+
+        // Esc leaves the process first, and then quits
+        assert!(press(&mut state, KeyCode::Esc));
+        assert_eq!(state.focus, None);
+        assert_eq!(state.rows().len(), 3);
+        assert!(!press(&mut state, KeyCode::Esc));
+    }
+
+    #[test]
+    fn test_pause_and_quit() {
+        let mut state = TopState::new();
+        assert!(press(&mut state, KeyCode::Char('p')));
+        assert!(state.paused);
+        assert!(press(&mut state, KeyCode::Char(' ')));
+        assert!(!state.paused);
+        assert!(!press(&mut state, KeyCode::Char('q')));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(!state.handle_key(ctrl_c, &[]));
+    }
+
+    #[test]
+    fn test_comms_of_current_window() {
+        let mut state = TopState::new();
+        state.set_window(busy_window());
+        assert_eq!(state.comm(1), "proc-1");
+        assert_eq!(state.comm(7), "proc-7");
+
+        state.focus = Some(1);
+        state.set_window(window(&[(9, 9, SyscallID::read, 100, 8)]));
+        let mut pids: Vec<pid_t> = state.comms.keys().copied().collect();
+        pids.sort();
+        assert_eq!(pids, [1, 9]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("read", 10), "read");
+        assert_eq!(truncate("héllo wörld", 4), "héll");
+        assert_eq!(truncate("日本語", 2), "日本");
+        assert_eq!(truncate("", 2), "");
+    }
+}
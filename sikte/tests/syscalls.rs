@@ -1,11 +1,9 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 use std::{
-    fs::File,
-    io::Read,
-    sync::{
-        Arc, Mutex,
-        atomic::{AtomicBool, Ordering},
-    },
+    fs::{self, File},
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
@@ -14,14 +12,19 @@ use nix::{
     unistd::{ForkResult, fork},
 };
 use sikte::{
-    common::generated_types::{SyscallData, SyscallStateExt},
+    common::{
+        capabilities::{CAP_PERFMON, CapabilitySet, drop_capabilities},
+        generated_types::{SyscallData, SyscallStateExt},
+        process_selector::ProcessSelector,
+    },
     ebpf::{
-        SikteEbpf,
-        map_types::{PidAllowList, SyscallRingBuf},
+        EbpfConfig, KernelFeatures, SikteEbpf,
+        map_types::{DroppedEvents, PidAllowList},
     },
     events::EventBus,
     memlock_rlimit::bump_memlock_rlimit,
     publishers::syscalls::{self, SyscallID, SyscallPublisher},
+    session::Session,
     subscribers::EventSubscriber,
 };
 
@@ -50,6 +53,324 @@ impl EventSubscriber for TestSubscriber {
     }
 }
 
+fn has_read_syscall(subscriber: &TestSubscriber) -> bool {
+    subscriber.syscalls.lock().unwrap().iter().any(|sc| {
+        sc.state
+            .syscall_id()
+            .is_some_and(|id| id == SyscallID::read as i64)
+    })
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_commands_in_concurrent_sessions() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    let mut sessions = vec![];
+    let mut subscribers = vec![];
+    for _ in 0..2 {
+        let subscriber = TestSubscriber::new();
+        let session = Session::builder()
+            .command(["head", "-c", "1", "/dev/zero"])
+            .subscriber(subscriber.clone())
+            .shutdown_timeout(Duration::from_secs(1))
+            .start()
+            .await
+            .expect("Failed to start session");
+        sessions.push(session);
+        subscribers.push(subscriber);
+    }
+
+    for mut session in sessions {
+        let status = session.wait().await.expect("Failed to wait for command");
+        assert!(status.is_some_and(|status| status.success()));
+        let summary = session.stop().await.expect("Failed to stop session");
+        assert_eq!(summary.subscribers.len(), 1);
+    }
+
+    for subscriber in &subscribers {
+        assert!(
+            has_read_syscall(subscriber),
+            "No read syscall was found for the traced command"
+        );
+    }
+}
+
+#[test]
+fn trace_command_after_dropping_capabilities() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    // Capabilities belong to threads, so the other tests keep theirs
+    let subscriber = TestSubscriber::new();
+    let thread_subscriber = subscriber.clone();
+    std::thread::spawn(move || {
+        let builder = Session::builder()
+            .command(["head", "-c", "1", "/dev/zero"])
+            .subscriber(thread_subscriber)
+            .shutdown_timeout(Duration::from_secs(1))
+            .load()
+            .expect("Failed to load eBPF programs");
+        drop_capabilities(CapabilitySet::EMPTY).expect("Failed to drop capabilities");
+        let effective = CapabilitySet::effective().expect("Failed to read capabilities");
+        assert!(!effective.contains(CAP_PERFMON));
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build runtime")
+            .block_on(async {
+                let mut session = builder.start().await.expect("Failed to start session");
+                session.wait().await.expect("Failed to wait for command");
+                session.stop().await.expect("Failed to stop session");
+            });
+    })
+    .join()
+    .expect("Tracing thread panicked");
+
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the traced command"
+    );
+}
+
+#[test]
+fn trace_other_user_process_after_dropping_capabilities() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    // A copy of the shell, so that only the process started here runs it
+    let dir = std::env::temp_dir().join(format!("sikte-exe-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create directory");
+    let exe = dir.join("sh");
+    fs::copy("/bin/sh", &exe).expect("Failed to copy the shell");
+    // Run by nobody, whose executable can't be read without capabilities
+    let mut child = std::process::Command::new(&exe)
+        .args(["-c", "sleep 2; read line < /proc/version"])
+        .uid(65534)
+        .gid(65534)
+        .spawn()
+        .expect("Failed to run command");
+
+    let subscriber = TestSubscriber::new();
+    let thread_subscriber = subscriber.clone();
+    let selector = ProcessSelector::exe(&exe);
+    let tracing = std::thread::spawn(move || {
+        let builder = Session::builder()
+            .matching(selector, false)
+            .subscriber(thread_subscriber)
+            .shutdown_timeout(Duration::from_secs(1))
+            .load()
+            .expect("Failed to load eBPF programs");
+        drop_capabilities(CapabilitySet::EMPTY).expect("Failed to drop capabilities");
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build runtime")
+            .block_on(async {
+                let mut session = builder.start().await.expect("Failed to start session");
+                session.wait().await.expect("Failed to wait for process");
+                session.stop().await.expect("Failed to stop session");
+            });
+    });
+    let status = child.wait().expect("Failed to wait for command");
+    tracing.join().expect("Tracing thread panicked");
+    fs::remove_dir_all(&dir).expect("Failed to remove directory");
+
+    assert!(status.success());
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the other user's process"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_command_with_fallbacks() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    // Perf buffers and classic tracepoints, as on kernels older than 5.8
+    let config = EbpfConfig {
+        features: Some(KernelFeatures::NONE),
+        ..EbpfConfig::default()
+    };
+    let subscriber = TestSubscriber::new();
+    let mut session = Session::builder()
+        .command(["head", "-c", "1", "/dev/zero"])
+        .ebpf_config(config)
+        .subscriber(subscriber.clone())
+        .shutdown_timeout(Duration::from_secs(1))
+        .start()
+        .await
+        .expect("Failed to start session");
+
+    let status = session.wait().await.expect("Failed to wait for command");
+    assert!(status.is_some_and(|status| status.success()));
+    session.stop().await.expect("Failed to stop session");
+
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the traced command"
+    );
+    let syscalls = subscriber.syscalls.lock().unwrap();
+    assert!(
+        syscalls.is_sorted_by_key(|sc| sc.timestamp),
+        "Events from the CPUs weren't merged by timestamp"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_process_started_while_watching() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    let selector = ProcessSelector::pattern("^sh -c .*# sikte-watch-test$").unwrap();
+    let subscriber = TestSubscriber::new();
+    let session = Session::builder()
+        .matching(selector, true)
+        .subscriber(subscriber.clone())
+        .shutdown_timeout(Duration::from_secs(1))
+        .start()
+        .await
+        .expect("Failed to start session");
+
+    // The shell reads with a builtin, once the watcher had time to find it
+    let status = tokio::process::Command::new("sh")
+        .args([
+            "-c",
+            "sleep 2; read line < /proc/version # sikte-watch-test",
+        ])
+        .status()
+        .await
+        .expect("Failed to run command");
+    assert!(status.success());
+    session.stop().await.expect("Failed to stop session");
+
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the watched process"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_process_on_exec() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    let subscriber = TestSubscriber::new();
+    let session = Session::builder()
+        .on_exec(["head"])
+        .subscriber(subscriber.clone())
+        .shutdown_timeout(Duration::from_secs(1))
+        .start()
+        .await
+        .expect("Failed to start session");
+
+    // Too short-lived to be found in /proc, but traced from its first syscall
+    let status = tokio::process::Command::new("head")
+        .args(["-c", "1", "/dev/zero"])
+        .status()
+        .await
+        .expect("Failed to run command");
+    assert!(status.success());
+    session.stop().await.expect("Failed to stop session");
+
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the executed command"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_single_thread() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    let (tid_sender, tid_receiver) = std::sync::mpsc::channel();
+    let (start_sender, start_receiver) = std::sync::mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        tid_sender.send(unsafe { libc::gettid() }).unwrap();
+        start_receiver.recv().unwrap();
+        let mut buf = [0u8; 1];
+        File::open("/dev/zero")
+            .unwrap()
+            .read_exact(&mut buf)
+            .unwrap();
+    });
+    let tid = tid_receiver.recv().unwrap();
+
+    let subscriber = TestSubscriber::new();
+    let session = Session::builder()
+        .tids([tid])
+        .subscriber(subscriber.clone())
+        .shutdown_timeout(Duration::from_secs(1))
+        .start()
+        .await
+        .expect("Failed to start session");
+    start_sender.send(()).unwrap();
+    thread.join().expect("Reading thread panicked");
+    session.stop().await.expect("Failed to stop session");
+
+    assert!(
+        has_read_syscall(&subscriber),
+        "No read syscall was found for the traced thread"
+    );
+    let syscalls = subscriber.syscalls.lock().unwrap();
+    assert!(
+        syscalls.iter().all(|sc| sc.pid == tid),
+        "Other threads of the process were traced"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn trace_process_after_main_thread_exits() {
+    bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
+
+    let (mut reader, mut writer) = std::io::pipe().expect("Failed to create pipe");
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            drop(reader);
+            let subscriber = TestSubscriber::new();
+            let session = Session::builder()
+                .pids([child.as_raw()])
+                .subscriber(subscriber.clone())
+                .shutdown_timeout(Duration::from_secs(1))
+                .start()
+                .await
+                .expect("Failed to start session");
+            // Let the main thread of the child exit while it is traced
+            writer.write_all(&[0]).expect("Failed to write to pipe");
+
+            let status = tokio::task::spawn_blocking(move || waitpid(child, None))
+                .await
+                .expect("Waiting thread panicked");
+            assert!(matches!(status, Ok(WaitStatus::Exited(_, 0))));
+            session.stop().await.expect("Failed to stop session");
+
+            assert!(
+                has_read_syscall(&subscriber),
+                "No read syscall was found after the main thread exited"
+            );
+        }
+        Ok(ForkResult::Child) => {
+            drop(writer);
+            std::thread::spawn(|| {
+                // Outlives the main thread
+                std::thread::sleep(Duration::from_millis(500));
+                let mut buf = [0u8; 1];
+                File::open("/dev/zero")
+                    .unwrap()
+                    .read_exact(&mut buf)
+                    .unwrap();
+                std::process::exit(0);
+            });
+
+            let mut buf = [0u8; 1];
+            reader.read_exact(&mut buf).unwrap();
+            // Only the main thread exits, like with pthread_exit
+            unsafe { libc::syscall(libc::SYS_exit, 0) };
+            unreachable!("exit returned");
+        }
+        Err(_) => {
+            panic!("Fork failed");
+        }
+    }
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn trace_child_process_read_syscall() {
     match unsafe { fork() } {
@@ -58,10 +379,9 @@ async fn trace_child_process_read_syscall() {
             let child_pid = child.as_raw();
 
             // 1. Set up tracing
-            bump_memlock_rlimit();
+            bump_memlock_rlimit().expect("Failed to bump memlock rlimit, run as root");
             let mut ebpf = SikteEbpf::load().expect("Failed to load eBPF program");
 
-            let interrupted = Arc::new(AtomicBool::new(false));
             let mut event_bus = EventBus::new();
             let subscriber = TestSubscriber::new();
             event_bus.spawn_subscription(subscriber.clone());
@@ -79,14 +399,14 @@ async fn trace_child_process_read_syscall() {
                 .expect("Failed to attach sys_exit program");
 
             let requirements = syscalls::Requirements::new(sys_enter, sys_exit);
-            let ring_buf = SyscallRingBuf::new(ebpf.syscall_events_map());
-            let tx = event_bus.tx();
-            let publisher = SyscallPublisher::new(requirements, ring_buf, interrupted.clone(), tx)
+            let dropped = DroppedEvents::new(ebpf.dropped_events_map())
+                .expect("Failed to open dropped events map");
+            let publisher = SyscallPublisher::new(requirements, ebpf.syscall_events(), dropped)
                 .expect("Failed to create publisher");
 
             event_bus.spawn_publishment(publisher);
 
-            // 2. Wait for child to exit and events to be processed
+            // 2. Wait for child to exit
             match waitpid(child, None) {
                 Ok(WaitStatus::Exited(pid, status)) => {
                     assert_eq!(pid, child);
@@ -94,11 +414,9 @@ async fn trace_child_process_read_syscall() {
                 }
                 other => panic!("Expected child to exit gracefully, got {other:?}"),
             }
-            tokio::time::sleep(Duration::from_millis(250)).await; // Allow time for events to propagate
 
-            // 3. Stop tracing
-            interrupted.store(true, Ordering::Release);
-            tokio::time::sleep(Duration::from_millis(100)).await;
+            // 3. Stop tracing, after every event has been processed
+            event_bus.shutdown(Duration::from_secs(1)).await;
 
             // 4. Assert that at least one read syscall was made
             let syscalls = subscriber.syscalls.lock().unwrap();
@@ -115,14 +433,9 @@ async fn trace_child_process_read_syscall() {
                 }
             }
 
-            let read_syscall_found = syscalls.iter().any(|sc| {
-                sc.state
-                    .syscall_id()
-                    .is_some_and(|id| id == SyscallID::read as i64)
-            });
-
+            drop(syscalls);
             assert!(
-                read_syscall_found,
+                has_read_syscall(&subscriber),
                 "No read syscall was found for the traced process"
             );
         }